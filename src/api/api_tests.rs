@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use tokio::sync::{mpsc, RwLock};
+use tower::ServiceExt;
+
+use crate::config::{Config, DisplayConfig};
+use crate::display::fsm::DisplayFiniteStateMachine;
+use crate::input::InputEvent;
+use crate::transit::http::HttpClient;
+use crate::transit::state::{TransitStateEntry, TransitStateManager};
+use crate::transit::{Direction, TransitIdentifier, TransitState};
+use crate::{SharedDisplayFiniteStateMachine, SharedTransitStateManager};
+use super::{bind_addr, router};
+
+const DISPLAY_TOML: &str = r#"
+message_timeout = { secs = 5, nanos = 0 }
+error_timeout = { secs = 30, nanos = 0 }
+
+[[pages]]
+subpages = [{ transit_identifier = { provider_key = "muni", station_id = "1", direction = "North" }, page_display_handler_key = "predictions" }]
+
+[[pages]]
+subpages = [{ transit_identifier = { provider_key = "muni", station_id = "2", direction = "North" }, page_display_handler_key = "predictions" }]
+"#;
+
+fn identifier(station_id: &str) -> TransitIdentifier {
+    TransitIdentifier {
+        provider_key: "muni".to_string(),
+        station_id: station_id.to_string(),
+        direction: Direction::North,
+    }
+}
+
+fn shared_state() -> (SharedDisplayFiniteStateMachine, SharedTransitStateManager) {
+    let mut manager = TransitStateManager::new(Config { providers: HashMap::new() }, HttpClient::live()).unwrap();
+    manager.state.insert(identifier("1"), TransitStateEntry {
+        state: TransitState::Predictions(Vec::new()),
+        fetched_at: None,
+        last_error: Some("rate limited".to_string()),
+    });
+    let manager: SharedTransitStateManager = Arc::new(RwLock::new(manager));
+    let config: DisplayConfig = toml::from_str(DISPLAY_TOML).unwrap();
+    let fsm = DisplayFiniteStateMachine::new(config, manager.clone());
+    (Arc::new(RwLock::new(fsm)), manager)
+}
+
+async fn send(app: Router, method: &str, uri: &str, body: &str) -> (StatusCode, serde_json::Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null))
+}
+
+#[tokio::test]
+async fn status_reports_mode_and_position() {
+    let (fsm, manager) = shared_state();
+    let (status, body) = send(router(fsm, manager, None), "GET", "/status", "").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["mode"], "transit");
+    assert_eq!(body["page_idx"], 0);
+    assert_eq!(body["transit_identifiers"][0]["station_id"], "1");
+}
+
+#[tokio::test]
+async fn state_lists_every_entry() {
+    let (fsm, manager) = shared_state();
+    let (status, body) = send(router(fsm, manager, None), "GET", "/state", "").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["transit_identifier"]["station_id"], "1");
+    assert_eq!(body[0]["last_error"], "rate limited");
+}
+
+#[tokio::test]
+async fn events_drive_the_display() {
+    let (fsm, manager) = shared_state();
+    let app = router(fsm.clone(), manager, None);
+
+    let (status, _) = send(app.clone(), "POST", "/events", r#""NextPage""#).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(fsm.read().await.page_idx, 1);
+
+    let (status, _) = send(app, "POST", "/events", r#""Sideways""#).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn input_is_forwarded_only_when_enabled() {
+    let (fsm, manager) = shared_state();
+    let (status, _) = send(router(fsm.clone(), manager.clone(), None), "POST", "/input", r#""SinglePress""#).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (sender, mut receiver) = mpsc::channel(1);
+    let (status, _) = send(router(fsm, manager, Some(sender)), "POST", "/input", r#""SinglePress""#).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(matches!(receiver.recv().await, Some(InputEvent::SinglePress)));
+}
+
+#[test]
+fn bare_ports_bind_to_loopback() {
+    assert_eq!(bind_addr("8080"), "127.0.0.1:8080");
+    assert_eq!(bind_addr(":8080"), "127.0.0.1:8080");
+    assert_eq!(bind_addr("0.0.0.0:8080"), "0.0.0.0:8080");
+    assert_eq!(bind_addr("[::1]:8080"), "[::1]:8080");
+}
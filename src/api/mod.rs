@@ -0,0 +1,189 @@
+use std::time::Instant;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::display::{DisplayMode, StateEvent};
+use crate::input::InputEvent;
+use crate::transit::{TransitIdentifier, TransitState};
+use crate::transit::state::TransitStateEntry;
+use crate::{SharedDisplayFiniteStateMachine, SharedTransitStateManager};
+
+#[derive(Clone)]
+struct ApiState {
+    display_fsm: SharedDisplayFiniteStateMachine,
+    transit_manager: SharedTransitStateManager,
+    // Set when the HTTP input source is enabled
+    input: Option<mpsc::Sender<InputEvent>>,
+}
+
+// Events that may be posted to /events, e.g. "NextPage" or {"CustomMessage": "Hello"}
+#[derive(Debug, Deserialize)]
+enum ApiEvent {
+    NextPage,
+    NextSubpage,
+    PreviousSubpage,
+    Reset,
+    CustomMessage(String),
+}
+
+impl From<ApiEvent> for StateEvent {
+    fn from(event: ApiEvent) -> Self {
+        match event {
+            ApiEvent::NextPage => StateEvent::NextPage,
+            ApiEvent::NextSubpage => StateEvent::NextSubpage,
+            ApiEvent::PreviousSubpage => StateEvent::PreviousSubpage,
+            ApiEvent::Reset => StateEvent::Reset,
+            ApiEvent::CustomMessage(message) => StateEvent::CustomMessage(message),
+        }
+    }
+}
+
+// Serializable view of DisplayMode; Instants are reported as seconds elapsed
+#[derive(Debug, Serialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+enum ModeStatus {
+    Transit {
+        transit_identifiers: Vec<TransitIdentifier>,
+        transit_state: Option<TransitState>,
+        fetched_at: Option<DateTime<Utc>>,
+        last_error: Option<String>,
+    },
+    CustomMessage {
+        message: String,
+        elapsed_secs: f64,
+    },
+    Error {
+        message: String,
+        elapsed_secs: f64,
+    },
+}
+
+impl ModeStatus {
+    fn from_mode(mode: &DisplayMode) -> Self {
+        let elapsed = |start_time: &Instant| start_time.elapsed().as_secs_f64();
+        match mode {
+            DisplayMode::Transit { transit_identifiers, transit_state, fetched_at, last_error, .. } => ModeStatus::Transit {
+                transit_identifiers: transit_identifiers.clone(),
+                transit_state: transit_state.clone(),
+                fetched_at: *fetched_at,
+                last_error: last_error.clone(),
+            },
+            DisplayMode::CustomMessage { message, start_time, .. } => ModeStatus::CustomMessage {
+                message: message.clone(),
+                elapsed_secs: elapsed(start_time),
+            },
+            DisplayMode::Error { message, start_time } => ModeStatus::Error {
+                message: message.clone(),
+                elapsed_secs: elapsed(start_time),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct StatusResponse {
+    #[serde(flatten)]
+    mode: ModeStatus,
+    page_idx: usize,
+    subpage_idx: usize,
+}
+
+// JSON object keys must be strings, so state is reported as a list of entries
+#[derive(Debug, Serialize)]
+struct StateResponseEntry {
+    transit_identifier: TransitIdentifier,
+    #[serde(flatten)]
+    entry: TransitStateEntry,
+}
+
+async fn get_status(State(state): State<ApiState>) -> Json<StatusResponse> {
+    let display_fsm = state.display_fsm.read().await;
+    Json(StatusResponse {
+        mode: ModeStatus::from_mode(display_fsm.current_state()),
+        page_idx: display_fsm.page_idx,
+        subpage_idx: display_fsm.subpage_idx,
+    })
+}
+
+async fn get_transit_state(State(state): State<ApiState>) -> Json<Vec<StateResponseEntry>> {
+    let transit_manager = state.transit_manager.read().await;
+    Json(transit_manager.state
+        .iter()
+        .map(|(transit_identifier, entry)| StateResponseEntry {
+            transit_identifier: transit_identifier.clone(),
+            entry: entry.clone(),
+        })
+        .collect())
+}
+
+async fn post_event(State(state): State<ApiState>, Json(event): Json<ApiEvent>) -> StatusCode {
+    debug!("API event: {:?}", event);
+    state.display_fsm.write().await.handle_event(event.into()).await;
+    StatusCode::NO_CONTENT
+}
+
+// Button gestures posted to /input go through the merged input stream, so they
+// behave exactly like presses of a physical button
+async fn post_input(State(state): State<ApiState>, Json(event): Json<InputEvent>) -> StatusCode {
+    debug!("API input: {:?}", event);
+    match &state.input {
+        Some(input) if input.send(event).await.is_ok() => StatusCode::NO_CONTENT,
+        Some(_) => StatusCode::SERVICE_UNAVAILABLE,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+// The control and status routes:
+//   GET  /status  current display mode and page/subpage indices
+//   GET  /state   every transit state entry
+//   POST /events  a JSON event such as "NextPage" or {"CustomMessage": "Hello"}
+//   POST /input   a JSON input event such as "SinglePress", when HTTP input is enabled
+fn router(
+    display_fsm: SharedDisplayFiniteStateMachine,
+    transit_manager: SharedTransitStateManager,
+    input: Option<mpsc::Sender<InputEvent>>,
+) -> Router {
+    Router::new()
+        .route("/status", get(get_status))
+        .route("/state", get(get_transit_state))
+        .route("/events", post(post_event))
+        .route("/input", post(post_input))
+        .with_state(ApiState { display_fsm, transit_manager, input })
+}
+
+// The API has no authentication, so a bare port such as "8080" is served on
+// loopback only; give a host, e.g. "0.0.0.0:8080", to expose it to the network
+pub fn bind_addr(addr: &str) -> String {
+    let addr = addr.trim();
+    match addr.strip_prefix(':').unwrap_or(addr) {
+        port if port.parse::<u16>().is_ok() => format!("127.0.0.1:{}", port),
+        _ => addr.to_string(),
+    }
+}
+
+/// Serves the control and status API on `addr` (see `bind_addr`).
+pub async fn serve(
+    addr: String,
+    display_fsm: SharedDisplayFiniteStateMachine,
+    transit_manager: SharedTransitStateManager,
+    input: Option<mpsc::Sender<InputEvent>>,
+) -> anyhow::Result<()> {
+    let app = router(display_fsm, transit_manager, input);
+    let addr = bind_addr(&addr);
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    info!("HTTP API listening on {}", addr);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod api_tests;
@@ -0,0 +1,181 @@
+use std::path::PathBuf;
+
+use std::env;
+
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+
+use crate::config_files::ConfigPaths;
+use crate::transit::discovery::{StopSearch, StopTemplate};
+use crate::transit::http::HttpClient;
+use crate::transit::registry::stop_directions;
+use crate::transit::{bart, baywheels, muni, Direction};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Transit arrival sign for RGB LED matrix panels")]
+pub struct Cli {
+    /// Save every raw provider response, with the time it arrived, to this directory
+    #[arg(long, value_name = "DIR", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Serve provider responses from a recording made with --record instead of
+    /// the network, shifted so they appear current. No API keys are needed.
+    #[arg(long, value_name = "DIR")]
+    pub replay: Option<PathBuf>,
+
+    /// config.toml to use instead of searching ., $XDG_CONFIG_HOME/transit-sign
+    /// and /etc/transit-sign
+    #[arg(long, global = true, value_name = "PATH", env = "TRANSIT_SIGN_CONFIG_PATH")]
+    pub config: Option<PathBuf>,
+
+    /// display.toml to use instead of searching the same directories
+    #[arg(long, global = true, value_name = "PATH", env = "TRANSIT_SIGN_DISPLAY_PATH")]
+    pub display_config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Search a provider's stops by name or location and print entries to paste
+    /// into config.toml and display.toml
+    Stops(StopsArgs),
+
+    /// Check config.toml and display.toml, with any environment overrides, including that every stop shown in
+    /// display.toml is declared in config.toml, and report every problem found
+    Validate,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum StopSource {
+    /// BART stations
+    Bart,
+    /// 511.org stops for an agency (Muni by default)
+    Muni,
+    /// Bay Wheels dock stations from the GBFS feed
+    Baywheels,
+}
+
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("query").required(true).multiple(true).args(["name", "near"])))]
+pub struct StopsArgs {
+    /// Stop list to search
+    pub source: StopSource,
+
+    /// Words that must all appear in the stop name, e.g. "geary divisadero"
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Only stops within --radius of this point
+    #[arg(long, value_name = "LAT,LON", value_parser = parse_coordinates, allow_hyphen_values = true)]
+    pub near: Option<(f64, f64)>,
+
+    /// Search radius for --near, in metres
+    #[arg(long, value_name = "METERS", default_value_t = 400.0)]
+    pub radius: f64,
+
+    /// Most stops to print
+    #[arg(long, default_value_t = 10)]
+    pub limit: usize,
+
+    /// 511.org operator id for the muni source
+    #[arg(long, default_value = "SF")]
+    pub agency: String,
+
+    /// Direction for muni stops; Both gives one subpage each for Inbound and Outbound
+    #[arg(long, default_value = "Both")]
+    pub direction: Direction,
+
+    /// provider_key to use in the printed entries [default: the source name]
+    #[arg(long)]
+    pub provider_key: Option<String>,
+
+    /// API key [default: $BART_API_KEY or $MUNI_API_KEY]
+    #[arg(long)]
+    pub api_key: Option<String>,
+
+    /// Query a different API host, as with base_url in config.toml
+    #[arg(long)]
+    pub base_url: Option<String>,
+}
+
+fn parse_coordinates(s: &str) -> Result<(f64, f64), String> {
+    let (latitude, longitude) = s.split_once(',').ok_or("expected LAT,LON")?;
+    let parse = |value: &str| value.trim().parse::<f64>().map_err(|e| format!("{}: {}", value.trim(), e));
+    Ok((parse(latitude)?, parse(longitude)?))
+}
+
+impl StopsArgs {
+    pub async fn run(&self, http: &HttpClient) -> anyhow::Result<()> {
+        let (candidates, template) = match self.source {
+            StopSource::Bart => (
+                bart::list_stations(http, &self.base_url(bart::DEFAULT_BASE_URL), &self.api_key("BART_API_KEY")?).await?,
+                self.template("bart", Direction::Both, "predictions"),
+            ),
+            StopSource::Muni => (
+                muni::list_stops(http, &self.base_url(muni::DEFAULT_BASE_URL), &self.api_key("MUNI_API_KEY")?, &self.agency).await?,
+                self.template("muni", self.direction, "predictions"),
+            ),
+            StopSource::Baywheels => (
+                baywheels::list_stations(http, &self.base_url(baywheels::DEFAULT_BASE_URL)).await?,
+                self.template("baywheels", Direction::Both, "bike_inventory"),
+            ),
+        };
+
+        let search = StopSearch {
+            name: self.name.clone(),
+            near: self.near,
+            radius_m: self.radius,
+            limit: self.limit,
+        };
+        let matches = search.run(candidates);
+        if matches.is_empty() {
+            println!("No matching stops");
+        }
+        for (candidate, distance) in matches {
+            println!("{}", template.format(&candidate, distance));
+        }
+        Ok(())
+    }
+
+    fn base_url(&self, default: &str) -> String {
+        self.base_url.as_deref().unwrap_or(default).trim_end_matches('/').to_string()
+    }
+
+    fn api_key(&self, env_var: &str) -> anyhow::Result<String> {
+        match &self.api_key {
+            Some(key) => Ok(key.clone()),
+            None => env::var(env_var).map_err(|_| anyhow::anyhow!("{} must be set, or pass --api-key", env_var)),
+        }
+    }
+
+    // Sources are named after their provider type
+    fn template(&self, provider_type: &str, stop_direction: Direction, handler: &'static str) -> StopTemplate {
+        StopTemplate {
+            provider_key: self.provider_key.clone().unwrap_or_else(|| provider_type.to_string()),
+            stop_direction,
+            subpage_directions: stop_directions(provider_type, stop_direction),
+            page_display_handler_key: handler,
+        }
+    }
+}
+
+impl Cli {
+    pub fn http_client(&self) -> anyhow::Result<HttpClient> {
+        match (&self.record, &self.replay) {
+            (Some(dir), _) => {
+                println!("Recording provider responses to {}", dir.display());
+                HttpClient::record(dir)
+            }
+            (_, Some(dir)) => {
+                println!("Replaying provider responses from {}", dir.display());
+                HttpClient::replay(dir)
+            }
+            _ => Ok(HttpClient::live()),
+        }
+    }
+
+    pub fn config_paths(&self) -> Result<ConfigPaths, String> {
+        ConfigPaths::resolve(self.config.clone(), self.display_config.clone())
+    }
+}
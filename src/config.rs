@@ -1,21 +1,29 @@
 use serde::Deserialize;
-use std::fs;
 use std::collections::HashMap;
 use std::time::Duration;
 
-use crate::transit::TransitIdentifier;
+use crate::transit::{Direction, PredictionFilter, TransitIdentifier};
 use crate::display::PageDisplayHandler;
+use crate::display::schedule::{BrightnessWindow, Location, ScheduleWindow};
+use crate::input::gesture::GestureConfig;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct Stop {
     pub id: String,
     pub name: String,
-    pub direction: String,
+    pub direction: Direction,
 }
 
-#[derive(Debug, Deserialize)]
+// A provider instance, declared in config.toml as [providers.<provider_key>].
+// `type` selects the implementation; any other keys are provider-specific settings.
+#[derive(Debug, Deserialize, PartialEq)]
 pub struct ProviderConfig {
+    #[serde(rename = "type")]
+    pub provider_type: String,
+    #[serde(default)]
     pub stops: Vec<Stop>,
+    #[serde(flatten)]
+    pub settings: toml::Table,
     #[serde(skip)]
     pub stops_by_id: HashMap<String, Stop>,
 }
@@ -28,44 +36,242 @@ impl ProviderConfig {
             .map(|stop| (stop.id.clone(), stop))
             .collect();
     }
+
+    pub fn setting_str(&self, name: &str) -> Option<&str> {
+        self.settings.get(name).and_then(|value| value.as_str())
+    }
+
+    pub fn setting_int(&self, name: &str) -> Option<i64> {
+        self.settings.get(name).and_then(|value| value.as_integer())
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    pub bart: ProviderConfig,
-    pub muni: ProviderConfig,
-    pub baywheels: ProviderConfig,
+    pub providers: HashMap<String, ProviderConfig>,
 }
 
 impl Config {
-    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
-        let config_str = fs::read_to_string("config.toml")?;
-        let mut config: Config = toml::from_str(&config_str)?;
+    // Parses config.toml; see validate::load for the checks against display.toml
+    pub fn from_toml(config_str: &str) -> Result<Self, toml::de::Error> {
+        let mut config: Config = toml::from_str(config_str)?;
 
-        config.bart.init();
-        config.muni.init();
-        config.baywheels.init();
+        for provider in config.providers.values_mut() {
+            provider.init();
+        }
 
         Ok(config)
     }
 }
 
+// LED panel geometry and driver options; defaults match three chained 32x16 panels
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct PanelConfig {
+    pub rows: u32,
+    pub cols: u32,
+    pub chain_length: u32,
+    pub parallel: u32,
+    pub hardware_mapping: String,
+    pub brightness: u8,
+    pub gpio_slowdown: u32,
+    // Baseline of the first line of text
+    pub y_offset: i32,
+}
+
+impl PanelConfig {
+    // Size of the whole drawable area across all chained and parallel panels
+    pub fn width(&self) -> u32 {
+        self.cols * self.chain_length
+    }
+
+    pub fn height(&self) -> u32 {
+        self.rows * self.parallel
+    }
+}
+
+impl Default for PanelConfig {
+    fn default() -> Self {
+        Self {
+            rows: 16,
+            cols: 32,
+            chain_length: 3,
+            parallel: 1,
+            hardware_mapping: "adafruit-hat".to_string(),
+            brightness: 35,
+            gpio_slowdown: 5,
+            y_offset: 7,
+        }
+    }
+}
+
+// Physical button wired between a GPIO line and ground (or VCC when active_low is false)
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct GpioButtonConfig {
+    #[serde(default = "default_gpio_chip")]
+    pub chip: String,
+    pub line: u32,
+    #[serde(default = "default_true")]
+    pub active_low: bool,
+    #[serde(default)]
+    pub debounce_ms: Option<u64>,
+    #[serde(default)]
+    pub double_press_ms: Option<u64>,
+    #[serde(default)]
+    pub long_press_ms: Option<u64>,
+}
+
+fn default_gpio_chip() -> String {
+    "/dev/gpiochip0".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl GpioButtonConfig {
+    pub fn gesture_config(&self) -> GestureConfig {
+        let defaults = GestureConfig::default();
+        GestureConfig {
+            debounce: self.debounce_ms.map(Duration::from_millis).unwrap_or(defaults.debounce),
+            double_press_window: self.double_press_ms.map(Duration::from_millis).unwrap_or(defaults.double_press_window),
+            long_press: self.long_press_ms.map(Duration::from_millis).unwrap_or(defaults.long_press),
+        }
+    }
+}
+
+// Quadrature rotary encoder on two GPIO lines. Swap a_line and b_line if it turns the wrong way.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct RotaryEncoderConfig {
+    #[serde(default = "default_gpio_chip")]
+    pub chip: String,
+    pub a_line: u32,
+    pub b_line: u32,
+    // Most encoders step through all four AB states per detent; half-step ones use 2
+    #[serde(default = "RotaryEncoderConfig::default_steps_per_detent")]
+    pub steps_per_detent: u8,
+}
+
+impl RotaryEncoderConfig {
+    fn default_steps_per_detent() -> u8 {
+        4
+    }
+}
+
+// Input sources; every enabled source runs at once and feeds the same event stream
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct InputConfig {
+    #[serde(default = "default_true")]
+    pub keyboard: bool,
+    // Accept events posted to /input on the HTTP API
+    #[serde(default)]
+    pub http: bool,
+    #[serde(default)]
+    pub gpio: Option<GpioButtonConfig>,
+    #[serde(default)]
+    pub rotary: Option<RotaryEncoderConfig>,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            keyboard: true,
+            http: false,
+            gpio: None,
+            rotary: None,
+        }
+    }
+}
+
+// Rotates through every subpage of every page in order while nobody is pressing buttons
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct AutoAdvanceConfig {
+    // How long each subpage is shown, unless its page sets its own dwell
+    pub dwell: Duration,
+    // How long rotation holds off after someone changes page by hand
+    pub pause_after_input: Duration,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct DisplayConfig {
+    #[serde(default)]
+    pub panel: PanelConfig,
+    #[serde(default)]
+    pub input: InputConfig,
     pub message_timeout: Duration,
     pub error_timeout: Duration,
+    // Show an error once a subpage's data is older than this (or two of its
+    // provider's refresh intervals, if longer); never if unset
+    #[serde(default)]
+    pub stale_after: Option<Duration>,
+    // Off unless configured
+    #[serde(default)]
+    pub auto_advance: Option<AutoAdvanceConfig>,
+    // Skip subpages with nothing departing within this when changing page; never if unset
+    #[serde(default)]
+    pub service_horizon: Option<Duration>,
+    // Needed only by schedules that use sunrise or sunset
+    #[serde(default)]
+    pub location: Option<Location>,
+    // The first active entry sets the panel brightness; panel.brightness applies otherwise
+    #[serde(default)]
+    pub brightness_schedule: Vec<BrightnessWindow>,
     pub pages: Vec<PageDefinition>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PageDefinition {
     pub subpages: Vec<SubpageDefinition>,
+    // Overrides auto_advance.dwell for this page's subpages
+    #[serde(default)]
+    pub dwell: Option<Duration>,
+    // When the page is shown; always if empty
+    #[serde(default)]
+    pub schedule: Vec<ScheduleWindow>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(try_from = "RawSubpageDefinition")]
 pub struct SubpageDefinition {
-    pub transit_identifier: TransitIdentifier,
+    // One stop, or several whose predictions are merged into a single list
+    pub transit_identifiers: Vec<TransitIdentifier>,
     pub page_display_handler_key: String,
+    pub filter: PredictionFilter,
+}
+
+// A subpage as written in display.toml, with either `transit_identifier` or `transit_identifiers`
+#[derive(Deserialize)]
+struct RawSubpageDefinition {
+    #[serde(default)]
+    transit_identifier: Option<TransitIdentifier>,
+    #[serde(default)]
+    transit_identifiers: Vec<TransitIdentifier>,
+    page_display_handler_key: String,
+    #[serde(default)]
+    filter: PredictionFilter,
+}
+
+impl TryFrom<RawSubpageDefinition> for SubpageDefinition {
+    type Error = String;
+
+    fn try_from(raw: RawSubpageDefinition) -> Result<Self, Self::Error> {
+        let transit_identifiers = match (raw.transit_identifier, raw.transit_identifiers) {
+            (Some(identifier), identifiers) if identifiers.is_empty() => vec![identifier],
+            (None, identifiers) if !identifiers.is_empty() => identifiers,
+            _ => return Err("a subpage needs either transit_identifier or a non-empty transit_identifiers".to_string()),
+        };
+        if transit_identifiers.len() > 1 && raw.page_display_handler_key != "predictions" {
+            return Err(format!(
+                "only \"predictions\" subpages can combine several stops, not \"{}\"", raw.page_display_handler_key
+            ));
+        }
+
+        Ok(Self {
+            transit_identifiers,
+            page_display_handler_key: raw.page_display_handler_key,
+            filter: raw.filter,
+        })
+    }
 }
 
 impl SubpageDefinition {
@@ -75,24 +281,7 @@ impl SubpageDefinition {
 }
 
 impl DisplayConfig {
-    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
-        let config_str = fs::read_to_string("display.toml")?;
-        
-        match toml::from_str::<DisplayConfig>(&config_str) {
-            Ok(config) => Ok(config),
-            Err(e) => {
-                println!("Error parsing display.toml:");
-                println!("Error details: {:#?}", e);
-                println!("Error message: {}", e);
-                
-                // Print the TOML content with line numbers for reference
-                println!("\nTOML content with line numbers:");
-                for (i, line) in config_str.lines().enumerate() {
-                    println!("{:3}: {}", i + 1, line);
-                }
-                
-                Err(Box::new(e))
-            }
-        }
+    pub fn from_toml(config_str: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(config_str)
     }
 }
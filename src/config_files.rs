@@ -0,0 +1,194 @@
+// Finds config.toml and display.toml and layers environment overrides on top of
+// them. Each file is taken from, in order: its command line flag or environment
+// variable, the working directory, $XDG_CONFIG_HOME/transit-sign (~/.config by
+// default) and /etc/transit-sign.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml_edit::{Document, Item, Table, Value};
+
+pub const CONFIG_FILE: &str = "config.toml";
+pub const DISPLAY_FILE: &str = "display.toml";
+
+// Overrides look like TRANSIT_SIGN_CONFIG__PROVIDERS__MUNI__AGENCY=SF, with `__`
+// between table names and keys
+pub const CONFIG_OVERRIDE_PREFIX: &str = "TRANSIT_SIGN_CONFIG__";
+pub const DISPLAY_OVERRIDE_PREFIX: &str = "TRANSIT_SIGN_DISPLAY__";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigPaths {
+    pub config: PathBuf,
+    pub display: PathBuf,
+}
+
+impl ConfigPaths {
+    // Explicit paths must exist; otherwise the first file found in the search
+    // directories is used
+    pub fn resolve(config: Option<PathBuf>, display: Option<PathBuf>) -> Result<Self, String> {
+        let search_dirs = search_dirs(env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME"));
+        Ok(Self {
+            config: find(CONFIG_FILE, config, &search_dirs)?,
+            display: find(DISPLAY_FILE, display, &search_dirs)?,
+        })
+    }
+}
+
+pub fn search_dirs(xdg_config_home: Option<std::ffi::OsString>, home: Option<std::ffi::OsString>) -> Vec<PathBuf> {
+    let user_dir = xdg_config_home
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.map(|home| PathBuf::from(home).join(".config")));
+
+    let mut dirs = vec![PathBuf::from(".")];
+    dirs.extend(user_dir.map(|dir| dir.join("transit-sign")));
+    dirs.push(PathBuf::from("/etc/transit-sign"));
+    dirs
+}
+
+fn find(file_name: &str, explicit: Option<PathBuf>, search_dirs: &[PathBuf]) -> Result<PathBuf, String> {
+    if let Some(path) = explicit {
+        return match path.is_file() {
+            true => Ok(path),
+            false => Err(format!("{}: no such file", path.display())),
+        };
+    }
+
+    let candidates: Vec<PathBuf> = search_dirs.iter().map(|dir| dir.join(file_name)).collect();
+    candidates.iter().find(|path| path.is_file()).cloned().ok_or_else(|| format!(
+        "{} not found; looked for {}",
+        file_name,
+        candidates.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+    ))
+}
+
+// A configuration file with environment overrides applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overridden {
+    pub text: String,
+    // Names of the variables applied
+    pub applied: Vec<String>,
+    // Where each line of `text` came from; empty if nothing was overridden
+    pub lines: Vec<LineOrigin>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineOrigin {
+    // This line number in the file as written
+    File(usize),
+    // Written for this environment variable
+    Env(String),
+}
+
+// Applies every environment variable starting with `prefix` to a TOML document,
+// keeping the rest of the file as written. Values are strings, unless the key is
+// already in the file with another type, in which case they're read as TOML
+// (numbers, booleans, arrays) where they parse.
+pub fn apply_overrides(text: &str, prefix: &str, vars: impl IntoIterator<Item = (String, String)>) -> Result<Overridden, String> {
+    let mut overrides: Vec<(String, String)> = vars.into_iter().filter(|(name, _)| name.starts_with(prefix)).collect();
+    if overrides.is_empty() {
+        return Ok(Overridden { text: text.to_string(), applied: Vec::new(), lines: Vec::new() });
+    }
+    overrides.sort();
+
+    let mut document: Document = text.parse().map_err(|e: toml_edit::TomlError| e.to_string())?;
+    // The same document with a marker in place of each value, to find the lines they end up on
+    let mut marked = document.clone();
+    let mut applied = Vec::new();
+    for (index, (name, raw_value)) in overrides.into_iter().enumerate() {
+        let path: Vec<String> = name[prefix.len()..].split("__").map(str::to_lowercase).collect();
+        let Some((key, tables)) = path.split_last().filter(|(key, tables)| !key.is_empty() && tables.iter().all(|table| !table.is_empty())) else {
+            return Err(format!("{}: expected {}TABLE__KEY or {}KEY", name, prefix, prefix));
+        };
+
+        let table = table_at(&mut document, &name, tables)?;
+        let value = match table.get(key).and_then(Item::as_value) {
+            Some(existing) if !existing.is_str() => raw_value.trim().parse::<Value>().unwrap_or_else(|_| Value::from(raw_value.as_str())),
+            _ => Value::from(raw_value.as_str()),
+        };
+        table.insert(key, Item::Value(value));
+        table_at(&mut marked, &name, tables)?.insert(key, Item::Value(Value::from(marker(index))));
+        applied.push(name);
+    }
+
+    let lines = line_origins(text, &marked.to_string(), &applied);
+    Ok(Overridden { text: document.to_string(), applied, lines })
+}
+
+// The table at `tables`, created if it doesn't exist yet
+fn table_at<'a>(document: &'a mut Document, name: &str, tables: &[String]) -> Result<&'a mut Table, String> {
+    let mut table: &mut Table = document.as_table_mut();
+    for table_name in tables {
+        let item = table.entry(table_name).or_insert_with(|| Item::Table(Table::new()));
+        table = match item {
+            Item::Table(table) => table,
+            _ => return Err(format!("{}: {} is not a table", name, table_name)),
+        };
+    }
+    Ok(table)
+}
+
+fn marker(index: usize) -> String {
+    format!("@override-{}@", index)
+}
+
+// Lines holding a marker belong to that override's variable. The rest are matched
+// in order against the file as written (a longest common subsequence), and any
+// left over, like the header of a table an override created, belong to the next
+// override below them.
+fn line_origins(original: &str, marked: &str, applied: &[String]) -> Vec<LineOrigin> {
+    let original: Vec<&str> = original.lines().collect();
+    let marked: Vec<&str> = marked.lines().collect();
+
+    // common[i][j] is the length of the longest common subsequence of marked[i..] and original[j..]
+    let mut common = vec![vec![0; original.len() + 1]; marked.len() + 1];
+    for i in (0..marked.len()).rev() {
+        for j in (0..original.len()).rev() {
+            common[i][j] = match marked[i] == original[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+
+    let mut origins: Vec<Option<LineOrigin>> = Vec::with_capacity(marked.len());
+    let (mut i, mut j) = (0, 0);
+    while i < marked.len() {
+        if j < original.len() && marked[i] == original[j] {
+            origins.push(Some(LineOrigin::File(j + 1)));
+            i += 1;
+            j += 1;
+        } else if j < original.len() && common[i][j + 1] >= common[i + 1][j] {
+            j += 1;
+        } else {
+            let writer = applied.iter().enumerate().find(|(index, _)| marked[i].contains(&marker(*index)));
+            origins.push(writer.map(|(_, name)| LineOrigin::Env(name.clone())));
+            i += 1;
+        }
+    }
+
+    let mut below = applied.last().map(|name| LineOrigin::Env(name.clone()));
+    let mut lines: Vec<LineOrigin> = origins.into_iter().rev().map(|origin| {
+        if let Some(LineOrigin::Env(_)) = &origin {
+            below = origin.clone();
+        }
+        origin.or_else(|| below.clone()).unwrap_or(LineOrigin::File(0))
+    }).collect();
+    lines.reverse();
+    lines
+}
+
+// Reads a file and applies the process's environment overrides to it
+pub fn read_with_overrides(path: &Path, prefix: &str) -> Result<Overridden, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    apply_overrides(&text, prefix, env::vars()).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// A secret kept in its own file, e.g. one provided by systemd's LoadCredential.
+// Relative paths are relative to the directory config.toml is in.
+pub fn read_secret(path: &Path, config_dir: &Path) -> Result<String, String> {
+    let path = config_dir.join(path);
+    let secret = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(secret.trim().to_string())
+}
@@ -0,0 +1,114 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use super::config_files::{apply_overrides, read_secret, search_dirs, LineOrigin, CONFIG_OVERRIDE_PREFIX, DISPLAY_OVERRIDE_PREFIX};
+
+const DISPLAY_TOML: &str = r#"# Transit Display Configuration
+message_timeout = { secs = 5, nanos = 0 }
+
+[panel]
+rows = 16
+brightness = 35 # daytime
+"#;
+
+fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+}
+
+fn overridden(pairs: &[(&str, &str)]) -> toml::Table {
+    let overridden = apply_overrides(DISPLAY_TOML, DISPLAY_OVERRIDE_PREFIX, vars(pairs)).unwrap();
+    toml::from_str(&overridden.text).unwrap()
+}
+
+#[test]
+fn search_prefers_working_directory_then_xdg_then_etc() {
+    let dirs = search_dirs(Some(OsString::from("/xdg")), Some(OsString::from("/home/pi")));
+    assert_eq!(dirs, vec![PathBuf::from("."), PathBuf::from("/xdg/transit-sign"), PathBuf::from("/etc/transit-sign")]);
+
+    // XDG_CONFIG_HOME defaults to ~/.config, and an empty one counts as unset
+    let dirs = search_dirs(Some(OsString::new()), Some(OsString::from("/home/pi")));
+    assert_eq!(dirs[1], PathBuf::from("/home/pi/.config/transit-sign"));
+    assert_eq!(search_dirs(None, None).len(), 2);
+}
+
+#[test]
+fn overrides_replace_nested_keys_and_keep_the_rest() {
+    let overridden = apply_overrides(
+        DISPLAY_TOML,
+        DISPLAY_OVERRIDE_PREFIX,
+        vars(&[("TRANSIT_SIGN_DISPLAY__PANEL__BRIGHTNESS", "10"), ("PATH", "/usr/bin")]),
+    ).unwrap();
+
+    assert_eq!(overridden.applied, vec!["TRANSIT_SIGN_DISPLAY__PANEL__BRIGHTNESS"]);
+    assert!(overridden.text.starts_with("# Transit Display Configuration\n"), "{}", overridden.text);
+    let table: toml::Table = toml::from_str(&overridden.text).unwrap();
+    assert_eq!(table["panel"]["brightness"].as_integer(), Some(10));
+    assert_eq!(table["panel"]["rows"].as_integer(), Some(16));
+}
+
+#[test]
+fn overrides_create_missing_tables() {
+    let table = overridden(&[("TRANSIT_SIGN_DISPLAY__INPUT__HTTP_BIND", "0.0.0.0:8080")]);
+    assert_eq!(table["input"]["http_bind"].as_str(), Some("0.0.0.0:8080"));
+}
+
+#[test]
+fn only_keys_the_file_gives_another_type_are_read_as_toml() {
+    let overridden = apply_overrides(
+        "[providers.muni]\ntype = \"muni\"\nagency = \"SF\"\npoll_interval_secs = 60\n",
+        CONFIG_OVERRIDE_PREFIX,
+        vars(&[
+            ("TRANSIT_SIGN_CONFIG__PROVIDERS__MUNI__AGENCY", "true"),
+            ("TRANSIT_SIGN_CONFIG__PROVIDERS__MUNI__API_KEY", "12345"),
+            ("TRANSIT_SIGN_CONFIG__PROVIDERS__MUNI__POLL_INTERVAL_SECS", "30"),
+            ("TRANSIT_SIGN_CONFIG__PROVIDERS__MUNI__BASE_URL", "0x1F"),
+        ]),
+    ).unwrap();
+    let table: toml::Table = toml::from_str(&overridden.text).unwrap();
+    let muni = &table["providers"]["muni"];
+    assert_eq!(muni["agency"].as_str(), Some("true"));
+    assert_eq!(muni["api_key"].as_str(), Some("12345"));
+    assert_eq!(muni["base_url"].as_str(), Some("0x1F"));
+    assert_eq!(muni["poll_interval_secs"].as_integer(), Some(30));
+}
+
+#[test]
+fn lines_map_back_to_the_file_or_the_variable_that_wrote_them() {
+    let overridden = apply_overrides(
+        DISPLAY_TOML,
+        DISPLAY_OVERRIDE_PREFIX,
+        vars(&[
+            ("TRANSIT_SIGN_DISPLAY__ERROR_TIMEOUT", "30s"),
+            ("TRANSIT_SIGN_DISPLAY__PANEL__BRIGHTNESS", "10"),
+            ("TRANSIT_SIGN_DISPLAY__PANEL__COLS", "64"),
+        ]),
+    ).unwrap();
+    let lines: Vec<(&str, &LineOrigin)> = overridden.text.lines().zip(&overridden.lines).collect();
+    let env = |name: &str| LineOrigin::Env(name.to_string());
+
+    assert_eq!(overridden.lines.len(), overridden.text.lines().count());
+    assert_eq!(lines[0], ("# Transit Display Configuration", &LineOrigin::File(1)));
+    assert!(lines.contains(&("error_timeout = \"30s\"", &env("TRANSIT_SIGN_DISPLAY__ERROR_TIMEOUT"))), "{:?}", lines);
+    assert!(lines.contains(&("[panel]", &LineOrigin::File(4))), "{:?}", lines);
+    assert!(lines.contains(&("rows = 16", &LineOrigin::File(5))), "{:?}", lines);
+    assert!(lines.contains(&("brightness = 10", &env("TRANSIT_SIGN_DISPLAY__PANEL__BRIGHTNESS"))), "{:?}", lines);
+    assert!(lines.contains(&("cols = \"64\"", &env("TRANSIT_SIGN_DISPLAY__PANEL__COLS"))), "{:?}", lines);
+}
+
+#[test]
+fn overrides_must_name_a_key_inside_tables() {
+    let into_value = apply_overrides(DISPLAY_TOML, DISPLAY_OVERRIDE_PREFIX, vars(&[("TRANSIT_SIGN_DISPLAY__PANEL__ROWS__X", "1")]));
+    assert!(into_value.unwrap_err().contains("rows is not a table"));
+    assert!(apply_overrides(DISPLAY_TOML, DISPLAY_OVERRIDE_PREFIX, vars(&[("TRANSIT_SIGN_DISPLAY__PANEL__", "1")])).is_err());
+}
+
+#[test]
+fn secrets_are_read_relative_to_the_config_directory() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/secrets_test");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("muni.key"), "abc123\n").unwrap();
+
+    assert_eq!(read_secret(&PathBuf::from("muni.key"), &dir).unwrap(), "abc123");
+    assert_eq!(read_secret(&dir.join("muni.key"), &PathBuf::from("/elsewhere")).unwrap(), "abc123");
+    assert!(read_secret(&PathBuf::from("missing.key"), &dir).is_err());
+}
@@ -0,0 +1,66 @@
+use embedded_graphics_core::{
+    draw_target::DrawTarget,
+    geometry::Dimensions,
+    pixelcolor::{Rgb888, RgbColor},
+    primitives::Rectangle,
+    Pixel,
+};
+
+// Scales a color to `level` percent of its full intensity
+pub fn dim(color: Rgb888, level: u8) -> Rgb888 {
+    if level >= 100 {
+        return color;
+    }
+    let scale = |channel: u8| (channel as u16 * level as u16 / 100) as u8;
+    Rgb888::new(scale(color.r()), scale(color.g()), scale(color.b()))
+}
+
+// Brightness as a percentage of a reference brightness, for previews that show
+// the configured panel brightness at full intensity
+pub fn relative_level(brightness: u8, full_brightness: u8) -> u8 {
+    (brightness as u32 * 100 / full_brightness.max(1) as u32).min(100) as u8
+}
+
+/// Draw target wrapper that dims every pixel on its way to the inner target,
+/// for displays whose own brightness can't be changed once they are running.
+pub struct Dimmed<T> {
+    inner: T,
+    level: u8,
+}
+
+impl<T> Dimmed<T> {
+    pub fn new(inner: T, level: u8) -> Self {
+        Self { inner, level }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Dimensions> Dimensions for Dimmed<T> {
+    fn bounding_box(&self) -> Rectangle {
+        self.inner.bounding_box()
+    }
+}
+
+impl<T: DrawTarget<Color = Rgb888>> DrawTarget for Dimmed<T> {
+    type Color = Rgb888;
+    type Error = T::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let level = self.level;
+        self.inner.draw_iter(pixels.into_iter().map(|Pixel(point, color)| Pixel(point, dim(color, level))))
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.inner.fill_solid(area, dim(color, self.level))
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.inner.clear(dim(color, self.level))
+    }
+}
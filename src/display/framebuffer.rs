@@ -0,0 +1,243 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use embedded_graphics_core::{
+    draw_target::DrawTarget,
+    geometry::{OriginDimensions, Point, Size},
+    pixelcolor::{Rgb888, RgbColor},
+    Pixel,
+};
+
+use crate::display::StateEvent;
+
+use super::dimming::{dim, relative_level};
+use super::DisplayContext;
+
+/// In-memory RGB framebuffer usable as a draw target without any hardware.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    size: Size,
+    pixels: Vec<Rgb888>,
+}
+
+impl Framebuffer {
+    pub fn new(size: Size) -> Self {
+        Self {
+            size,
+            pixels: vec![Rgb888::BLACK; (size.width * size.height) as usize],
+        }
+    }
+
+    #[cfg(test)]
+    pub fn pixel(&self, point: Point) -> Option<Rgb888> {
+        self.index(point).map(|idx| self.pixels[idx])
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        let (x, y) = (point.x, point.y);
+        if x < 0 || y < 0 || x >= self.size.width as i32 || y >= self.size.height as i32 {
+            return None;
+        }
+        Some(y as usize * self.size.width as usize + x as usize)
+    }
+
+    // Copy with every pixel scaled to `level` percent
+    pub fn dimmed(&self, level: u8) -> Self {
+        Self {
+            size: self.size,
+            pixels: self.pixels.iter().map(|&pixel| dim(pixel, level)).collect(),
+        }
+    }
+
+    // Packed 8-bit RGB rows, top to bottom
+    pub fn to_rgb_bytes(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|p| [p.r(), p.g(), p.b()]).collect()
+    }
+
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{} {}\n255\n", self.size.width, self.size.height).into_bytes();
+        ppm.extend(self.to_rgb_bytes());
+        ppm
+    }
+
+    pub fn write_ppm(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_ppm())?;
+        Ok(())
+    }
+
+    pub fn write_png(&self, path: &Path) -> anyhow::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.size.width, self.size.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgb_bytes())?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn read_png(path: &Path) -> anyhow::Result<Self> {
+        let decoder = png::Decoder::new(File::open(path)?);
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        if info.color_type != png::ColorType::Rgb || info.bit_depth != png::BitDepth::Eight {
+            return Err(anyhow::anyhow!("{} is not an 8-bit RGB PNG", path.display()));
+        }
+
+        let pixels = buf[..info.buffer_size()]
+            .chunks_exact(3)
+            .map(|rgb| Rgb888::new(rgb[0], rgb[1], rgb[2]))
+            .collect();
+        Ok(Self { size: Size::new(info.width, info.height), pixels })
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = Rgb888;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if let Some(idx) = self.index(point) {
+                self.pixels[idx] = color;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameFormat {
+    Png,
+    Ppm,
+}
+
+impl FrameFormat {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "png" => Some(Self::Png),
+            "ppm" => Some(Self::Ppm),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Ppm => "ppm",
+        }
+    }
+}
+
+/// Where and how often frames are written to disk. Animations such as the
+/// marquee change the frame many times a second, so at most one frame is written
+/// per `min_interval` and the files are reused as a ring of `keep` frames,
+/// bounding the writes to an SD card as well as the space taken.
+#[derive(Debug, Clone)]
+pub struct FrameDump {
+    pub dir: PathBuf,
+    pub format: FrameFormat,
+    pub keep: usize,
+    pub min_interval: Duration,
+}
+
+impl FrameDump {
+    pub const DEFAULT_KEEP: usize = 100;
+    pub const DEFAULT_MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(dir: PathBuf, format: FrameFormat) -> Self {
+        Self { dir, format, keep: Self::DEFAULT_KEEP, min_interval: Self::DEFAULT_MIN_INTERVAL }
+    }
+}
+
+/// Headless display context. Keeps the most recently shown frame and, when
+/// dumping is set up, writes new frames to numbered PNGs or PPMs as `FrameDump`
+/// allows. Frames are kept at the configured panel brightness and dimmed only
+/// when the brightness schedule turns it down.
+pub struct FramebufferContext {
+    size: Size,
+    full_brightness: u8,
+    brightness: u8,
+    dump: Option<FrameDump>,
+    dump_count: usize,
+    last_dump: Option<Instant>,
+    // The last frame changed since it was last written
+    dump_pending: bool,
+    last_frame: Option<Framebuffer>,
+}
+
+impl FramebufferContext {
+    pub fn new(size: Size, brightness: u8, dump: Option<FrameDump>) -> Self {
+        Self {
+            size,
+            full_brightness: brightness,
+            brightness,
+            dump,
+            dump_count: 0,
+            last_dump: None,
+            dump_pending: false,
+            last_frame: None,
+        }
+    }
+
+    // Writes the last frame if it hasn't been and the interval allows. A frame
+    // held back is written on a later call, so the final frame of an animation
+    // still reaches disk once the display settles.
+    fn dump_if_due(&mut self) {
+        let (Some(dump), Some(frame)) = (&self.dump, &self.last_frame) else {
+            return;
+        };
+        if !self.dump_pending || self.last_dump.is_some_and(|last_dump| last_dump.elapsed() < dump.min_interval) {
+            return;
+        }
+
+        let path = dump.dir.join(format!("frame-{:06}.{}", self.dump_count % dump.keep.max(1), dump.format.extension()));
+        let result = match dump.format {
+            FrameFormat::Png => frame.write_png(&path),
+            FrameFormat::Ppm => frame.write_ppm(&path),
+        };
+        if let Err(e) = result {
+            warn!("Failed to write frame {}: {}", path.display(), e);
+        }
+        self.dump_count += 1;
+        self.last_dump = Some(Instant::now());
+        self.dump_pending = false;
+    }
+}
+
+impl DisplayContext for FramebufferContext {
+    type Display = Framebuffer;
+
+    fn show_display(&mut self, display: Self::Display) -> (Self::Display, impl Iterator<Item = StateEvent>) {
+        let frame = display.dimmed(relative_level(self.brightness, self.full_brightness));
+        if self.last_frame.as_ref() != Some(&frame) {
+            self.last_frame = Some(frame);
+            self.dump_pending = true;
+        }
+        self.dump_if_due();
+        (display, std::iter::empty())
+    }
+
+    fn target(&mut self) -> Self::Display {
+        Framebuffer::new(self.size)
+    }
+
+    fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+}
+
@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use embedded_graphics_core::geometry::{Point, Size};
+use embedded_graphics_core::pixelcolor::{Rgb888, RgbColor};
+use embedded_graphics_core::Pixel;
+use embedded_graphics_core::draw_target::DrawTarget;
+
+use super::framebuffer::{FrameDump, FrameFormat, Framebuffer, FramebufferContext};
+use super::DisplayContext;
+
+fn dump_dir(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/frame_dumps").join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// A frame with one lit pixel at x, so that each x gives a different frame
+fn frame(x: i32) -> Framebuffer {
+    let mut frame = Framebuffer::new(Size::new(8, 1));
+    frame.draw_iter([Pixel(Point::new(x, 0), Rgb888::WHITE)]).unwrap();
+    frame
+}
+
+fn dumped(dir: &PathBuf) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn dumps_reuse_a_ring_of_files() {
+    let dir = dump_dir("ring");
+    let dump = FrameDump { keep: 3, min_interval: Duration::ZERO, ..FrameDump::new(dir.clone(), FrameFormat::Ppm) };
+    let mut context = FramebufferContext::new(Size::new(8, 1), 100, Some(dump));
+
+    for x in 0..5 {
+        let _ = context.show_display(frame(x));
+    }
+
+    assert_eq!(dumped(&dir), vec!["frame-000000.ppm", "frame-000001.ppm", "frame-000002.ppm"]);
+    // The fourth and fifth frames replaced the first two
+    assert_eq!(std::fs::read(dir.join("frame-000001.ppm")).unwrap(), frame(4).to_ppm());
+}
+
+#[test]
+fn frames_within_the_interval_are_held_back_until_it_passes() {
+    let dir = dump_dir("interval");
+    let dump = FrameDump { min_interval: Duration::from_millis(200), ..FrameDump::new(dir.clone(), FrameFormat::Ppm) };
+    let mut context = FramebufferContext::new(Size::new(8, 1), 100, Some(dump));
+
+    // An animation: only its first frame is written straight away
+    for x in 0..5 {
+        let _ = context.show_display(frame(x));
+    }
+    assert_eq!(dumped(&dir), vec!["frame-000000.ppm"]);
+
+    // Once the display settles, its final frame is written, and only once
+    std::thread::sleep(Duration::from_millis(250));
+    let _ = context.show_display(frame(4));
+    std::thread::sleep(Duration::from_millis(250));
+    let _ = context.show_display(frame(4));
+    assert_eq!(dumped(&dir), vec!["frame-000000.ppm", "frame-000001.ppm"]);
+    assert_eq!(std::fs::read(dir.join("frame-000001.ppm")).unwrap(), frame(4).to_ppm());
+}
@@ -1,10 +1,12 @@
 use std::time::{Duration, Instant};
-use std::collections::HashMap;
-use std::error::Error;
+
+use chrono::{DateTime, Local, Utc};
+use itertools::Itertools;
 
 use crate::config::DisplayConfig;
-use super::{Color, DisplayMode, StateEvent};
-use crate::transit::{TransitIdentifier, TransitState};
+use super::schedule::Schedule;
+use super::{DisplayMode, StateEvent};
+use crate::transit::TransitIdentifier;
 
 use super::super::SharedTransitStateManager;
 
@@ -14,120 +16,244 @@ pub struct DisplayFiniteStateMachine {
     config: DisplayConfig,  // Stores page list, other display options
     pub page_idx: usize,
     pub subpage_idx: usize,
+    // When the current subpage was shown, and when the page was last changed by hand
+    shown_at: Instant,
+    last_input: Option<Instant>,
+    // Decides which pages are shown and how bright the panel is right now
+    schedule: Schedule,
+    // The page, subpage and fetch time whose stale error has already been shown,
+    // so returning to that subpage doesn't raise it again until it is refreshed
+    stale_dismissed: Option<(usize, usize, Option<DateTime<Utc>>)>,
     
     transit_manager: SharedTransitStateManager
 }
 
 impl DisplayFiniteStateMachine {
     pub fn new(config: DisplayConfig, transit_manager: SharedTransitStateManager) -> Self {
-        let initial_transit_identifier = config.pages[0].subpages[0].transit_identifier.clone();
+        let schedule = Schedule::new(config.location);
+        let page_idx = Self::scheduled_pages(&config, &schedule)[0];
+        let initial_subpage = &config.pages[page_idx].subpages[0];
 
         Self {
             current_state: DisplayMode::Transit {
-                transit_identifier: initial_transit_identifier,
+                transit_identifiers: initial_subpage.transit_identifiers.clone(),
+                filter: Box::new(initial_subpage.filter.clone()),
                 transit_state: None,
                 last_update: Instant::now(),
+                fetched_at: None,
+                last_error: None,
             },
             config,
-            page_idx: 0,
+            page_idx,
             subpage_idx: 0,
+            shown_at: Instant::now(),
+            last_input: None,
+            schedule,
+            stale_dismissed: None,
             transit_manager
         }
     }
 
     pub async fn handle_event(&mut self, event: StateEvent) {
+        if matches!(event, StateEvent::NextPage | StateEvent::NextSubpage | StateEvent::PreviousSubpage | StateEvent::Reset) {
+            self.last_input = Some(Instant::now());
+        }
+
         match event {
             StateEvent::NextPage => self.handle_next_page().await,
             StateEvent::NextSubpage => self.handle_next_subpage().await,
+            StateEvent::PreviousSubpage => self.handle_previous_subpage().await,
             StateEvent::Reset => self.handle_reset().await,
-            StateEvent::TransitUpdate => self.handle_transit_update().await,
+            StateEvent::TransitUpdate(changed) => self.handle_transit_update(changed).await,
             StateEvent::CustomMessage(msg) => self.handle_custom_message(msg),
             StateEvent::DisplayRefresh => self.handle_display_refresh().await,
             _ => (),
         };
     }
 
+    // Swaps in a new display.toml, staying on the current page and subpage where
+    // they still exist and the page is scheduled now. A message being shown stays
+    // up and then returns to the new subpage; an error is cleared, since it was
+    // about the old one. The panel and input handlers are set up once at startup,
+    // so changes to [panel] (other than its brightness) and [input] are kept out
+    // until a restart; the names of those tables are returned.
+    pub async fn reload(&mut self, mut config: DisplayConfig) -> Vec<&'static str> {
+        let mut needs_restart = Vec::new();
+        let brightness = config.panel.brightness;
+        config.panel.brightness = self.config.panel.brightness;
+        if config.panel != self.config.panel {
+            needs_restart.push("[panel]");
+            config.panel = self.config.panel.clone();
+        }
+        config.panel.brightness = brightness;
+        if config.input != self.config.input {
+            needs_restart.push("[input]");
+            config.input = self.config.input.clone();
+        }
+
+        self.schedule = Schedule::new(config.location);
+        self.config = config;
+        let active_pages = self.active_pages();
+        if !active_pages.contains(&self.page_idx) {
+            self.page_idx = active_pages[0];
+            self.subpage_idx = 0;
+        }
+        self.subpage_idx = self.subpage_idx.min(self.config.pages[self.page_idx].subpages.len() - 1);
+
+        let transit_mode = self.transit_mode(self.page_idx, self.subpage_idx).await;
+        match &mut self.current_state {
+            DisplayMode::CustomMessage { previous_state, .. } => **previous_state = transit_mode,
+            _ => self.current_state = transit_mode,
+        }
+        needs_restart
+    }
+
     pub fn current_state(&self) -> &DisplayMode {
         &self.current_state
     }
 
-    async fn get_state_for_identifier(&self, transit_identifier: &TransitIdentifier) -> TransitState {
-        self.transit_manager.read().await.get_state_for_identifier(transit_identifier)
+    // How long the current mode lasts before returning to transit, if it times out
+    pub fn current_timeout(&self) -> Option<Duration> {
+        match self.current_state {
+            DisplayMode::Transit { .. } => None,
+            DisplayMode::CustomMessage { .. } => Some(self.config.message_timeout),
+            DisplayMode::Error { .. } => Some(self.config.error_timeout),
+        }
     }
 
-    async fn handle_next_page(&mut self) {
-        match &self.current_state {
-            DisplayMode::Transit { .. } => {
-                let next_page_idx = (self.page_idx + 1) % self.config.pages.len();
-                let subpage_idx = 0;
-                let next_transit_identifier = self.config.pages[next_page_idx].subpages[subpage_idx].transit_identifier.clone();
-
-                let state_for_identifier = self.get_state_for_identifier(&next_transit_identifier).await;
-                self.current_state = DisplayMode::Transit {
-                    transit_identifier: next_transit_identifier,
-                    transit_state: Some(state_for_identifier),
-                    last_update: Instant::now(),
-                };
-                self.page_idx = next_page_idx;
-                self.subpage_idx = subpage_idx;
-            },
-            DisplayMode::CustomMessage { .. } => {
-                let first_transit_identifier = self.config.pages[0].subpages[0].transit_identifier.clone();
-                let state_for_identifier = self.get_state_for_identifier(&first_transit_identifier).await;
-                self.current_state = DisplayMode::Transit {
-                    transit_identifier: first_transit_identifier,
-                    transit_state: Some(state_for_identifier),
-                    last_update: Instant::now(),
-                };
-                self.page_idx = 0;
-                self.subpage_idx = 0;
-            },
-            DisplayMode::Error { .. } => (),
+    // Scheduled brightness for the panel, 0 when it should be blank
+    pub fn current_brightness(&self) -> u8 {
+        self.schedule.brightness(&self.config.brightness_schedule, self.config.panel.brightness, &Local::now())
+    }
+
+    // Indices of the pages whose schedule is active now. If none are, every page
+    // is shown rather than leaving the sign with nothing to display.
+    fn scheduled_pages(config: &DisplayConfig, schedule: &Schedule) -> Vec<usize> {
+        let now = Local::now();
+        let active: Vec<usize> = (0..config.pages.len())
+            .filter(|&idx| schedule.is_active(&config.pages[idx].schedule, &now))
+            .collect();
+        if active.is_empty() {
+            (0..config.pages.len()).collect()
+        } else {
+            active
         }
     }
 
-    async fn handle_next_subpage(&mut self) {
-        let next_subpage_idx = (self.subpage_idx + 1) % self.config.pages[self.page_idx].subpages.len();
-        let next_transit_identifier = self.config.pages[self.page_idx].subpages[next_subpage_idx].transit_identifier.clone();
-        let state_for_identifier = self.get_state_for_identifier(&next_transit_identifier).await;
+    fn active_pages(&self) -> Vec<usize> {
+        Self::scheduled_pages(&self.config, &self.schedule)
+    }
 
-        match &self.current_state {
-            DisplayMode::Error { .. } => (),
-            _ => {
-                self.current_state = DisplayMode::Transit {
-                    transit_identifier: next_transit_identifier,
-                    transit_state: Some(state_for_identifier),
-                    last_update: Instant::now(),
-                };
-                self.subpage_idx = next_subpage_idx;
+    // Active pages in order, starting after `page_idx` and wrapping around to it
+    fn active_pages_after(&self, page_idx: usize) -> Vec<usize> {
+        let (after, up_to): (Vec<usize>, Vec<usize>) = self.active_pages().into_iter().partition(|&idx| idx > page_idx);
+        after.into_iter().chain(up_to).collect()
+    }
+
+    fn next_active_page(&self, page_idx: usize) -> usize {
+        self.active_pages_after(page_idx)[0]
+    }
+
+    fn first_active_page(&self) -> usize {
+        self.active_pages()[0]
+    }
+
+    // The horizon for skipping subpages, if skipping is enabled
+    pub fn service_horizon(&self) -> Option<chrono::Duration> {
+        self.config.service_horizon.map(|horizon| chrono::Duration::from_std(horizon).unwrap_or(chrono::Duration::MAX))
+    }
+
+    // Whether a subpage has anything departing within the service horizon
+    async fn has_service(&self, page_idx: usize, subpage_idx: usize) -> bool {
+        let Some(horizon) = self.service_horizon() else {
+            return true;
+        };
+        let subpage = &self.config.pages[page_idx].subpages[subpage_idx];
+        let state = self.transit_manager.read().await.get_entry_for_identifiers(&subpage.transit_identifiers).state;
+        let now = Utc::now();
+        subpage.filter.apply(&state, now).has_service_within(now, horizon)
+    }
+
+    // The first of the candidate subpages of a page that has service
+    async fn subpage_with_service(&self, page_idx: usize, candidates: impl IntoIterator<Item = usize>) -> Option<usize> {
+        for subpage_idx in candidates {
+            if self.has_service(page_idx, subpage_idx).await {
+                return Some(subpage_idx);
             }
         }
+        None
     }
 
-    async fn handle_reset(&mut self) {
-        let first_transit_identifier = self.config.pages[0].subpages[0].transit_identifier.clone();
-        let state_for_identifier = self.get_state_for_identifier(&first_transit_identifier).await;
-        self.current_state = DisplayMode::Transit {
-            transit_identifier: first_transit_identifier,
-            transit_state: Some(state_for_identifier),
+    async fn first_subpage_with_service(&self, page_idx: usize) -> Option<usize> {
+        self.subpage_with_service(page_idx, 0..self.config.pages[page_idx].subpages.len()).await
+    }
+
+    // The next active page with a subpage in service, or just the next active page
+    // when nothing is running anywhere
+    async fn next_page_with_service(&self, page_idx: usize) -> usize {
+        for candidate in self.active_pages_after(page_idx) {
+            if self.first_subpage_with_service(candidate).await.is_some() {
+                return candidate;
+            }
+        }
+        self.next_active_page(page_idx)
+    }
+
+    // Builds the Transit mode for a subpage from the latest state entry
+    async fn transit_mode(&self, page_idx: usize, subpage_idx: usize) -> DisplayMode {
+        let subpage = &self.config.pages[page_idx].subpages[subpage_idx];
+        let entry = self.transit_manager.read().await.get_entry_for_identifiers(&subpage.transit_identifiers);
+        DisplayMode::Transit {
+            transit_identifiers: subpage.transit_identifiers.clone(),
+            filter: Box::new(subpage.filter.clone()),
+            transit_state: Some(entry.state),
             last_update: Instant::now(),
-        };
-        self.page_idx = 0;
-        self.subpage_idx = 0;
+            fetched_at: entry.fetched_at,
+            last_error: entry.last_error,
+        }
     }
 
-    async fn handle_transit_update(&mut self) {
+    async fn handle_next_page(&mut self) {
         match &self.current_state {
-            DisplayMode::Transit { transit_identifier, .. } => {
-                let new_state = self.get_state_for_identifier(&transit_identifier).await;
+            DisplayMode::Transit { .. } | DisplayMode::Error { .. } => self.show_page(self.next_page_with_service(self.page_idx).await).await,
+            DisplayMode::CustomMessage { .. } => self.show_page(self.first_active_page()).await,
+        }
+    }
 
-                self.current_state = DisplayMode::Transit {
-                    transit_identifier: transit_identifier.clone(),
-                    transit_state: Some(new_state),
-                    last_update: Instant::now(),
-                };
+    // Subpages without service are passed over, staying put if no other subpage
+    // has any, unless nothing on the page is running
+    async fn handle_next_subpage(&mut self) {
+        let subpage_count = self.config.pages[self.page_idx].subpages.len();
+        let next_subpage_idx = (self.subpage_idx + 1) % subpage_count;
+        let candidates = (1..=subpage_count).map(|offset| (self.subpage_idx + offset) % subpage_count);
+        let subpage_idx = self.subpage_with_service(self.page_idx, candidates).await.unwrap_or(next_subpage_idx);
+        self.show_subpage(subpage_idx).await;
+    }
+
+    async fn handle_previous_subpage(&mut self) {
+        let subpage_count = self.config.pages[self.page_idx].subpages.len();
+        let previous_subpage_idx = (self.subpage_idx + subpage_count - 1) % subpage_count;
+        let candidates = (1..=subpage_count).map(|offset| (self.subpage_idx + subpage_count - offset) % subpage_count);
+        let subpage_idx = self.subpage_with_service(self.page_idx, candidates).await.unwrap_or(previous_subpage_idx);
+        self.show_subpage(subpage_idx).await;
+    }
+
+    async fn show_subpage(&mut self, subpage_idx: usize) {
+        self.current_state = self.transit_mode(self.page_idx, subpage_idx).await;
+        self.subpage_idx = subpage_idx;
+        self.shown_at = Instant::now();
+    }
+
+    async fn handle_reset(&mut self) {
+        self.show_page(self.first_active_page()).await;
+    }
+
+    async fn handle_transit_update(&mut self, changed: Vec<TransitIdentifier>) {
+        match &self.current_state {
+            DisplayMode::Transit { transit_identifiers, .. } if transit_identifiers.iter().any(|identifier| changed.contains(identifier)) => {
+                self.current_state = self.transit_mode(self.page_idx, self.subpage_idx).await;
             }
-            DisplayMode::CustomMessage { .. } | DisplayMode::Error { .. } => (),
+            _ => (),
         }
     }
 
@@ -148,10 +274,109 @@ impl DisplayFiniteStateMachine {
             }
             DisplayMode::Error { start_time, .. } => {
                 if start_time.elapsed() >= self.config.error_timeout {
-                    self.handle_reset().await;
+                    // Back to the subpage that went stale, showing its last data
+                    let transit_mode = self.transit_mode(self.page_idx, self.subpage_idx).await;
+                    if let DisplayMode::Transit { fetched_at, .. } = &transit_mode {
+                        self.stale_dismissed = Some((self.page_idx, self.subpage_idx, *fetched_at));
+                    }
+                    self.current_state = transit_mode;
                 }
             }
-            _ => (),
+            DisplayMode::Transit { .. } if !self.active_pages().contains(&self.page_idx) => {
+                // The current page's schedule has ended
+                self.show_page(self.next_page_with_service(self.page_idx).await).await;
+            }
+            DisplayMode::Transit { transit_identifiers, .. } => {
+                // A refresh that changed nothing sends no update, so take the fetch time from the manager
+                let transit_identifiers = transit_identifiers.clone();
+                let entry = self.transit_manager.read().await.get_entry_for_identifiers(&transit_identifiers);
+                if let DisplayMode::Transit { fetched_at, .. } = &mut self.current_state {
+                    *fetched_at = entry.fetched_at;
+                }
+
+                let stale_message = match self.stale_dismissed == Some((self.page_idx, self.subpage_idx, entry.fetched_at)) {
+                    true => None,
+                    false => self.stale_message(&transit_identifiers, entry.fetched_at, entry.last_error.as_deref()).await,
+                };
+                match stale_message {
+                    Some(message) => {
+                        self.current_state = DisplayMode::Error {
+                            message,
+                            start_time: Instant::now(),
+                        };
+                    }
+                    None => self.auto_advance().await,
+                }
+            }
+        }
+    }
+
+    // Moves to the next subpage, or the first subpage of the next page, once the
+    // current one has been shown for its dwell time and nobody has pressed a
+    // button within pause_after_input
+    async fn auto_advance(&mut self) {
+        let Some(auto_advance) = self.config.auto_advance else {
+            return;
+        };
+
+        let now = Instant::now();
+        if self.last_input.is_some_and(|input| now.duration_since(input) < auto_advance.pause_after_input) {
+            return;
+        }
+        let dwell = self.config.pages[self.page_idx].dwell.unwrap_or(auto_advance.dwell);
+        if now.duration_since(self.shown_at) < dwell {
+            return;
         }
+
+        let remaining = self.subpage_idx + 1..self.config.pages[self.page_idx].subpages.len();
+        match self.subpage_with_service(self.page_idx, remaining).await {
+            Some(subpage_idx) => self.show_subpage(subpage_idx).await,
+            None => self.show_page(self.next_page_with_service(self.page_idx).await).await,
+        }
+    }
+
+    // Shows the first subpage of a page that has service, or its first subpage
+    async fn show_page(&mut self, page_idx: usize) {
+        let subpage_idx = self.first_subpage_with_service(page_idx).await.unwrap_or(0);
+        self.current_state = self.transit_mode(page_idx, subpage_idx).await;
+        self.page_idx = page_idx;
+        self.subpage_idx = subpage_idx;
+        self.shown_at = Instant::now();
+    }
+
+    // Data is stale once it is older than `stale_after`, or than two refresh
+    // intervals of the slowest stop on the subpage where that is longer (a
+    // provider whose request budget is shared by many stops refreshes each one
+    // rarely), or when it has never been fetched and the last attempt failed
+    async fn stale_message(
+        &self,
+        transit_identifiers: &[TransitIdentifier],
+        fetched_at: Option<DateTime<Utc>>,
+        last_error: Option<&str>,
+    ) -> Option<String> {
+        let stale_after = {
+            let transit_manager = self.transit_manager.read().await;
+            transit_identifiers
+                .iter()
+                .filter_map(|identifier| transit_manager.refresh_interval(&identifier.provider_key))
+                .map(|interval| interval * 2)
+                .fold(self.config.stale_after?, Duration::max)
+        };
+        let is_stale = match fetched_at {
+            Some(fetched_at) => Utc::now()
+                .signed_duration_since(fetched_at)
+                .to_std()
+                .is_ok_and(|age| age >= stale_after),
+            None => last_error.is_some(),
+        };
+        if !is_stale {
+            return None;
+        }
+
+        let station_ids = transit_identifiers.iter().map(|identifier| identifier.station_id.as_str()).unique().join("/");
+        Some(match last_error {
+            Some(error) => format!("{}: {}", station_ids, error),
+            None => format!("{}: data stale", station_ids),
+        })
     }
 }
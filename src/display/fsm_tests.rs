@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use tokio::sync::RwLock;
+
+use crate::config::{Config, DisplayConfig};
+use crate::transit::http::HttpClient;
+use crate::transit::state::{TransitStateEntry, TransitStateManager};
+use crate::transit::{LabelFormat, Prediction, TransitIdentifier, TransitState};
+use super::fsm::DisplayFiniteStateMachine;
+use super::{Color, DisplayMode, StateEvent};
+use crate::SharedTransitStateManager;
+
+// Two pages of two Muni subpages each, skipping anything without a departure in the next hour
+const DISPLAY_TOML: &str = r#"
+message_timeout = { secs = 5, nanos = 0 }
+error_timeout = { secs = 30, nanos = 0 }
+service_horizon = { secs = 3600, nanos = 0 }
+
+[[pages]]
+subpages = [
+    { transit_identifier = { provider_key = "muni", station_id = "1", direction = "North" }, page_display_handler_key = "predictions" },
+    { transit_identifier = { provider_key = "muni", station_id = "1", direction = "South" }, page_display_handler_key = "predictions" },
+]
+
+[[pages]]
+subpages = [
+    { transit_identifier = { provider_key = "muni", station_id = "2", direction = "North" }, page_display_handler_key = "predictions" },
+    { transit_identifier = { provider_key = "muni", station_id = "2", direction = "South" }, page_display_handler_key = "predictions" },
+]
+"#;
+
+fn identifier(station_id: &str, direction: &str) -> TransitIdentifier {
+    TransitIdentifier {
+        provider_key: "muni".to_string(),
+        station_id: station_id.to_string(),
+        direction: direction.parse().unwrap(),
+    }
+}
+
+// Predictions for a subpage with one departure the given number of minutes away
+fn departing_in(identifier: &TransitIdentifier, minutes: i64) -> TransitStateEntry {
+    let prediction = Prediction {
+        provider_key: identifier.provider_key.clone(),
+        route_name: "24".to_string(),
+        destination: "Divisadero".to_string(),
+        minutes_until_arrival: minutes as i32,
+        predicted_time: Utc::now() + Duration::minutes(minutes),
+        station_id: identifier.station_id.clone(),
+        stop_id: identifier.station_id.clone(),
+        direction: identifier.direction,
+        color: Color::default(),
+        label_format: LabelFormat::default(),
+        scheduled: false,
+    };
+    TransitStateEntry {
+        state: TransitState::Predictions(vec![prediction]),
+        fetched_at: Some(Utc::now()),
+        last_error: None,
+    }
+}
+
+fn no_departures() -> TransitStateEntry {
+    TransitStateEntry {
+        state: TransitState::Predictions(Vec::new()),
+        fetched_at: Some(Utc::now()),
+        last_error: None,
+    }
+}
+
+fn fsm(display_toml: &str, state: Vec<(TransitIdentifier, TransitStateEntry)>) -> DisplayFiniteStateMachine {
+    fsm_with_providers(Config { providers: HashMap::new() }, display_toml, state)
+}
+
+fn fsm_with_providers(providers: Config, display_toml: &str, state: Vec<(TransitIdentifier, TransitStateEntry)>) -> DisplayFiniteStateMachine {
+    let config: DisplayConfig = toml::from_str(display_toml).unwrap();
+    let mut manager = TransitStateManager::new(providers, HttpClient::live()).unwrap();
+    manager.state = state.into_iter().collect();
+    let manager: SharedTransitStateManager = Arc::new(RwLock::new(manager));
+    DisplayFiniteStateMachine::new(config, manager)
+}
+
+#[tokio::test]
+async fn next_subpage_skips_direction_without_service() {
+    let mut fsm = fsm(DISPLAY_TOML, vec![
+        (identifier("1", "North"), departing_in(&identifier("1", "North"), 5)),
+        (identifier("1", "South"), no_departures()),
+    ]);
+
+    fsm.handle_event(StateEvent::NextSubpage).await;
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (0, 0));
+}
+
+#[tokio::test]
+async fn next_page_lands_on_first_subpage_with_service() {
+    let mut fsm = fsm(DISPLAY_TOML, vec![
+        (identifier("1", "North"), departing_in(&identifier("1", "North"), 5)),
+        (identifier("2", "North"), departing_in(&identifier("2", "North"), 90)),
+        (identifier("2", "South"), departing_in(&identifier("2", "South"), 10)),
+    ]);
+
+    fsm.handle_event(StateEvent::NextPage).await;
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (1, 1));
+}
+
+#[tokio::test]
+async fn next_page_skips_pages_without_service() {
+    let mut fsm = fsm(DISPLAY_TOML, vec![
+        (identifier("1", "North"), departing_in(&identifier("1", "North"), 5)),
+        (identifier("2", "North"), no_departures()),
+        (identifier("2", "South"), departing_in(&identifier("2", "South"), 120)),
+    ]);
+
+    fsm.handle_event(StateEvent::NextPage).await;
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (0, 0));
+}
+
+#[tokio::test]
+async fn pages_are_shown_when_nothing_is_running() {
+    let mut fsm = fsm(DISPLAY_TOML, vec![
+        (identifier("1", "North"), no_departures()),
+        (identifier("1", "South"), no_departures()),
+        (identifier("2", "North"), no_departures()),
+        (identifier("2", "South"), no_departures()),
+    ]);
+
+    fsm.handle_event(StateEvent::NextPage).await;
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (1, 0));
+    fsm.handle_event(StateEvent::NextSubpage).await;
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (1, 1));
+}
+
+#[tokio::test]
+async fn nothing_is_skipped_without_a_horizon() {
+    let display_toml = DISPLAY_TOML.replace("service_horizon = { secs = 3600, nanos = 0 }", "");
+    let mut fsm = fsm(&display_toml, vec![
+        (identifier("1", "North"), departing_in(&identifier("1", "North"), 5)),
+        (identifier("1", "South"), no_departures()),
+    ]);
+
+    fsm.handle_event(StateEvent::NextSubpage).await;
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (0, 1));
+}
+
+#[tokio::test]
+async fn filtered_out_departures_do_not_count_as_service() {
+    // Station 2's northbound departure is the 24, which the subpage filters out
+    let display_toml = DISPLAY_TOML.replace(
+        r#"station_id = "2", direction = "North" }, page_display_handler_key = "predictions" }"#,
+        r#"station_id = "2", direction = "North" }, page_display_handler_key = "predictions", filter = { exclude_routes = ["24"] } }"#,
+    );
+    let mut fsm = fsm(&display_toml, vec![
+        (identifier("1", "North"), departing_in(&identifier("1", "North"), 5)),
+        (identifier("2", "North"), departing_in(&identifier("2", "North"), 5)),
+        (identifier("2", "South"), departing_in(&identifier("2", "South"), 5)),
+    ]);
+
+    fsm.handle_event(StateEvent::NextPage).await;
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (1, 1));
+}
+
+const COMBINED_TOML: &str = r#"
+message_timeout = { secs = 5, nanos = 0 }
+error_timeout = { secs = 30, nanos = 0 }
+
+[[pages]]
+subpages = [
+    { transit_identifiers = [
+        { provider_key = "muni", station_id = "1", direction = "North" },
+        { provider_key = "muni", station_id = "2", direction = "North" },
+    ], page_display_handler_key = "predictions" },
+]
+"#;
+
+#[tokio::test]
+async fn combined_subpage_merges_stops_in_time_order() {
+    let mut fsm = fsm(COMBINED_TOML, vec![
+        (identifier("1", "North"), departing_in(&identifier("1", "North"), 9)),
+        (identifier("2", "North"), departing_in(&identifier("2", "North"), 4)),
+        (identifier("2", "South"), departing_in(&identifier("2", "South"), 1)),
+    ]);
+
+    // Any of the combined stops changing refreshes the subpage
+    fsm.handle_event(StateEvent::TransitUpdate(vec![identifier("2", "North")])).await;
+    let DisplayMode::Transit { transit_state: Some(TransitState::Predictions(predictions)), .. } = fsm.current_state() else {
+        panic!("expected predictions, got {:?}", fsm.current_state());
+    };
+    let stations: Vec<&str> = predictions.iter().map(|pred| pred.station_id.as_str()).collect();
+    assert_eq!(stations, vec!["2", "1"]);
+}
+
+#[test]
+fn subpage_needs_exactly_one_kind_of_identifier() {
+    let both = COMBINED_TOML.replace(
+        "], page_display_handler_key",
+        r#"], transit_identifier = { provider_key = "muni", station_id = "3", direction = "North" }, page_display_handler_key"#,
+    );
+    assert!(toml::from_str::<DisplayConfig>(&both).is_err());
+
+    let bikes = COMBINED_TOML.replace(r#""predictions""#, r#""bike_inventory""#);
+    let error = toml::from_str::<DisplayConfig>(&bikes).unwrap_err();
+    assert!(error.to_string().contains("combine several stops"), "{}", error);
+}
+
+#[tokio::test]
+async fn reload_keeps_position_where_it_still_exists() {
+    let mut fsm = fsm(DISPLAY_TOML, vec![
+        (identifier("2", "South"), departing_in(&identifier("2", "South"), 5)),
+    ]);
+    fsm.handle_event(StateEvent::NextPage).await;
+    fsm.handle_event(StateEvent::NextSubpage).await;
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (1, 1));
+
+    // Same pages with an extra subpage: nothing moves
+    let added = DISPLAY_TOML.replacen("\n]\n", "\n    { transit_identifier = { provider_key = \"muni\", station_id = \"3\", direction = \"North\" }, page_display_handler_key = \"predictions\" },\n]\n", 1);
+    fsm.reload(toml::from_str(&added).unwrap()).await;
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (1, 1));
+
+    // Only the first page is left, and it has a single subpage
+    fsm.reload(toml::from_str(COMBINED_TOML).unwrap()).await;
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (0, 0));
+    let DisplayMode::Transit { transit_identifiers, .. } = fsm.current_state() else {
+        panic!("expected transit mode, got {:?}", fsm.current_state());
+    };
+    assert_eq!(transit_identifiers, &vec![identifier("1", "North"), identifier("2", "North")]);
+}
+
+#[tokio::test]
+async fn reload_leaves_a_page_that_is_no_longer_scheduled() {
+    let mut fsm = fsm(DISPLAY_TOML, vec![
+        (identifier("1", "North"), departing_in(&identifier("1", "North"), 5)),
+        (identifier("2", "South"), departing_in(&identifier("2", "South"), 5)),
+    ]);
+    fsm.handle_event(StateEvent::NextPage).await;
+    fsm.handle_event(StateEvent::NextSubpage).await;
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (1, 1));
+
+    // The second page is still there, but now only shown on no day at all
+    let unscheduled = DISPLAY_TOML.replacen(
+        "[[pages]]\nsubpages = [\n    { transit_identifier = { provider_key = \"muni\", station_id = \"2\"",
+        "[[pages]]\nschedule = [{ days = [], start = \"00:00\", end = \"00:00\" }]\nsubpages = [\n    { transit_identifier = { provider_key = \"muni\", station_id = \"2\"",
+        1,
+    );
+    assert_ne!(unscheduled, DISPLAY_TOML);
+    fsm.reload(toml::from_str(&unscheduled).unwrap()).await;
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (0, 0));
+}
+
+#[tokio::test]
+async fn reload_reports_panel_and_input_changes_needing_a_restart() {
+    let mut fsm = fsm(DISPLAY_TOML, Vec::new());
+
+    // Brightness is applied as the panel is drawn, so it changes straight away
+    let brighter = format!("{}\n[panel]\nbrightness = 20\n", DISPLAY_TOML);
+    assert!(fsm.reload(toml::from_str(&brighter).unwrap()).await.is_empty());
+    assert_eq!(fsm.current_brightness(), 20);
+
+    let resized = format!("{}\n[panel]\nbrightness = 30\nrows = 32\n\n[input]\nhttp = true\n", DISPLAY_TOML);
+    assert_eq!(fsm.reload(toml::from_str(&resized).unwrap()).await, vec!["[panel]", "[input]"]);
+    assert_eq!(fsm.current_brightness(), 30);
+
+    // Still differs from what is running, so it is reported again
+    assert_eq!(fsm.reload(toml::from_str(&resized).unwrap()).await, vec!["[panel]", "[input]"]);
+}
+
+// Six Muni stops share 511.org's 60 requests an hour, so each is refreshed every 6 minutes
+const SIX_MUNI_STOPS: &str = r#"
+[providers.muni]
+type = "muni"
+api_key = "key"
+stops = [
+    { id = "1", name = "1", direction = "North" },
+    { id = "2", name = "2", direction = "North" },
+    { id = "3", name = "3", direction = "North" },
+    { id = "4", name = "4", direction = "North" },
+    { id = "5", name = "5", direction = "North" },
+    { id = "6", name = "6", direction = "North" },
+]
+"#;
+
+// The mode shown after a refresh of station 1 fetched the given number of seconds ago
+async fn mode_after_fetch(age_secs: i64) -> DisplayMode {
+    let mut entry = departing_in(&identifier("1", "North"), 5);
+    entry.fetched_at = Some(Utc::now() - Duration::seconds(age_secs));
+    let display_toml = format!("stale_after = {{ secs = 300, nanos = 0 }}\n{}", DISPLAY_TOML);
+    let mut fsm = fsm_with_providers(Config::from_toml(SIX_MUNI_STOPS).unwrap(), &display_toml, vec![(identifier("1", "North"), entry)]);
+
+    fsm.handle_event(StateEvent::TransitUpdate(vec![identifier("1", "North")])).await;
+    fsm.handle_event(StateEvent::DisplayRefresh).await;
+    fsm.current_state().clone()
+}
+
+#[tokio::test]
+async fn stale_after_allows_for_the_refresh_interval() {
+    assert!(matches!(mode_after_fetch(400).await, DisplayMode::Transit { .. }));
+    let DisplayMode::Error { message, .. } = mode_after_fetch(730).await else {
+        panic!("expected stale data to be reported");
+    };
+    assert_eq!(message, "1: data stale");
+}
+
+#[tokio::test]
+async fn refreshes_that_change_nothing_keep_the_subpage_fresh() {
+    let mut entry = departing_in(&identifier("1", "North"), 5);
+    entry.fetched_at = Some(Utc::now() - Duration::seconds(1000));
+    let display_toml = format!("stale_after = {{ secs = 300, nanos = 0 }}\n{}", DISPLAY_TOML);
+    let mut manager = TransitStateManager::new(Config { providers: HashMap::new() }, HttpClient::live()).unwrap();
+    manager.state.insert(identifier("1", "North"), entry);
+    let manager: SharedTransitStateManager = Arc::new(RwLock::new(manager));
+    let mut fsm = DisplayFiniteStateMachine::new(toml::from_str(&display_toml).unwrap(), manager.clone());
+    fsm.handle_event(StateEvent::Reset).await;
+
+    // The same departures came back, so no TransitUpdate is sent
+    manager.write().await.state.get_mut(&identifier("1", "North")).unwrap().fetched_at = Some(Utc::now());
+    fsm.handle_event(StateEvent::DisplayRefresh).await;
+    assert!(matches!(fsm.current_state(), DisplayMode::Transit { .. }), "{:?}", fsm.current_state());
+}
+
+// Station 2 was last fetched long ago; station 1 is fresh
+async fn on_stale_page(display_toml: &str) -> DisplayFiniteStateMachine {
+    let mut stale = departing_in(&identifier("2", "North"), 5);
+    stale.fetched_at = Some(Utc::now() - Duration::seconds(1000));
+    let display_toml = format!("stale_after = {{ secs = 300, nanos = 0 }}\n{}", display_toml);
+    let mut fsm = fsm(&display_toml, vec![
+        (identifier("1", "North"), departing_in(&identifier("1", "North"), 5)),
+        (identifier("2", "North"), stale),
+    ]);
+
+    fsm.handle_event(StateEvent::NextPage).await;
+    fsm.handle_event(StateEvent::DisplayRefresh).await;
+    assert!(matches!(fsm.current_state(), DisplayMode::Error { .. }), "{:?}", fsm.current_state());
+    fsm
+}
+
+#[tokio::test]
+async fn stale_error_returns_to_the_same_subpage() {
+    let mut fsm = on_stale_page(&DISPLAY_TOML.replace("error_timeout = { secs = 30", "error_timeout = { secs = 0")).await;
+
+    fsm.handle_event(StateEvent::DisplayRefresh).await;
+    assert!(matches!(fsm.current_state(), DisplayMode::Transit { .. }), "{:?}", fsm.current_state());
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (1, 0));
+
+    // Already reported, so it stays up rather than flipping back to the error
+    fsm.handle_event(StateEvent::DisplayRefresh).await;
+    assert!(matches!(fsm.current_state(), DisplayMode::Transit { .. }), "{:?}", fsm.current_state());
+}
+
+#[tokio::test]
+async fn navigation_leaves_a_stale_error() {
+    let mut fsm = on_stale_page(DISPLAY_TOML).await;
+
+    fsm.handle_event(StateEvent::NextPage).await;
+    assert!(matches!(fsm.current_state(), DisplayMode::Transit { .. }), "{:?}", fsm.current_state());
+    assert_eq!((fsm.page_idx, fsm.subpage_idx), (0, 0));
+}
@@ -3,25 +3,47 @@ use rpi_led_matrix::{LedCanvas, LedMatrix};
 
 use crate::display::StateEvent;
 
+use super::dimming::Dimmed;
 use super::DisplayContext;
 
-impl DisplayContext for LedMatrix {
-    type Display = LedCanvas;
+// LED matrix driven at full hardware brightness, dimmed in software so the
+// brightness can follow the schedule
+pub struct HardwareContext {
+    matrix: LedMatrix,
+    brightness: u8,
+}
+
+impl HardwareContext {
+    pub fn new(matrix: LedMatrix, brightness: u8) -> Self {
+        Self { matrix, brightness }
+    }
+
+    pub fn canvas(&self) -> Dimmed<LedCanvas> {
+        Dimmed::new(self.matrix.canvas(), self.brightness)
+    }
+}
+
+impl DisplayContext for HardwareContext {
+    type Display = Dimmed<LedCanvas>;
 
     fn show_display(&mut self, display: Self::Display) -> (Self::Display, impl Iterator<Item = StateEvent>) {
         debug!("Hardware display swap starting");
         // Try to get some debug info about the canvas state
         let new_canvas = {
             debug!("About to swap canvas");
-            let result = self.swap(display);
+            let result = self.matrix.swap(display.into_inner());
             debug!("Canvas swap completed");
             result
         };
         debug!("Hardware display swap completed, returning new canvas");
-        (new_canvas, std::iter::empty())
+        (Dimmed::new(new_canvas, self.brightness), std::iter::empty())
     }
 
     fn target(&mut self) -> Self::Display {
-        self.offscreen_canvas()
+        Dimmed::new(self.matrix.offscreen_canvas(), self.brightness)
+    }
+
+    fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
     }
 }
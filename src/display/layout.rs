@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use log::debug;
+use embedded_graphics::{
+    prelude::*,
+    primitives::Rectangle,
+    text::{renderer::TextRenderer, Alignment, Baseline, Text},
+    mono_font::MonoTextStyle,
+    pixelcolor::Rgb888,
+};
+
+// Width of a FONT_5X7 glyph, and the spacing between baselines of stacked lines
+pub const CHAR_WIDTH: u32 = 5;
+pub const LINE_HEIGHT: i32 = 8;
+
+// Marquee scroll speed in pixels per second
+pub(super) const MARQUEE_SPEED: f32 = 20.0;
+// Time the text rests at its start position before each pass
+pub(super) const MARQUEE_PAUSE: Duration = Duration::from_millis(1500);
+// Blank space between the end of the text and its wrapped-around start
+pub(super) const MARQUEE_GAP: i32 = 16;
+
+pub fn text_width(text: &str, style: &MonoTextStyle<'_, Rgb888>) -> u32 {
+    style.measure_string(text, Point::zero(), Baseline::Alphabetic).bounding_box.size.width
+}
+
+// Number of lines whose baselines fit on the panel when the first sits at y_offset
+pub fn lines_that_fit(bounds: Rectangle, y_offset: i32) -> usize {
+    ((bounds.size.height as i32 - 1 - y_offset) / LINE_HEIGHT + 1).max(0) as usize
+}
+
+// Region covering one line of text drawn at the given baseline
+pub fn line_region(x: i32, baseline: i32, width: u32) -> Rectangle {
+    Rectangle::new(Point::new(x, baseline + 1 - LINE_HEIGHT), Size::new(width, LINE_HEIGHT as u32))
+}
+
+// How far overflowing text has scrolled after `elapsed`; zero if it fits
+pub fn marquee_offset(text_width: u32, region_width: u32, elapsed: Duration) -> i32 {
+    if text_width <= region_width {
+        return 0;
+    }
+
+    let cycle_px = text_width as i32 + MARQUEE_GAP;
+    let pause = MARQUEE_PAUSE.as_secs_f32();
+    let cycle_secs = pause + cycle_px as f32 / MARQUEE_SPEED;
+    let t = elapsed.as_secs_f32() % cycle_secs;
+    if t < pause {
+        0
+    } else {
+        (((t - pause) * MARQUEE_SPEED) as i32).min(cycle_px)
+    }
+}
+
+/// Draws text clipped to `region`, scrolling it horizontally when it is wider
+/// than the region. Scrolling is driven by `elapsed`, so calling this every
+/// frame of the display loop animates it smoothly.
+pub fn draw_marquee_text<D>(
+    target: &mut D,
+    text: &str,
+    region: Rectangle,
+    baseline: i32,
+    style: MonoTextStyle<'_, Rgb888>,
+    elapsed: Duration,
+) where
+    D: DrawTarget<Color = Rgb888>,
+    D::Error: std::fmt::Debug,
+{
+    let width = text_width(text, &style);
+    let offset = marquee_offset(width, region.size.width, elapsed);
+    let x = region.top_left.x - offset;
+
+    let mut clipped = target.clipped(&region);
+    if let Err(e) = Text::new(text, Point::new(x, baseline), style).draw(&mut clipped) {
+        debug!("Failed to draw text: {:?}", e);
+    }
+    // Trailing copy so the start of the text follows its end around seamlessly
+    if offset > 0 {
+        let wrapped_x = x + width as i32 + MARQUEE_GAP;
+        if let Err(e) = Text::new(text, Point::new(wrapped_x, baseline), style).draw(&mut clipped) {
+            debug!("Failed to draw text: {:?}", e);
+        }
+    }
+}
+
+pub fn draw_right_aligned<D>(target: &mut D, text: &str, right_x: i32, baseline: i32, style: MonoTextStyle<'_, Rgb888>)
+where
+    D: DrawTarget<Color = Rgb888>,
+    D::Error: std::fmt::Debug,
+{
+    if let Err(e) = Text::with_alignment(text, Point::new(right_x, baseline), style, Alignment::Right).draw(target) {
+        debug!("Failed to draw text: {:?}", e);
+    }
+}
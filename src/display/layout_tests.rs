@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use crate::config::PanelConfig;
+use super::get_framebuffer_display;
+use super::layout::{marquee_offset, MARQUEE_GAP, MARQUEE_PAUSE, MARQUEE_SPEED};
+
+// 100px of text in a 60px region, so one pass scrolls 100px plus the gap
+const TEXT: u32 = 100;
+const REGION: u32 = 60;
+
+fn after_pause(secs: f32) -> Duration {
+    MARQUEE_PAUSE + Duration::from_secs_f32(secs)
+}
+
+#[test]
+fn text_that_fits_never_moves() {
+    assert_eq!(marquee_offset(REGION, REGION, Duration::ZERO), 0);
+    assert_eq!(marquee_offset(REGION, REGION, after_pause(2.0)), 0);
+}
+
+#[test]
+fn overflowing_text_rests_then_scrolls_at_marquee_speed() {
+    assert_eq!(marquee_offset(TEXT, REGION, Duration::ZERO), 0);
+    assert_eq!(marquee_offset(TEXT, REGION, MARQUEE_PAUSE - Duration::from_millis(1)), 0);
+    assert_eq!(marquee_offset(TEXT, REGION, after_pause(1.0)), MARQUEE_SPEED as i32);
+    assert_eq!(marquee_offset(TEXT, REGION, after_pause(2.5)), (2.5 * MARQUEE_SPEED) as i32);
+}
+
+#[test]
+fn each_pass_ends_where_the_next_starts() {
+    let cycle_px = TEXT as i32 + MARQUEE_GAP;
+    let scroll_secs = cycle_px as f32 / MARQUEE_SPEED;
+
+    // Just before the end of the pass the wrapped-around start is almost back in place
+    let near_end = marquee_offset(TEXT, REGION, after_pause(scroll_secs - 0.05));
+    assert!(near_end > cycle_px - MARQUEE_SPEED as i32 && near_end <= cycle_px, "{}", near_end);
+    // Then the next pass rests at the start again
+    assert_eq!(marquee_offset(TEXT, REGION, after_pause(scroll_secs + 0.05)), 0);
+    assert_eq!(marquee_offset(TEXT, REGION, after_pause(scroll_secs + 1.0)), 0);
+}
+
+#[test]
+fn restarting_the_animation_rewinds_it() {
+    let mut display = get_framebuffer_display(&PanelConfig::default(), None);
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(display.animation_time() >= Duration::from_millis(50));
+
+    display.restart_animation();
+    assert!(display.animation_time() < Duration::from_millis(50));
+    assert_eq!(marquee_offset(TEXT, REGION, display.animation_time()), 0);
+}
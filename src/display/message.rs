@@ -0,0 +1,183 @@
+use std::time::{Duration, Instant};
+
+use log::debug;
+use embedded_graphics::{
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, Rectangle},
+    text::Text,
+    mono_font::MonoTextStyle,
+    mono_font::ascii::FONT_5X7,
+    pixelcolor::Rgb888,
+};
+
+use super::{Display, DisplayContext};
+use super::layout::{lines_that_fit, CHAR_WIDTH, LINE_HEIGHT};
+
+// How long each screenful of a message that doesn't fit is shown before moving on
+pub(super) const PAGE_DURATION: Duration = Duration::from_secs(3);
+
+const MESSAGE_COLOR: Rgb888 = Rgb888::new(255, 255, 255);
+const ERROR_COLOR: Rgb888 = Rgb888::new(255, 0, 0);
+const TIMER_COLOR: Rgb888 = Rgb888::new(255, 255, 51);
+
+// Splits text into lines of at most max_chars, breaking on whitespace and
+// hard-splitting words that are longer than a line
+pub fn wrap_text(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut lines = Vec::new();
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        while word.len() > max_chars {
+            if !current.is_empty() {
+                lines.push(std::mem::take(&mut current));
+            }
+            lines.push(word.drain(..max_chars).collect());
+        }
+        let word: String = word.into_iter().collect();
+        if word.is_empty() {
+            continue;
+        }
+
+        if current.is_empty() {
+            current = word;
+        } else if current.chars().count() + 1 + word.chars().count() <= max_chars {
+            current.push(' ');
+            current.push_str(&word);
+        } else {
+            lines.push(std::mem::replace(&mut current, word));
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    lines
+}
+
+// Fraction of the timeout still remaining, from 1.0 down to 0.0
+fn remaining_fraction(start_time: Instant, timeout: Duration) -> f32 {
+    if timeout.is_zero() {
+        return 0.0;
+    }
+    1.0 - (start_time.elapsed().as_secs_f32() / timeout.as_secs_f32()).min(1.0)
+}
+
+// Picks the screenful of wrapped lines to show, cycling when they don't all fit
+pub(super) fn visible_lines(lines: &[String], lines_per_page: usize, start_time: Instant) -> &[String] {
+    let lines_per_page = lines_per_page.max(1);
+    let page_count = lines.len().div_ceil(lines_per_page).max(1);
+    let page = (start_time.elapsed().as_millis() / PAGE_DURATION.as_millis()) as usize % page_count;
+    let start = page * lines_per_page;
+    &lines[start.min(lines.len())..(start + lines_per_page).min(lines.len())]
+}
+
+fn draw_lines<D>(target: &mut D, lines: &[String], x: i32, first_baseline: i32, color: Rgb888)
+where
+    D: DrawTarget<Color = Rgb888>,
+    D::Error: std::fmt::Debug,
+{
+    for (i, line) in lines.iter().enumerate() {
+        let text = Text::new(
+            line,
+            Point::new(x, first_baseline + i as i32 * LINE_HEIGHT),
+            MonoTextStyle::new(&FONT_5X7, color),
+        );
+        if let Err(e) = text.draw(target) {
+            debug!("Failed to draw text: {:?}", e);
+        }
+    }
+}
+
+// Horizontal bar along row y that shrinks as the timeout runs out
+fn draw_timer_bar<D>(target: &mut D, bounds: Rectangle, y: i32, start_time: Instant, timeout: Duration)
+where
+    D: DrawTarget<Color = Rgb888>,
+    D::Error: std::fmt::Debug,
+{
+    let width = (bounds.size.width as f32 * remaining_fraction(start_time, timeout)).round() as i32;
+    if width <= 0 {
+        return;
+    }
+    if let Err(e) = Line::new(Point::new(0, y), Point::new(width - 1, y))
+        .into_styled(PrimitiveStyle::with_stroke(TIMER_COLOR, 1))
+        .draw(target) {
+        debug!("Failed to draw timer bar: {:?}", e);
+    }
+}
+
+fn clear<D>(target: &mut D, bounds: Rectangle)
+where
+    D: DrawTarget<Color = Rgb888>,
+    D::Error: std::fmt::Debug,
+{
+    if let Err(e) = Rectangle::new(Point::new(0, 0), bounds.size)
+        .into_styled(PrimitiveStyle::with_fill(Rgb888::BLACK))
+        .draw(target) {
+        debug!("Failed to clear display: {:?}", e);
+    }
+}
+
+/// Draws a custom message word-wrapped across the panel, with a timer bar
+/// along the top row counting down to `message_timeout`.
+pub fn draw_custom_message<C>(display: &mut Display<C>, message: &str, start_time: Instant, timeout: Option<Duration>)
+where
+    C: DisplayContext,
+    <C::Display as DrawTarget>::Error: std::fmt::Debug,
+{
+    let y_offset = display.y_offset;
+    let target = display.target_mut();
+    let bounds = target.bounding_box();
+    clear(target, bounds);
+
+    let max_chars = (bounds.size.width.saturating_sub(2) / CHAR_WIDTH) as usize;
+    let lines_per_page = lines_that_fit(bounds, y_offset);
+    let lines = wrap_text(message, max_chars);
+    draw_lines(target, visible_lines(&lines, lines_per_page, start_time), 1, y_offset, MESSAGE_COLOR);
+
+    if let Some(timeout) = timeout {
+        draw_timer_bar(target, bounds, 0, start_time, timeout);
+    }
+}
+
+/// Draws an error inside a red border with a "!" marker. The top edge of the
+/// border doubles as the timer bar counting down to `error_timeout`.
+pub fn draw_error<C>(display: &mut Display<C>, message: &str, start_time: Instant, timeout: Option<Duration>)
+where
+    C: DisplayContext,
+    <C::Display as DrawTarget>::Error: std::fmt::Debug,
+{
+    let y_offset = display.y_offset;
+    let target = display.target_mut();
+    let bounds = target.bounding_box();
+    clear(target, bounds);
+
+    if let Err(e) = Rectangle::new(Point::new(0, 0), bounds.size)
+        .into_styled(PrimitiveStyle::with_stroke(ERROR_COLOR, 1))
+        .draw(target) {
+        debug!("Failed to draw error border: {:?}", e);
+    }
+    draw_lines(target, &["!".to_string()], 2, y_offset, ERROR_COLOR);
+
+    // Text sits between the marker and the border, one pixel tighter vertically
+    // so the second line clears the bottom edge
+    let text_x = 2 + CHAR_WIDTH as i32 + 1;
+    let max_chars = (bounds.size.width as i32 - text_x - 1).max(0) as u32 / CHAR_WIDTH;
+    let lines_per_page = ((bounds.size.height as i32 - 1 - y_offset) / (LINE_HEIGHT - 1) + 1).max(1) as usize;
+    let lines = wrap_text(message, max_chars as usize);
+    for (i, line) in visible_lines(&lines, lines_per_page, start_time).iter().enumerate() {
+        let text = Text::new(
+            line,
+            Point::new(text_x, y_offset + i as i32 * (LINE_HEIGHT - 1)),
+            MonoTextStyle::new(&FONT_5X7, MESSAGE_COLOR),
+        );
+        if let Err(e) = text.draw(target) {
+            debug!("Failed to draw text: {:?}", e);
+        }
+    }
+
+    if let Some(timeout) = timeout {
+        draw_timer_bar(target, bounds, 0, start_time, timeout);
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::Instant;
+
+use super::message::{visible_lines, wrap_text, PAGE_DURATION};
+
+fn lines(lines: &[&str]) -> Vec<String> {
+    lines.iter().map(|line| line.to_string()).collect()
+}
+
+#[test]
+fn words_fill_each_line() {
+    assert_eq!(wrap_text("Delays on the N line", 10), lines(&["Delays on", "the N line"]));
+    // Runs of whitespace, including newlines, are a single break
+    assert_eq!(wrap_text("  Delays \n on\tthe  N ", 10), lines(&["Delays on", "the N"]));
+    assert!(wrap_text("   ", 10).is_empty());
+}
+
+#[test]
+fn lines_may_be_exactly_the_width() {
+    assert_eq!(wrap_text("abcde fghij", 5), lines(&["abcde", "fghij"]));
+    assert_eq!(wrap_text("ab de fghij", 5), lines(&["ab de", "fghij"]));
+    // One more character and the second word moves down
+    assert_eq!(wrap_text("ab def", 5), lines(&["ab", "def"]));
+}
+
+#[test]
+fn long_words_are_split_across_lines() {
+    assert_eq!(wrap_text("Embarcadero", 5), lines(&["Embar", "cader", "o"]));
+    // The split starts on a line of its own, and what is left can be joined
+    assert_eq!(wrap_text("to Embarcadero st", 5), lines(&["to", "Embar", "cader", "o st"]));
+    assert_eq!(wrap_text("abcdefghij", 5), lines(&["abcde", "fghij"]));
+}
+
+#[test]
+fn widths_are_counted_in_characters() {
+    assert_eq!(wrap_text("café crème", 5), lines(&["café", "crème"]));
+    assert_eq!(wrap_text("ab", 0), lines(&["a", "b"]));
+}
+
+#[test]
+fn overflowing_lines_are_shown_a_screenful_at_a_time() {
+    let wrapped = lines(&["one", "two", "three", "four", "five"]);
+    let started_pages_ago = |pages: u32| Instant::now() - PAGE_DURATION * pages;
+
+    assert_eq!(visible_lines(&wrapped, 2, Instant::now()), &wrapped[0..2]);
+    assert_eq!(visible_lines(&wrapped, 2, started_pages_ago(1)), &wrapped[2..4]);
+    // The last screenful is partly empty, then it starts over
+    assert_eq!(visible_lines(&wrapped, 2, started_pages_ago(2)), &wrapped[4..5]);
+    assert_eq!(visible_lines(&wrapped, 2, started_pages_ago(3)), &wrapped[0..2]);
+}
+
+#[test]
+fn lines_that_fit_stay_put() {
+    let wrapped = lines(&["one", "two"]);
+    let long_ago = Instant::now() - PAGE_DURATION * 7;
+    assert_eq!(visible_lines(&wrapped, 2, long_ago), &wrapped[..]);
+    assert_eq!(visible_lines(&wrapped, 0, Instant::now()), &wrapped[0..1]);
+    assert!(visible_lines(&[], 2, long_ago).is_empty());
+}
@@ -1,12 +1,14 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use crate::transit::{TransitIdentifier, TransitState};
+use crate::transit::{PredictionFilter, TransitIdentifier, TransitState};
 
 use embedded_graphics_core::draw_target::DrawTarget;
 use embedded_graphics::pixelcolor::Rgb888;
 
-#[cfg(target_os = "macos")]
-use embedded_graphics_simulator::Window;
+use embedded_graphics::geometry::Size;
+
+use crate::config::PanelConfig;
 
 #[cfg(target_os = "linux")]
 use rpi_led_matrix::{
@@ -20,9 +22,11 @@ use rpi_led_matrix::{
 pub enum StateEvent {
     NextPage,
     NextSubpage,
+    PreviousSubpage,
     Reset,
     Quit,
-    TransitUpdate,
+    // Identifiers whose state changed in the latest refresh
+    TransitUpdate(Vec<TransitIdentifier>),
     CustomMessage(String),
     DisplayRefresh,
 }
@@ -31,9 +35,12 @@ pub enum StateEvent {
 pub enum DisplayMode {
     // Normal mode showing transit state
     Transit {
-        transit_identifier: TransitIdentifier,
+        transit_identifiers: Vec<TransitIdentifier>, // Several when stops are combined
+        filter: Box<PredictionFilter>, // The subpage's filter, applied when drawing
         transit_state: Option<TransitState>, // Optional because we might not have data yet
         last_update: Instant,
+        fetched_at: Option<DateTime<Utc>>, // When the provider last returned data for this identifier
+        last_error: Option<String>,
     },
     // Showing a custom message
     CustomMessage {
@@ -55,13 +62,20 @@ pub enum PageDisplayHandler {
 }
 
 impl PageDisplayHandler {
-    pub fn from_key(key: &str) -> Self {
+    pub const KEYS: [&'static str; 2] = ["predictions", "bike_inventory"];
+
+    pub fn parse(key: &str) -> Option<Self> {
         match key {
-            "predictions" => Self::PredictionsDisplay,
-            "bike_inventory" => Self::BikeInventoryDisplay,
-            _ => panic!("Invalid page display handler key: {}", key),
+            "predictions" => Some(Self::PredictionsDisplay),
+            "bike_inventory" => Some(Self::BikeInventoryDisplay),
+            _ => None,
         }
     }
+
+    // Keys are checked when the configuration is loaded
+    pub fn from_key(key: &str) -> Self {
+        Self::parse(key).unwrap_or_else(|| panic!("Invalid page display handler key: {}", key))
+    }
 }
 
 pub trait DisplayContext {
@@ -72,6 +86,9 @@ pub trait DisplayContext {
 
     // Creates a new display target
     fn target(&mut self) -> Self::Display;
+
+    // Panel brightness in percent, applied from the next frame; 0 is blank
+    fn set_brightness(&mut self, brightness: u8);
 }
 
 // A wrapper type that holds both the display context and its drawable target
@@ -79,11 +96,22 @@ pub struct Display<C: DisplayContext> {
     context: C,
     target: C::Display,
     pub y_offset: i32,
+    // Reference point for time-based animation such as marquee scrolling
+    animation_epoch: Instant,
 }
 
 impl<C: DisplayContext> Display<C> {
     pub fn new(context: C, target: C::Display, y_offset: i32) -> Self {
-        Self { context, target, y_offset }
+        Self { context, target, y_offset, animation_epoch: Instant::now() }
+    }
+
+    pub fn animation_time(&self) -> Duration {
+        self.animation_epoch.elapsed()
+    }
+
+    // Starts animations over, so that a newly shown page scrolls from its start
+    pub fn restart_animation(&mut self) {
+        self.animation_epoch = Instant::now();
     }
 
     pub fn context_mut(&mut self) -> &mut C {
@@ -94,6 +122,10 @@ impl<C: DisplayContext> Display<C> {
         &mut self.target
     }
 
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.context.set_brightness(brightness);
+    }
+
     // Add a method that handles the swap internally
     pub fn show_display(&mut self) -> impl Iterator<Item = StateEvent> + '_ {
         let target = std::mem::replace(&mut self.target, self.context.target());
@@ -109,33 +141,50 @@ mod sim;
 
 // Factory function to create the appropriate display implementation
 #[cfg(target_os = "macos")]
-pub fn get_display() -> Display<Window> {
-    let context = sim::setup_container();
-    let target = sim::setup_drawable();
-    Display::new(context, target, 7)
+pub fn get_display(panel: &PanelConfig) -> Display<sim::SimulatorContext> {
+    let size = Size::new(panel.width(), panel.height());
+    let mut context = sim::SimulatorContext::new(size, panel.brightness);
+    let target = context.target();
+    Display::new(context, target, panel.y_offset)
+}
+
+// Headless in-memory framebuffer, available on every platform
+pub mod framebuffer;
+
+pub fn get_framebuffer_display(
+    panel: &PanelConfig,
+    dump: Option<framebuffer::FrameDump>,
+) -> Display<framebuffer::FramebufferContext> {
+    let size = Size::new(panel.width(), panel.height());
+    let context = framebuffer::FramebufferContext::new(size, panel.brightness, dump);
+    let target = framebuffer::Framebuffer::new(size);
+    Display::new(context, target, panel.y_offset)
 }
 
 #[cfg(target_os = "linux")]
 mod hardware;
 
 #[cfg(target_os = "linux")]
-pub fn get_display() -> Display<LedMatrix> {
+pub fn get_display(panel: &PanelConfig) -> Display<hardware::HardwareContext> {
     let mut options = LedMatrixOptions::new();
-    options.set_rows(16);
-    options.set_cols(32);
-    options.set_brightness(35);
-    options.set_chain_length(3);
-    options.set_hardware_mapping("adafruit-hat");
+    options.set_rows(panel.rows);
+    options.set_cols(panel.cols);
+    // Brightness is applied in software so schedules can change it at runtime
+    options.set_brightness(100).unwrap();
+    options.set_chain_length(panel.chain_length);
+    options.set_parallel(panel.parallel);
+    options.set_hardware_mapping(&panel.hardware_mapping);
 
     let mut rt_options = LedRuntimeOptions::new();
-    rt_options.set_gpio_slowdown(5);
+    rt_options.set_gpio_slowdown(panel.gpio_slowdown);
     let matrix = LedMatrix::new(Some(options), Some(rt_options)).unwrap();
-    let mut canvas = matrix.canvas();
+    let context = hardware::HardwareContext::new(matrix, panel.brightness);
+    let target = context.canvas();
 
-    Display::new(matrix, canvas, 7)
+    Display::new(context, target, panel.y_offset)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Color {
     pub red: u8,
     pub green: u8,
@@ -153,6 +202,16 @@ impl Color {
             _ => None
         }
     }
+
+    // Parses a hex color such as GTFS `route_color` ("FF0000", with or without '#')
+    pub fn from_hex(s: &str) -> Option<Self> {
+        let hex = s.trim().trim_start_matches('#');
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        Some(Self { red: channel(0)?, green: channel(2)?, blue: channel(4)? })
+    }
 }
 
 impl Default for Color {
@@ -161,4 +220,19 @@ impl Default for Color {
     }
 }
 
+pub mod dimming;
 pub mod fsm;
+pub mod layout;
+pub mod message;
+pub mod schedule;
+
+#[cfg(test)]
+mod framebuffer_tests;
+#[cfg(test)]
+mod fsm_tests;
+#[cfg(test)]
+mod layout_tests;
+#[cfg(test)]
+mod message_tests;
+#[cfg(test)]
+mod schedule_tests;
@@ -0,0 +1,195 @@
+use std::f64::consts::PI;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use serde::{de, Deserialize, Deserializer};
+
+// Where the sign is, for schedules that start or end at sunrise or sunset
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+// A clock time ("07:30") or the local sunrise or sunset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeOfDay {
+    At(NaiveTime),
+    Sunrise,
+    Sunset,
+}
+
+impl TimeOfDay {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.trim() {
+            "sunrise" => Some(Self::Sunrise),
+            "sunset" => Some(Self::Sunset),
+            "24:00" => Some(Self::At(NaiveTime::MIN)),
+            time => NaiveTime::parse_from_str(time, "%H:%M").ok().map(Self::At),
+        }
+    }
+
+    pub fn is_solar(&self) -> bool {
+        matches!(self, Self::Sunrise | Self::Sunset)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).ok_or_else(|| de::Error::custom(format!(
+            "invalid time of day \"{}\", expected HH:MM, \"sunrise\" or \"sunset\"", s
+        )))
+    }
+}
+
+// Days a window applies to: "daily", "weekdays", "weekends" or a list such as ["sat", "sun"]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Days(Vec<Weekday>);
+
+impl Days {
+    pub fn daily() -> Self {
+        Self(vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun])
+    }
+
+    pub fn contains(&self, day: Weekday) -> bool {
+        self.0.contains(&day)
+    }
+}
+
+impl Default for Days {
+    fn default() -> Self {
+        Self::daily()
+    }
+}
+
+impl<'de> Deserialize<'de> for Days {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Alias(String),
+            List(Vec<String>),
+        }
+
+        let parse_day = |s: &str| s.parse::<Weekday>().map_err(|_| de::Error::custom(format!("invalid day \"{}\"", s)));
+        match Raw::deserialize(deserializer)? {
+            Raw::Alias(alias) => match alias.as_str() {
+                "daily" => Ok(Self::daily()),
+                "weekdays" => Ok(Self(vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri])),
+                "weekends" => Ok(Self(vec![Weekday::Sat, Weekday::Sun])),
+                day => Ok(Self(vec![parse_day(day)?])),
+            },
+            Raw::List(days) => days.iter().map(|day| parse_day(day)).collect::<Result<_, _>>().map(Self),
+        }
+    }
+}
+
+/// A recurring span of time, e.g. 07:00 to 10:00 on weekdays. A window whose
+/// end is before its start runs overnight; its days are the days it starts on.
+/// One that ends when it starts covers the whole day.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct ScheduleWindow {
+    #[serde(default)]
+    pub days: Days,
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+}
+
+impl ScheduleWindow {
+    pub fn is_solar(&self) -> bool {
+        self.start.is_solar() || self.end.is_solar()
+    }
+}
+
+// Panel brightness (0-100) while a window is active; 0 blanks the panel
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct BrightnessWindow {
+    #[serde(flatten)]
+    pub window: ScheduleWindow,
+    pub brightness: u8,
+}
+
+/// Evaluates schedule windows against the local time. Sunrise and sunset are
+/// worked out for each day from the configured location.
+#[derive(Debug, Clone, Copy)]
+pub struct Schedule {
+    location: Option<Location>,
+}
+
+impl Schedule {
+    pub fn new(location: Option<Location>) -> Self {
+        Self { location }
+    }
+
+    // True if any window is active; an empty schedule is always active
+    pub fn is_active<Tz: TimeZone>(&self, windows: &[ScheduleWindow], now: &DateTime<Tz>) -> bool {
+        windows.is_empty() || windows.iter().any(|window| self.window_active(window, now))
+    }
+
+    // Brightness of the first active window, or `default` outside all of them
+    pub fn brightness<Tz: TimeZone>(&self, windows: &[BrightnessWindow], default: u8, now: &DateTime<Tz>) -> u8 {
+        windows
+            .iter()
+            .find(|entry| self.window_active(&entry.window, now))
+            .map_or(default, |entry| entry.brightness)
+    }
+
+    fn window_active<Tz: TimeZone>(&self, window: &ScheduleWindow, now: &DateTime<Tz>) -> bool {
+        let local = now.naive_local();
+        let (today, time) = (local.date(), local.time());
+        let resolve = |time_of_day: TimeOfDay, date: NaiveDate| self.resolve(time_of_day, date, &now.timezone());
+
+        let (Some(start), Some(end)) = (resolve(window.start, today), resolve(window.end, today)) else {
+            // No sunrise or sunset today (or no location to work it out from)
+            return false;
+        };
+
+        if start == end {
+            window.days.contains(today.weekday())
+        } else if start < end {
+            window.days.contains(today.weekday()) && start <= time && time < end
+        } else {
+            // Overnight: the evening part belongs to today, the early hours to yesterday
+            let yesterday = today.pred_opt().unwrap_or(today);
+            (window.days.contains(today.weekday()) && time >= start)
+                || (window.days.contains(yesterday.weekday()) && time < end)
+        }
+    }
+
+    fn resolve<Tz: TimeZone>(&self, time_of_day: TimeOfDay, date: NaiveDate, tz: &Tz) -> Option<NaiveTime> {
+        let rising = match time_of_day {
+            TimeOfDay::At(time) => return Some(time),
+            TimeOfDay::Sunrise => true,
+            TimeOfDay::Sunset => false,
+        };
+        let location = self.location?;
+        let event = sun_event(date, location, rising)?;
+        Some(event.with_timezone(tz).naive_local().time())
+    }
+}
+
+// Sunrise or sunset on `date` using NOAA's approximate solar equations, which
+// are good to a minute or two. None during polar day or night.
+pub fn sun_event(date: NaiveDate, location: Location, rising: bool) -> Option<DateTime<Utc>> {
+    let gamma = 2.0 * PI / 365.0 * (date.ordinal0() as f64);
+    let equation_of_time = 229.18 * (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin()
+        - 0.014615 * (2.0 * gamma).cos() - 0.040849 * (2.0 * gamma).sin());
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos() + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos() + 0.00148 * (3.0 * gamma).sin();
+
+    // The sun's centre is 0.833 degrees below the horizon at sunrise and sunset,
+    // allowing for refraction and the size of its disc
+    let latitude = location.latitude.to_radians();
+    let cos_hour_angle = 90.833f64.to_radians().cos() / (latitude.cos() * declination.cos())
+        - latitude.tan() * declination.tan();
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+    let hour_angle = if rising { hour_angle } else { -hour_angle };
+
+    let minutes = 720.0 - 4.0 * (location.longitude + hour_angle) - equation_of_time;
+    let midnight = date.and_time(NaiveTime::MIN).and_utc();
+    Some(midnight + Duration::seconds((minutes * 60.0).round() as i64))
+}
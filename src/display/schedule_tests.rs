@@ -0,0 +1,117 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeZone, Timelike};
+
+use super::schedule::{sun_event, BrightnessWindow, Location, Schedule, ScheduleWindow};
+
+const SAN_FRANCISCO: Location = Location { latitude: 37.7749, longitude: -122.4194 };
+
+// Pacific daylight time; 2024-06-17 is a Monday
+fn pdt(day: u32, hour: u32, minute: u32) -> DateTime<FixedOffset> {
+    FixedOffset::west_opt(7 * 3600).unwrap().with_ymd_and_hms(2024, 6, day, hour, minute, 0).unwrap()
+}
+
+fn window(toml: &str) -> ScheduleWindow {
+    toml::from_str(toml).unwrap()
+}
+
+#[test]
+fn weekday_morning_window() {
+    let schedule = Schedule::new(None);
+    let morning = [window(r#"days = "weekdays"
+start = "07:00"
+end = "10:00""#)];
+
+    assert!(schedule.is_active(&morning, &pdt(17, 7, 0)));
+    assert!(schedule.is_active(&morning, &pdt(21, 9, 59)));
+    assert!(!schedule.is_active(&morning, &pdt(17, 10, 0)));
+    assert!(!schedule.is_active(&morning, &pdt(17, 6, 59)));
+    // Saturday
+    assert!(!schedule.is_active(&morning, &pdt(22, 8, 0)));
+}
+
+#[test]
+fn empty_schedule_is_always_active() {
+    assert!(Schedule::new(None).is_active(&[], &pdt(17, 3, 0)));
+}
+
+#[test]
+fn day_lists_and_aliases() {
+    let schedule = Schedule::new(None);
+    let weekends = [window(r#"days = "weekends"
+start = "00:00"
+end = "24:00""#)];
+    let listed = [window(r#"days = ["mon", "Wednesday"]
+start = "00:00"
+end = "24:00""#)];
+
+    assert!(schedule.is_active(&weekends, &pdt(22, 12, 0)));
+    assert!(schedule.is_active(&weekends, &pdt(23, 23, 59)));
+    assert!(!schedule.is_active(&weekends, &pdt(21, 12, 0)));
+    assert!(schedule.is_active(&listed, &pdt(19, 12, 0)));
+    assert!(!schedule.is_active(&listed, &pdt(18, 12, 0)));
+    assert!(toml::from_str::<ScheduleWindow>(r#"days = ["someday"]
+start = "00:00"
+end = "01:00""#).is_err());
+}
+
+#[test]
+fn overnight_window_belongs_to_the_day_it_starts() {
+    let schedule = Schedule::new(None);
+    // Friday and Saturday nights only
+    let late = [window(r#"days = ["fri", "sat"]
+start = "23:00"
+end = "02:00""#)];
+
+    assert!(schedule.is_active(&late, &pdt(21, 23, 30)));
+    assert!(schedule.is_active(&late, &pdt(22, 1, 30)));
+    assert!(schedule.is_active(&late, &pdt(23, 1, 30)));
+    // Early Friday morning follows Thursday night
+    assert!(!schedule.is_active(&late, &pdt(21, 1, 30)));
+    assert!(!schedule.is_active(&late, &pdt(22, 2, 0)));
+}
+
+#[test]
+fn sunset_in_san_francisco_at_midsummer() {
+    let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+    let sunrise = sun_event(date, SAN_FRANCISCO, true).unwrap().with_timezone(&FixedOffset::west_opt(7 * 3600).unwrap());
+    let sunset = sun_event(date, SAN_FRANCISCO, false).unwrap().with_timezone(&FixedOffset::west_opt(7 * 3600).unwrap());
+
+    // Published times are 05:48 and 20:35
+    let minutes = |time: DateTime<FixedOffset>| time.hour() * 60 + time.minute();
+    assert!(minutes(sunrise).abs_diff(5 * 60 + 48) <= 2, "sunrise {}", sunrise);
+    assert!(minutes(sunset).abs_diff(20 * 60 + 35) <= 2, "sunset {}", sunset);
+}
+
+#[test]
+fn no_sunset_during_polar_day() {
+    let tromso = Location { latitude: 69.65, longitude: 18.96 };
+    assert_eq!(sun_event(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), tromso, false), None);
+}
+
+#[test]
+fn brightness_follows_the_first_active_entry() {
+    let entries: Vec<BrightnessWindow> = toml::from_str::<toml::Table>(r#"
+        [[brightness_schedule]]
+        start = "01:00"
+        end = "06:00"
+        brightness = 0
+
+        [[brightness_schedule]]
+        start = "sunset"
+        end = "sunrise"
+        brightness = 10
+    "#).unwrap()["brightness_schedule"].clone().try_into().unwrap();
+    let schedule = Schedule::new(Some(SAN_FRANCISCO));
+
+    assert_eq!(schedule.brightness(&entries, 35, &pdt(17, 12, 0)), 35);
+    assert_eq!(schedule.brightness(&entries, 35, &pdt(17, 21, 0)), 10);
+    assert_eq!(schedule.brightness(&entries, 35, &pdt(18, 0, 30)), 10);
+    assert_eq!(schedule.brightness(&entries, 35, &pdt(18, 3, 0)), 0);
+}
+
+#[test]
+fn solar_windows_need_a_location() {
+    let evening = [window(r#"start = "sunset"
+end = "23:00""#)];
+    assert!(!Schedule::new(None).is_active(&evening, &pdt(17, 22, 0)));
+    assert!(Schedule::new(Some(SAN_FRANCISCO)).is_active(&evening, &pdt(17, 22, 0)));
+}
@@ -12,14 +12,35 @@ use embedded_graphics_core::{
 
 use crate::display::StateEvent;
 
+use super::dimming::{relative_level, Dimmed};
 use super::DisplayContext;
 
-impl DisplayContext for Window {
-    type Display = SimulatorDisplay<Rgb888>;
+// Simulator window along with the panel size used for each new drawable.
+// The window shows the configured panel brightness at full intensity.
+pub struct SimulatorContext {
+    window: Window,
+    size: Size,
+    full_brightness: u8,
+    brightness: u8,
+}
+
+impl SimulatorContext {
+    pub fn new(size: Size, brightness: u8) -> Self {
+        Self { window: setup_container(), size, full_brightness: brightness, brightness }
+    }
+
+    fn level(&self) -> u8 {
+        relative_level(self.brightness, self.full_brightness)
+    }
+}
+
+impl DisplayContext for SimulatorContext {
+    type Display = Dimmed<SimulatorDisplay<Rgb888>>;
 
     fn show_display(&mut self, display: Self::Display) -> (Self::Display, impl Iterator<Item = StateEvent>) {
-        self.update(&display);
-        let events = self.events().filter_map(|event| {
+        let display = display.into_inner();
+        self.window.update(&display);
+        let events = self.window.events().filter_map(|event| {
             match event {
                 SimulatorEvent::Quit => Some(StateEvent::Quit),
                 SimulatorEvent::KeyDown { keycode, keymod: _, repeat: _ } => {
@@ -34,11 +55,15 @@ impl DisplayContext for Window {
                 _ => None,
             }
         }).collect::<Vec<_>>();
-        (display, events.into_iter())
+        (Dimmed::new(display, self.level()), events.into_iter())
     }
 
     fn target(&mut self) -> Self::Display {
-        setup_drawable()
+        Dimmed::new(setup_drawable(self.size), self.level())
+    }
+
+    fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
     }
 }
 
@@ -50,6 +75,6 @@ pub fn setup_container() -> Window {
     Window::new("Transit Sign", &output_settings)
 }
 
-pub fn setup_drawable() -> SimulatorDisplay<Rgb888> {
-    SimulatorDisplay::new(Size::new(96, 16))
-}
\ No newline at end of file
+pub fn setup_drawable(size: Size) -> SimulatorDisplay<Rgb888> {
+    SimulatorDisplay::new(size)
+}
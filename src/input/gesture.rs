@@ -0,0 +1,116 @@
+use std::time::{Duration, Instant};
+
+use super::InputEvent;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    Pressed,
+    Released,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GestureConfig {
+    // Edges closer than this to the previous accepted edge are contact bounce
+    pub debounce: Duration,
+    // How long after a short press a second press still counts as a double press
+    pub double_press_window: Duration,
+    // Holding at least this long is a long press, reported without waiting for release
+    pub long_press: Duration,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            debounce: Duration::from_millis(20),
+            double_press_window: Duration::from_millis(300),
+            long_press: Duration::from_millis(800),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    // Button down, not yet classified
+    Pressed { since: Instant },
+    // Short press finished; waiting to see whether a second one follows
+    Released { at: Instant },
+    // Gesture already reported; ignore everything until the button comes up
+    AwaitingRelease,
+}
+
+/// Turns debounced button edges into single, double and long presses. Time is
+/// passed in rather than read from the clock, so the classification can be
+/// exercised with synthetic timestamps independently of any hardware.
+///
+/// Call `edge` for every edge and `poll` once `deadline` has passed; either may
+/// complete a gesture.
+#[derive(Debug)]
+pub struct GestureDetector {
+    config: GestureConfig,
+    state: State,
+    last_edge: Option<Instant>,
+}
+
+impl GestureDetector {
+    pub fn new(config: GestureConfig) -> Self {
+        Self { config, state: State::Idle, last_edge: None }
+    }
+
+    pub fn edge(&mut self, edge: Edge, at: Instant) -> Option<InputEvent> {
+        if self.last_edge.is_some_and(|last| at.saturating_duration_since(last) < self.config.debounce) {
+            return None;
+        }
+
+        let (state, event) = match (self.state, edge) {
+            (State::Idle, Edge::Pressed) => (State::Pressed { since: at }, None),
+            (State::Pressed { since }, Edge::Released) => {
+                if at.saturating_duration_since(since) >= self.config.long_press {
+                    // Released before poll noticed the threshold had passed
+                    (State::Idle, Some(InputEvent::LongPress))
+                } else {
+                    (State::Released { at }, None)
+                }
+            }
+            (State::Released { at: released }, Edge::Pressed) => {
+                if at.saturating_duration_since(released) <= self.config.double_press_window {
+                    (State::AwaitingRelease, Some(InputEvent::DoublePress))
+                } else {
+                    // The window ran out before poll noticed, so the first press
+                    // was a single press and this one starts a new gesture
+                    (State::Pressed { since: at }, Some(InputEvent::SinglePress))
+                }
+            }
+            (State::AwaitingRelease, Edge::Released) => (State::Idle, None),
+            // Repeated edges in the same direction mean we missed one; keep the current state
+            _ => return None,
+        };
+
+        self.last_edge = Some(at);
+        self.state = state;
+        event
+    }
+
+    pub fn poll(&mut self, now: Instant) -> Option<InputEvent> {
+        match self.state {
+            State::Pressed { since } if now.saturating_duration_since(since) >= self.config.long_press => {
+                self.state = State::AwaitingRelease;
+                Some(InputEvent::LongPress)
+            }
+            State::Released { at } if now.saturating_duration_since(at) > self.config.double_press_window => {
+                self.state = State::Idle;
+                Some(InputEvent::SinglePress)
+            }
+            _ => None,
+        }
+    }
+
+    // When poll next needs to run, if a gesture is waiting on a timeout
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.state {
+            State::Pressed { since } => Some(since + self.config.long_press),
+            State::Released { at } => Some(at + self.config.double_press_window + Duration::from_millis(1)),
+            _ => None,
+        }
+    }
+}
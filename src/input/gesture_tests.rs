@@ -0,0 +1,112 @@
+// Gesture classification driven by synthetic edge timestamps, as offsets in
+// milliseconds from an arbitrary start, using the default timings: 20ms
+// debounce, 300ms double-press window and 800ms long press.
+
+use std::time::{Duration, Instant};
+
+use super::gesture::{Edge, GestureConfig, GestureDetector};
+use super::InputEvent;
+
+struct Harness {
+    start: Instant,
+    detector: GestureDetector,
+}
+
+impl Harness {
+    fn new() -> Self {
+        Self { start: Instant::now(), detector: GestureDetector::new(GestureConfig::default()) }
+    }
+
+    fn at(&self, ms: u64) -> Instant {
+        self.start + Duration::from_millis(ms)
+    }
+
+    fn edge(&mut self, edge: Edge, ms: u64) -> Option<InputEvent> {
+        let at = self.at(ms);
+        self.detector.edge(edge, at)
+    }
+
+    fn poll(&mut self, ms: u64) -> Option<InputEvent> {
+        let now = self.at(ms);
+        self.detector.poll(now)
+    }
+
+    fn deadline_ms(&self) -> Option<u64> {
+        self.detector.deadline().map(|deadline| (deadline - self.start).as_millis() as u64)
+    }
+}
+
+#[test]
+fn short_press_is_single_once_double_window_passes() {
+    let mut h = Harness::new();
+    assert_eq!(h.edge(Edge::Pressed, 0), None);
+    assert_eq!(h.edge(Edge::Released, 100), None);
+    assert_eq!(h.poll(350), None);
+    assert_eq!(h.deadline_ms(), Some(401));
+    assert_eq!(h.poll(401), Some(InputEvent::SinglePress));
+    assert_eq!(h.deadline_ms(), None);
+}
+
+#[test]
+fn second_press_within_window_is_double() {
+    let mut h = Harness::new();
+    h.edge(Edge::Pressed, 0);
+    h.edge(Edge::Released, 100);
+    assert_eq!(h.edge(Edge::Pressed, 250), Some(InputEvent::DoublePress));
+    assert_eq!(h.edge(Edge::Released, 350), None);
+    assert_eq!(h.poll(2000), None);
+}
+
+#[test]
+fn holding_past_threshold_is_long_without_release() {
+    let mut h = Harness::new();
+    h.edge(Edge::Pressed, 0);
+    assert_eq!(h.deadline_ms(), Some(800));
+    assert_eq!(h.poll(799), None);
+    assert_eq!(h.poll(800), Some(InputEvent::LongPress));
+    // Releasing afterwards doesn't start another gesture
+    assert_eq!(h.edge(Edge::Released, 1500), None);
+    assert_eq!(h.poll(3000), None);
+}
+
+#[test]
+fn late_release_after_missed_poll_is_still_long() {
+    let mut h = Harness::new();
+    h.edge(Edge::Pressed, 0);
+    assert_eq!(h.edge(Edge::Released, 1200), Some(InputEvent::LongPress));
+    assert_eq!(h.poll(3000), None);
+}
+
+#[test]
+fn press_after_missed_poll_reports_the_earlier_single() {
+    let mut h = Harness::new();
+    h.edge(Edge::Pressed, 0);
+    h.edge(Edge::Released, 100);
+    assert_eq!(h.edge(Edge::Pressed, 900), Some(InputEvent::SinglePress));
+    assert_eq!(h.edge(Edge::Released, 1000), None);
+    assert_eq!(h.poll(1301), Some(InputEvent::SinglePress));
+}
+
+#[test]
+fn contact_bounce_is_ignored() {
+    let mut h = Harness::new();
+    // A noisy press and release, each chattering for a few milliseconds
+    for (edge, ms) in [
+        (Edge::Pressed, 0), (Edge::Released, 2), (Edge::Pressed, 5), (Edge::Released, 9), (Edge::Pressed, 12),
+        (Edge::Released, 120), (Edge::Pressed, 123), (Edge::Released, 127),
+    ] {
+        assert_eq!(h.edge(edge, ms), None);
+    }
+    assert_eq!(h.poll(421), Some(InputEvent::SinglePress));
+}
+
+#[test]
+fn repeated_edges_in_same_direction_are_ignored() {
+    let mut h = Harness::new();
+    h.edge(Edge::Released, 0);
+    assert_eq!(h.deadline_ms(), None);
+    h.edge(Edge::Pressed, 100);
+    h.edge(Edge::Pressed, 200);
+    // The long press is still timed from the first press
+    assert_eq!(h.poll(900), Some(InputEvent::LongPress));
+}
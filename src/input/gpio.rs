@@ -0,0 +1,148 @@
+use std::error::Error;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use gpio_cdev::{AsyncLineEventHandle, Chip, EventRequestFlags, EventType, LineRequestFlags};
+use log::debug;
+
+use crate::config::{GpioButtonConfig, RotaryEncoderConfig};
+use super::gesture::{Edge, GestureDetector};
+use super::quadrature::QuadratureDecoder;
+use super::{InputEvent, InputHandler};
+
+// Requests edge events in both directions for one line
+fn open_line_events(chip: &str, line: u32) -> Result<AsyncLineEventHandle, Box<dyn Error + Send>> {
+    let open = || -> Result<AsyncLineEventHandle, gpio_cdev::Error> {
+        let mut chip = Chip::new(chip)?;
+        let line = chip.get_line(line)?;
+        let handle = line.events(LineRequestFlags::INPUT, EventRequestFlags::BOTH_EDGES, "transit-sign")?;
+        AsyncLineEventHandle::new(handle)
+    };
+    open().map_err(|e| Box::new(e) as Box<dyn Error + Send>)
+}
+
+fn stream_closed() -> Box<dyn Error + Send> {
+    Box::new(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "GPIO event stream closed"))
+}
+
+/// Push button read through the Linux GPIO character device. Both edges are
+/// requested so presses and releases can be timed by the gesture detector.
+pub struct GpioButton {
+    events: AsyncLineEventHandle,
+    detector: GestureDetector,
+    active_low: bool,
+}
+
+impl GpioButton {
+    pub fn new(config: &GpioButtonConfig) -> Result<Self, Box<dyn Error + Send>> {
+        Ok(Self {
+            events: open_line_events(&config.chip, config.line)?,
+            detector: GestureDetector::new(config.gesture_config()),
+            active_low: config.active_low,
+        })
+    }
+
+    fn edge(&self, event_type: EventType) -> Edge {
+        // An active-low button pulls the line to ground while held
+        match (event_type, self.active_low) {
+            (EventType::FallingEdge, true) | (EventType::RisingEdge, false) => Edge::Pressed,
+            _ => Edge::Released,
+        }
+    }
+}
+
+#[async_trait]
+impl InputHandler for GpioButton {
+    async fn listen(&mut self) -> Result<InputEvent, Box<dyn Error + Send>> {
+        loop {
+            // Wake up for the next edge, or when a pending gesture times out
+            let event = match self.detector.deadline() {
+                Some(deadline) => tokio::select! {
+                    event = self.events.next() => Some(event),
+                    _ = tokio::time::sleep_until(deadline.into()) => None,
+                },
+                None => Some(self.events.next().await),
+            };
+
+            // Edges are timed on arrival; the few microseconds of delivery latency
+            // are far below the debounce and gesture thresholds
+            let gesture = match event {
+                None => self.detector.poll(Instant::now()),
+                Some(Some(Ok(event))) => {
+                    let edge = self.edge(event.event_type());
+                    debug!("GPIO button edge: {:?}", edge);
+                    self.detector.edge(edge, Instant::now())
+                }
+                Some(Some(Err(e))) => return Err(Box::new(e)),
+                Some(None) => return Err(stream_closed()),
+            };
+
+            if let Some(gesture) = gesture {
+                return Ok(gesture);
+            }
+        }
+    }
+
+    async fn cleanup(&mut self) -> Result<(), Box<dyn Error + Send>> {
+        // The line is released when the event handle is dropped
+        Ok(())
+    }
+}
+
+/// Quadrature rotary encoder on two GPIO lines, reporting a turn per detent.
+pub struct RotaryEncoder {
+    a_events: AsyncLineEventHandle,
+    b_events: AsyncLineEventHandle,
+    a: bool,
+    b: bool,
+    decoder: QuadratureDecoder,
+}
+
+impl RotaryEncoder {
+    pub fn new(config: &RotaryEncoderConfig) -> Result<Self, Box<dyn Error + Send>> {
+        let a_events = open_line_events(&config.chip, config.a_line)?;
+        let b_events = open_line_events(&config.chip, config.b_line)?;
+        let level = |events: &AsyncLineEventHandle| events.as_ref()
+            .get_value()
+            .map(|value| value != 0)
+            .map_err(|e| Box::new(e) as Box<dyn Error + Send>);
+        let (a, b) = (level(&a_events)?, level(&b_events)?);
+
+        Ok(Self {
+            a_events,
+            b_events,
+            a,
+            b,
+            decoder: QuadratureDecoder::new(a, b, config.steps_per_detent),
+        })
+    }
+}
+
+#[async_trait]
+impl InputHandler for RotaryEncoder {
+    async fn listen(&mut self) -> Result<InputEvent, Box<dyn Error + Send>> {
+        loop {
+            // Each edge gives the new level of the contact that moved
+            tokio::select! {
+                event = self.a_events.next() => {
+                    let event = event.ok_or_else(stream_closed)?.map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+                    self.a = event.event_type() == EventType::RisingEdge;
+                }
+                event = self.b_events.next() => {
+                    let event = event.ok_or_else(stream_closed)?.map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+                    self.b = event.event_type() == EventType::RisingEdge;
+                }
+            }
+
+            if let Some(turn) = self.decoder.update(self.a, self.b) {
+                return Ok(turn);
+            }
+        }
+    }
+
+    async fn cleanup(&mut self) -> Result<(), Box<dyn Error + Send>> {
+        // Both lines are released when their event handles are dropped
+        Ok(())
+    }
+}
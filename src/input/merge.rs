@@ -0,0 +1,74 @@
+use log::{debug, warn};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+use super::{InputEvent, InputHandler};
+
+pub type NamedInputHandler = (&'static str, Box<dyn InputHandler + Send>);
+
+/// Runs several input handlers concurrently and merges their events into a
+/// single stream. A handler that fails stops on its own; the rest keep going.
+/// `shutdown` stops every handler and waits for its `cleanup` to finish.
+pub struct MergedInput {
+    events: mpsc::Receiver<InputEvent>,
+    shutdown: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl MergedInput {
+    pub fn spawn(handlers: Vec<NamedInputHandler>) -> Self {
+        let (sender, events) = mpsc::channel(16);
+        let (shutdown, shutdown_receiver) = watch::channel(false);
+
+        let tasks = handlers
+            .into_iter()
+            .map(|(name, handler)| tokio::spawn(run_source(name, handler, sender.clone(), shutdown_receiver.clone())))
+            .collect();
+
+        Self { events, shutdown, tasks }
+    }
+
+    // Next event from any source; None once every source has stopped
+    pub async fn next(&mut self) -> Option<InputEvent> {
+        self.events.recv().await
+    }
+
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(true);
+        for task in self.tasks {
+            if let Err(e) = task.await {
+                warn!("Input task failed: {}", e);
+            }
+        }
+    }
+}
+
+async fn run_source(
+    name: &'static str,
+    mut handler: Box<dyn InputHandler + Send>,
+    events: mpsc::Sender<InputEvent>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    debug!("Listening for {} input", name);
+    loop {
+        let event = tokio::select! {
+            result = handler.listen() => match result {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("{} input stopped: {}", name, e);
+                    break;
+                }
+            },
+            _ = shutdown.changed() => break,
+        };
+
+        debug!("{} input: {:?}", name, event);
+        if events.send(event).await.is_err() {
+            break;
+        }
+    }
+
+    if let Err(e) = handler.cleanup().await {
+        warn!("Failed to clean up {} input: {}", name, e);
+    }
+}
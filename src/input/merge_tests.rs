@@ -0,0 +1,97 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use super::merge::MergedInput;
+use super::{InputEvent, InputHandler};
+
+// Emits its scripted events in order, then waits forever like an idle button
+struct ScriptedInput {
+    events: Vec<InputEvent>,
+    cleaned_up: Arc<AtomicBool>,
+}
+
+impl ScriptedInput {
+    fn new(events: Vec<InputEvent>) -> (Self, Arc<AtomicBool>) {
+        let cleaned_up = Arc::new(AtomicBool::new(false));
+        (Self { events, cleaned_up: cleaned_up.clone() }, cleaned_up)
+    }
+}
+
+#[async_trait]
+impl InputHandler for ScriptedInput {
+    async fn listen(&mut self) -> Result<InputEvent, Box<dyn Error + Send>> {
+        if self.events.is_empty() {
+            std::future::pending::<()>().await;
+        }
+        Ok(self.events.remove(0))
+    }
+
+    async fn cleanup(&mut self) -> Result<(), Box<dyn Error + Send>> {
+        self.cleaned_up.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+// Fails on the first listen, like a keyboard whose stdin is closed
+struct FailingInput {
+    cleaned_up: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl InputHandler for FailingInput {
+    async fn listen(&mut self) -> Result<InputEvent, Box<dyn Error + Send>> {
+        Err(Box::new(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "closed")))
+    }
+
+    async fn cleanup(&mut self) -> Result<(), Box<dyn Error + Send>> {
+        self.cleaned_up.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+async fn next(merged: &mut MergedInput) -> Option<InputEvent> {
+    tokio::time::timeout(Duration::from_secs(1), merged.next()).await.expect("timed out waiting for input")
+}
+
+#[tokio::test]
+async fn events_from_every_source_are_merged() {
+    let (button, _) = ScriptedInput::new(vec![InputEvent::SinglePress, InputEvent::LongPress]);
+    let (encoder, _) = ScriptedInput::new(vec![InputEvent::TurnClockwise]);
+    let mut merged = MergedInput::spawn(vec![("button", Box::new(button)), ("encoder", Box::new(encoder))]);
+
+    let mut events = vec![next(&mut merged).await, next(&mut merged).await, next(&mut merged).await];
+    // Sources interleave arbitrarily, but each keeps its own order
+    let button_events: Vec<_> = events.iter().flatten().filter(|e| **e != InputEvent::TurnClockwise).cloned().collect();
+    assert_eq!(button_events, vec![InputEvent::SinglePress, InputEvent::LongPress]);
+    events.retain(|e| *e == Some(InputEvent::TurnClockwise));
+    assert_eq!(events.len(), 1);
+
+    merged.shutdown().await;
+}
+
+#[tokio::test]
+async fn failing_source_stops_without_affecting_others() {
+    let failed_cleaned_up = Arc::new(AtomicBool::new(false));
+    let failing = FailingInput { cleaned_up: failed_cleaned_up.clone() };
+    let (button, _) = ScriptedInput::new(vec![InputEvent::DoublePress]);
+    let mut merged = MergedInput::spawn(vec![("keyboard", Box::new(failing)), ("button", Box::new(button))]);
+
+    assert_eq!(next(&mut merged).await, Some(InputEvent::DoublePress));
+    merged.shutdown().await;
+    assert!(failed_cleaned_up.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn shutdown_cleans_up_idle_sources() {
+    let (button, button_cleaned_up) = ScriptedInput::new(vec![]);
+    let (encoder, encoder_cleaned_up) = ScriptedInput::new(vec![]);
+    let merged = MergedInput::spawn(vec![("button", Box::new(button)), ("encoder", Box::new(encoder))]);
+
+    tokio::time::timeout(Duration::from_secs(1), merged.shutdown()).await.expect("shutdown hung");
+    assert!(button_cleaned_up.load(Ordering::SeqCst));
+    assert!(encoder_cleaned_up.load(Ordering::SeqCst));
+}
@@ -1,13 +1,31 @@
 use std::error::Error;
 use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
 use async_trait::async_trait;
+use serde::Deserialize;
+
+pub mod gesture;
+#[cfg(target_os = "linux")]
+pub mod gpio;
+pub mod merge;
+pub mod quadrature;
+
+#[cfg(test)]
+mod gesture_tests;
+#[cfg(test)]
+mod merge_tests;
+#[cfg(test)]
+mod quadrature_tests;
 
 // Common event type for all input methods
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub enum InputEvent {
     SinglePress,
     DoublePress,
     LongPress,
+    // One detent of a rotary encoder
+    TurnClockwise,
+    TurnCounterClockwise,
 }
 
 #[async_trait]
@@ -46,4 +64,32 @@ impl InputHandler for KeyboardInput {
     async fn cleanup(&mut self) -> Result<(), Box<dyn Error + Send>> {
         Ok(()) // Nothing to clean up for keyboard
     }
-}
\ No newline at end of file
+}
+
+// Input events posted to the HTTP API's /input endpoint
+pub struct HttpInput {
+    events: mpsc::Receiver<InputEvent>,
+}
+
+impl HttpInput {
+    // Returns the handler and the sender the API feeds it through
+    pub fn new() -> (Self, mpsc::Sender<InputEvent>) {
+        let (sender, events) = mpsc::channel(16);
+        (Self { events }, sender)
+    }
+}
+
+#[async_trait]
+impl InputHandler for HttpInput {
+    async fn listen(&mut self) -> Result<InputEvent, Box<dyn Error + Send>> {
+        self.events.recv().await.ok_or_else(|| Box::new(std::io::Error::new(
+            std::io::ErrorKind::BrokenPipe,
+            "HTTP API stopped",
+        )) as Box<dyn Error + Send>)
+    }
+
+    async fn cleanup(&mut self) -> Result<(), Box<dyn Error + Send>> {
+        self.events.close();
+        Ok(())
+    }
+}
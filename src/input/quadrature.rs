@@ -0,0 +1,45 @@
+use super::InputEvent;
+
+// Step for each (previous << 2 | current) pair of AB states. Transitions that
+// skip a state are noise or missed edges and count as no movement.
+const STEPS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+/// Decodes the two-bit Gray code from a rotary encoder's A and B contacts into
+/// one turn event per detent. Like the gesture detector it only sees levels, so
+/// it can be tested without hardware.
+#[derive(Debug)]
+pub struct QuadratureDecoder {
+    state: u8,
+    steps: i8,
+    steps_per_detent: i8,
+}
+
+impl QuadratureDecoder {
+    pub fn new(a: bool, b: bool, steps_per_detent: u8) -> Self {
+        Self {
+            state: Self::encode(a, b),
+            steps: 0,
+            steps_per_detent: steps_per_detent.clamp(1, 4) as i8,
+        }
+    }
+
+    fn encode(a: bool, b: bool) -> u8 {
+        (a as u8) << 1 | b as u8
+    }
+
+    pub fn update(&mut self, a: bool, b: bool) -> Option<InputEvent> {
+        let next = Self::encode(a, b);
+        self.steps += STEPS[(self.state << 2 | next) as usize];
+        self.state = next;
+
+        if self.steps >= self.steps_per_detent {
+            self.steps = 0;
+            Some(InputEvent::TurnClockwise)
+        } else if self.steps <= -self.steps_per_detent {
+            self.steps = 0;
+            Some(InputEvent::TurnCounterClockwise)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use super::quadrature::QuadratureDecoder;
+use super::InputEvent;
+
+// One full detent of a four-step encoder from rest (both contacts high), B changing first
+const CLOCKWISE: [(bool, bool); 4] = [(false, true), (false, false), (true, false), (true, true)];
+
+fn feed(decoder: &mut QuadratureDecoder, levels: impl IntoIterator<Item = (bool, bool)>) -> Vec<InputEvent> {
+    levels.into_iter().filter_map(|(a, b)| decoder.update(a, b)).collect()
+}
+
+#[test]
+fn full_detent_clockwise_is_one_turn() {
+    let mut decoder = QuadratureDecoder::new(true, true, 4);
+    assert_eq!(feed(&mut decoder, CLOCKWISE), vec![InputEvent::TurnClockwise]);
+    assert_eq!(feed(&mut decoder, CLOCKWISE), vec![InputEvent::TurnClockwise]);
+}
+
+#[test]
+fn reversed_sequence_is_counter_clockwise() {
+    let mut decoder = QuadratureDecoder::new(true, true, 4);
+    let mut levels = CLOCKWISE.to_vec();
+    levels.reverse();
+    levels.remove(0);
+    levels.push((true, true));
+    assert_eq!(feed(&mut decoder, levels), vec![InputEvent::TurnCounterClockwise]);
+}
+
+#[test]
+fn bouncing_contact_does_not_turn() {
+    let mut decoder = QuadratureDecoder::new(true, true, 4);
+    let jitter = [(true, false), (true, true), (true, false), (true, true), (true, false), (true, true)];
+    assert_eq!(feed(&mut decoder, jitter), vec![]);
+}
+
+#[test]
+fn skipped_states_are_ignored() {
+    let mut decoder = QuadratureDecoder::new(true, true, 4);
+    // Both contacts changing at once is ambiguous
+    assert_eq!(decoder.update(false, false), None);
+    assert_eq!(decoder.update(true, true), None);
+    assert_eq!(feed(&mut decoder, CLOCKWISE), vec![InputEvent::TurnClockwise]);
+}
+
+#[test]
+fn half_step_encoders_turn_every_two_steps() {
+    let mut decoder = QuadratureDecoder::new(true, true, 2);
+    assert_eq!(feed(&mut decoder, CLOCKWISE), vec![InputEvent::TurnClockwise, InputEvent::TurnClockwise]);
+}
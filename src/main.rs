@@ -1,15 +1,13 @@
-#![allow(warnings)]
-
 use std::env;
 use std::error::Error;
 use std::time::Duration;
-use log::debug;
+use log::{debug, error, info, warn};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use clap::Parser;
+use tokio::sync::{watch, Notify, RwLock};
 use embedded_graphics::{
     prelude::*,
     pixelcolor::Rgb888,
-    primitives::{Circle, PrimitiveStyle},
 };
 
 use embedded_graphics_core::draw_target::DrawTarget;
@@ -18,19 +16,60 @@ mod display;
 mod transit;
 mod config;
 mod input;
+mod api;
+mod cli;
+mod config_files;
+#[cfg(test)]
+mod config_files_tests;
+mod validate;
+#[cfg(test)]
+mod validate_tests;
 
 use display::{Display, DisplayContext, DisplayMode, StateEvent};
 use display::fsm::DisplayFiniteStateMachine;
+use display::framebuffer::{FrameDump, FrameFormat};
+use config_files::ConfigPaths;
 
 use transit::state::TransitStateManager;
 
-use input::{InputEvent, InputHandler, KeyboardInput};
+use input::{HttpInput, InputEvent, KeyboardInput};
+use input::merge::{MergedInput, NamedInputHandler};
 
 pub type SharedTransitStateManager = Arc<RwLock<TransitStateManager>>;
 pub type SharedDisplayFiniteStateMachine = Arc<RwLock<DisplayFiniteStateMachine>>;
 
-async fn create_input_handler() -> Result<Box<dyn InputHandler + Send>, Box<dyn Error>> {
-    Ok(Box::new(KeyboardInput::new()))
+// Builds every input source enabled in display.toml. A source that can't be
+// opened is reported and left out rather than stopping the sign.
+fn create_input_handlers(input_config: &config::InputConfig, http_input: Option<HttpInput>) -> Vec<NamedInputHandler> {
+    let mut handlers: Vec<NamedInputHandler> = Vec::new();
+    if input_config.keyboard {
+        handlers.push(("keyboard", Box::new(KeyboardInput::new())));
+    }
+    if let Some(http_input) = http_input {
+        handlers.push(("http", Box::new(http_input)));
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(gpio) = &input_config.gpio {
+            match input::gpio::GpioButton::new(gpio) {
+                Ok(button) => handlers.push(("gpio", Box::new(button))),
+                Err(e) => warn!("GPIO button unavailable: {}", e),
+            }
+        }
+        if let Some(rotary) = &input_config.rotary {
+            match input::gpio::RotaryEncoder::new(rotary) {
+                Ok(encoder) => handlers.push(("rotary", Box::new(encoder))),
+                Err(e) => warn!("Rotary encoder unavailable: {}", e),
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    if input_config.gpio.is_some() || input_config.rotary.is_some() {
+        warn!("GPIO input is only supported on Linux");
+    }
+
+    handlers
 }
 
 fn console_display(display_mode: &DisplayMode, page_idx: usize, subpage_idx: usize) {
@@ -45,15 +84,15 @@ fn console_display(display_mode: &DisplayMode, page_idx: usize, subpage_idx: usi
     println!("-----------------------------------------");
 
     match display_mode {
-        DisplayMode::Transit { transit_identifier, transit_state, last_update } => {
+        DisplayMode::Transit { transit_identifiers, transit_state, filter, .. } => {
             if let Some(transit_state) = transit_state {
-                transit_state.console_display(transit_identifier.clone());
+                filter.apply(transit_state, chrono::Utc::now()).console_display(transit_identifiers);
             }
         },
-        DisplayMode::CustomMessage { message, start_time, previous_state } => {
+        DisplayMode::CustomMessage { message, .. } => {
             println!("{}", message);
         },
-        DisplayMode::Error { message, start_time } => {
+        DisplayMode::Error { message, .. } => {
             println!("Error: {}", message);
         },
     }
@@ -63,24 +102,31 @@ fn graphics_display<'a, C>(
     display: &'a mut Display<C>,
     display_mode: &DisplayMode,
     page_idx: usize,
-    subpage_idx: usize
+    subpage_idx: usize,
+    timeout: Option<Duration>,
+    service_horizon: Option<chrono::Duration>
 ) -> impl Iterator<Item = StateEvent> + 'a where 
     C: DisplayContext,
     <C::Display as DrawTarget>::Error: std::fmt::Debug
 {
     debug!("Drawing to display with mode: {:?}", display_mode);
     match display_mode {
-        DisplayMode::Transit { transit_identifier, transit_state, last_update } => {
-            debug!("Transit mode - identifier: {:?}", transit_identifier);
+        DisplayMode::Transit { transit_identifiers, transit_state, filter, .. } => {
+            debug!("Transit mode - identifiers: {:?}", transit_identifiers);
             if let Some(transit_state) = transit_state {
                 debug!("Drawing transit state");
-                transit_state.draw(display, page_idx, subpage_idx);
+                transit_state.draw(display, filter, page_idx, subpage_idx, service_horizon);
             } else {
                 debug!("No transit state available yet");
             }
         },
-        _ => {
-            debug!("Non-transit display mode");
+        DisplayMode::CustomMessage { message, start_time, .. } => {
+            debug!("Custom message mode");
+            display::message::draw_custom_message(display, message, *start_time, timeout);
+        },
+        DisplayMode::Error { message, start_time } => {
+            debug!("Error mode");
+            display::message::draw_error(display, message, *start_time, timeout);
         },
     }
     
@@ -88,26 +134,95 @@ fn graphics_display<'a, C>(
     display.show_display()
 }
 
-async fn transit_update_task(shared_transit_manager: SharedTransitStateManager, display_fsm: SharedDisplayFiniteStateMachine) {
+async fn transit_update_task(
+    shared_transit_manager: SharedTransitStateManager,
+    display_fsm: SharedDisplayFiniteStateMachine,
+    config_reloaded: Arc<Notify>,
+) {
     loop {
-        // Make updates in smaller scopes to ensure the locks are dropped before the next step
-        {
+        // Only hold the lock to pick the stops and to record the results, not
+        // while waiting on the network
+        let due = shared_transit_manager.read().await.due_fetches();
+        let fetched = TransitStateManager::fetch(due).await;
+        let (changed, next_wakeup) = {
             let mut transit_manager = shared_transit_manager.write().await;
-            transit_manager.update_state().await;
-        }
+            (transit_manager.apply_fetched(fetched), transit_manager.next_wakeup())
+        };
 
-        {
+        if !changed.is_empty() {
             let mut display_fsm = display_fsm.write().await;
-            display_fsm.handle_event(StateEvent::TransitUpdate).await;
+            display_fsm.handle_event(StateEvent::TransitUpdate(changed)).await;
         }
 
-        // Wait 60 sec due to Muni API rate limit
-        tokio::time::sleep(Duration::from_secs(60)).await;
+        // Each provider schedules its own stops; sleep until the next one is due
+        let wait = next_wakeup
+            .map(|wakeup| wakeup.saturating_duration_since(std::time::Instant::now()))
+            .unwrap_or(Duration::from_secs(60));
+        // or until a reload adds stops that should be fetched straight away
+        tokio::select! {
+            _ = tokio::time::sleep(wait.max(Duration::from_secs(1))) => (),
+            _ = config_reloaded.notified() => (),
+        }
     }
 }
 
-fn run_display_loop(display_fsm: SharedDisplayFiniteStateMachine) {
-    let mut display = display::get_display();
+// When each configuration file was last modified, to notice edits
+fn config_modified(config_paths: &ConfigPaths) -> Vec<Option<std::time::SystemTime>> {
+    [&config_paths.config, &config_paths.display]
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
+
+// Reloads config.toml and display.toml whenever either file changes, or when
+// `reload_requested` is notified (on SIGHUP). A configuration that fails to
+// load or validate is reported and the running one kept.
+async fn config_reload_task(
+    config_paths: ConfigPaths,
+    shared_transit_manager: SharedTransitStateManager,
+    display_fsm: SharedDisplayFiniteStateMachine,
+    reload_requested: Arc<Notify>,
+    config_reloaded: Arc<Notify>,
+) {
+    let mut modified = config_modified(&config_paths);
+    let mut interval = tokio::time::interval(Duration::from_secs(2));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let now_modified = config_modified(&config_paths);
+                if now_modified == modified {
+                    continue;
+                }
+                modified = now_modified;
+            }
+            _ = reload_requested.notified() => modified = config_modified(&config_paths),
+        }
+
+        let (config, display_config) = match validate::load(&config_paths) {
+            Ok(loaded) => loaded,
+            Err(issues) => {
+                warn!("Not reloading configuration:\n{}", issues);
+                continue;
+            }
+        };
+
+        // Locked in the same order as the state machine's own handlers, and held
+        // across both swaps so the display never sees one file's changes without the other's
+        let mut display_fsm = display_fsm.write().await;
+        if let Err(e) = shared_transit_manager.write().await.reload(config) {
+            warn!("Not reloading configuration: {}", e);
+            continue;
+        }
+        let needs_restart = display_fsm.reload(display_config).await;
+        config_reloaded.notify_one();
+        info!("Reloaded {} and {}", config_paths.config.display(), config_paths.display.display());
+        if !needs_restart.is_empty() {
+            warn!("Changes to {} in {} take effect after a restart", needs_restart.join(" and "), config_paths.display.display());
+        }
+    }
+}
+
+fn run_display_loop(display_fsm: SharedDisplayFiniteStateMachine, panel: config::PanelConfig, shutdown: watch::Receiver<bool>) {
     let rt = tokio::runtime::Runtime::new().unwrap();
     let display_mode = env::var("DISPLAY_MODE").unwrap_or(String::from("console"));
 
@@ -115,7 +230,7 @@ fn run_display_loop(display_fsm: SharedDisplayFiniteStateMachine) {
     debug!("Initializing display loop");
 
     if display_mode == "console" {
-        loop {
+        while !*shutdown.borrow() {
             // Run the async FSM update in a blocking context
             rt.block_on(async {
                 let mut display_fsm_write = display_fsm.write().await;
@@ -131,63 +246,117 @@ fn run_display_loop(display_fsm: SharedDisplayFiniteStateMachine) {
             console_display(&current_state.0, current_state.1, current_state.2);
             std::thread::sleep(Duration::from_millis(1000)); // Slower refresh for console mode
         }
-    } else {
-        // Graphics mode
-        debug!("Starting graphics mode display loop");
-        let mut last_refresh = std::time::Instant::now();
-        'running: loop {
-            // Add protection against too-frequent refreshes
-            let now = std::time::Instant::now();
-            if now.duration_since(last_refresh) < Duration::from_millis(50) {
-                std::thread::sleep(Duration::from_millis(10));
-                continue;
+    } else if display_mode == "framebuffer" {
+        // Headless: render into memory, optionally dumping new frames to disk
+        let dump = env::var("FRAMEBUFFER_DUMP_DIR").ok().map(|dir| {
+            let format = env::var("FRAMEBUFFER_DUMP_FORMAT").ok()
+                .and_then(|format| FrameFormat::from_str(&format))
+                .unwrap_or(FrameFormat::Png);
+            let mut dump = FrameDump::new(std::path::PathBuf::from(dir), format);
+            if let Some(keep) = env::var("FRAMEBUFFER_DUMP_KEEP").ok().and_then(|keep| keep.parse().ok()) {
+                dump.keep = keep;
             }
-            last_refresh = now;
-
-            debug!("Graphics mode refresh cycle starting");
-            // Run the async FSM update in a blocking context
-            rt.block_on(async {
-                let mut display_fsm_write = display_fsm.write().await;
-                debug!("Graphics mode: acquired FSM lock");
-                display_fsm_write.handle_event(StateEvent::DisplayRefresh).await;
-                debug!("Graphics mode: completed display refresh");
-            });
+            dump
+        });
+        run_graphics_loop(display::get_framebuffer_display(&panel, dump), display_fsm, &rt, shutdown);
+    } else {
+        run_graphics_loop(display::get_display(&panel), display_fsm, &rt, shutdown);
+    }
+}
 
-            // Get the current state
-            let current_state: (DisplayMode, usize, usize) = rt.block_on(async {
-                let display_fsm_read = display_fsm.read().await;
-                (display_fsm_read.current_state().clone(), display_fsm_read.page_idx, display_fsm_read.subpage_idx)
-            });
+fn run_graphics_loop<C>(
+    mut display: Display<C>,
+    display_fsm: SharedDisplayFiniteStateMachine,
+    rt: &tokio::runtime::Runtime,
+    shutdown: watch::Receiver<bool>,
+) where
+    C: DisplayContext,
+    <C::Display as DrawTarget>::Error: std::fmt::Debug
+{
+    // Graphics mode
+    debug!("Starting graphics mode display loop");
+    let mut last_refresh = std::time::Instant::now();
+    // Mode, page and subpage last drawn; marquees start over when any of them changes
+    let mut shown = None;
+    'running: while !*shutdown.borrow() {
+        // Add protection against too-frequent refreshes
+        let now = std::time::Instant::now();
+        if now.duration_since(last_refresh) < Duration::from_millis(50) {
+            std::thread::sleep(Duration::from_millis(10));
+            continue;
+        }
+        last_refresh = now;
+
+        debug!("Graphics mode refresh cycle starting");
+        // Run the async FSM update in a blocking context
+        rt.block_on(async {
+            let mut display_fsm_write = display_fsm.write().await;
+            debug!("Graphics mode: acquired FSM lock");
+            display_fsm_write.handle_event(StateEvent::DisplayRefresh).await;
+            debug!("Graphics mode: completed display refresh");
+        });
+
+        // Get the current state
+        let (current_state, service_horizon, brightness) = rt.block_on(async {
+            let display_fsm_read = display_fsm.read().await;
+            (
+                (display_fsm_read.current_state().clone(), display_fsm_read.page_idx, display_fsm_read.subpage_idx, display_fsm_read.current_timeout()),
+                display_fsm_read.service_horizon(),
+                display_fsm_read.current_brightness(),
+            )
+        });
+
+        let now_shown = (std::mem::discriminant(&current_state.0), current_state.1, current_state.2);
+        if shown != Some(now_shown) {
+            display.restart_animation();
+            shown = Some(now_shown);
+        }
 
-            debug!("Graphics mode: updating display");
-            // Update display
-            let events = graphics_display(&mut display, &current_state.0, current_state.1, current_state.2);
-            debug!("Graphics mode: display update complete");
-
-            for event in events {
-                match event {
-                    StateEvent::Quit => {
-                        println!("Quitting");
-                        break 'running;
-                    }
-                    _ => {
-                        rt.block_on(async {
-                            let mut display_fsm_write = display_fsm.write().await;
-                            display_fsm_write.handle_event(event).await;
-                        })
-                    }
+        display.set_brightness(brightness);
+        debug!("Graphics mode: updating display");
+        // Update display, or blank it while the brightness schedule has it off
+        let events: Vec<StateEvent> = if brightness == 0 {
+            let _ = display.target_mut().clear(Rgb888::BLACK);
+            display.show_display().collect()
+        } else {
+            graphics_display(&mut display, &current_state.0, current_state.1, current_state.2, current_state.3, service_horizon).collect()
+        };
+        debug!("Graphics mode: display update complete");
+
+        for event in events {
+            match event {
+                StateEvent::Quit => {
+                    println!("Quitting");
+                    break 'running;
                 }
-            };
+                _ => {
+                    rt.block_on(async {
+                        let mut display_fsm_write = display_fsm.write().await;
+                        display_fsm_write.handle_event(event).await;
+                    })
+                }
+            }
+        };
 
-            std::thread::sleep(Duration::from_millis(16)); // ~60 FPS for simulator
-        }
+        std::thread::sleep(Duration::from_millis(16)); // ~60 FPS
     }
 }
 
-async fn input_handler_task(display_fsm: SharedDisplayFiniteStateMachine) {
-    let mut input_handler = create_input_handler().await.expect("Failed to create input handler");
+async fn input_handler_task(
+    display_fsm: SharedDisplayFiniteStateMachine,
+    handlers: Vec<NamedInputHandler>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut input = MergedInput::spawn(handlers);
     loop {
-        let event = input_handler.listen().await.expect("Failed to listen for input");
+        let event = tokio::select! {
+            event = input.next() => match event {
+                Some(event) => event,
+                // Every source has stopped and been cleaned up already
+                None => break,
+            },
+            _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+        };
         {
             let mut display_fsm = display_fsm.write().await;
             match event {
@@ -203,31 +372,76 @@ async fn input_handler_task(display_fsm: SharedDisplayFiniteStateMachine) {
                     debug!("Long press");
                     display_fsm.handle_event(StateEvent::Reset).await;
                 }
+                InputEvent::TurnClockwise => {
+                    debug!("Turn clockwise");
+                    display_fsm.handle_event(StateEvent::NextSubpage).await;
+                }
+                InputEvent::TurnCounterClockwise => {
+                    debug!("Turn counter-clockwise");
+                    display_fsm.handle_event(StateEvent::PreviousSubpage).await;
+                }
             }
         }   
     }
+
+    input.shutdown().await;
 }
     
 
+fn config_paths_or_exit(cli: &cli::Cli) -> ConfigPaths {
+    cli.config_paths().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+// Reports every configuration problem at once rather than starting with a broken config
+fn load_config_or_exit(config_paths: &ConfigPaths) -> (config::Config, config::DisplayConfig) {
+    match validate::load(config_paths) {
+        Ok(loaded) => loaded,
+        Err(issues) => {
+            eprintln!("{}", issues);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     // Initialize logging
     env_logger::init();
-    
-    // Load environment variables from .env file
+
+    // Load environment variables from .env file, before the command line so it can set config paths
     match dotenv::dotenv() {
         Ok(_) => println!("Successfully loaded .env file"),
         Err(e) => println!("Failed to load .env file: {}", e),
     };
-    
-    // Get API keys from environment variables
-    let bart_api_key = env::var("BART_API_KEY").expect("BART_API_KEY must be set");
-    let muni_api_key = env::var("MUNI_API_KEY").expect("MUNI_API_KEY must be set");
+    let cli = cli::Cli::parse();
 
-    let config = config::Config::load()?;
-    let display_config = config::DisplayConfig::load()?;
+    match &cli.command {
+        Some(cli::Command::Stops(args)) => {
+            tokio::runtime::Runtime::new()?.block_on(args.run(&cli.http_client()?))?;
+            return Ok(());
+        }
+        Some(cli::Command::Validate) => {
+            let config_paths = config_paths_or_exit(&cli);
+            let (config, display_config) = load_config_or_exit(&config_paths);
+            println!(
+                "{} and {} are valid: {} providers, {} pages",
+                config_paths.config.display(), config_paths.display.display(), config.providers.len(), display_config.pages.len()
+            );
+            return Ok(());
+        }
+        None => (),
+    }
+    
+    let config_paths = config_paths_or_exit(&cli);
+    println!("Using {} and {}", config_paths.config.display(), config_paths.display.display());
+    let (config, display_config) = load_config_or_exit(&config_paths);
+    let panel_config = display_config.panel.clone();
+    let input_config = display_config.input.clone();
     
     // Initialize transit state manager
-    let transit_manager = TransitStateManager::new(config, bart_api_key, muni_api_key);
+    let transit_manager = TransitStateManager::new(config, cli.http_client()?)?;
     let shared_transit_manager = Arc::new(RwLock::new(transit_manager));
 
     let display_fsm = DisplayFiniteStateMachine::new(display_config, shared_transit_manager.clone());
@@ -239,12 +453,68 @@ fn main() -> Result<(), Box<dyn Error>> {
     // Create the runtime for async tasks
     let rt = tokio::runtime::Runtime::new()?;
 
+    // Set on Ctrl+C or when the display loop exits
+    let (shutdown_sender, shutdown) = watch::channel(false);
+    let ctrl_c_sender = shutdown_sender.clone();
+    rt.spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            let _ = ctrl_c_sender.send(true);
+        }
+    });
+
+    // The HTTP control API is opt-in, e.g. HTTP_API_ADDR=8080 for localhost only
+    let api_addr = env::var("HTTP_API_ADDR").ok();
+    let (http_input, http_input_sender) = match (input_config.http, &api_addr) {
+        (true, Some(_)) => {
+            let (http_input, sender) = HttpInput::new();
+            (Some(http_input), Some(sender))
+        }
+        (true, None) => {
+            warn!("HTTP input is enabled but HTTP_API_ADDR is not set");
+            (None, None)
+        }
+        _ => (None, None),
+    };
+    if let Some(api_addr) = api_addr {
+        let api = api::serve(api_addr, shared_display_fsm.clone(), shared_transit_manager.clone(), http_input_sender);
+        rt.spawn(async move {
+            if let Err(e) = api.await {
+                error!("HTTP API failed: {}", e);
+            }
+        });
+    }
+
     // Spawn background tasks
-    rt.spawn(transit_update_task(shared_transit_manager.clone(), shared_display_fsm.clone()));
-    rt.spawn(input_handler_task(shared_display_fsm.clone()));
+    let config_reloaded = Arc::new(Notify::new());
+    let reload_requested = Arc::new(Notify::new());
+    rt.spawn(transit_update_task(shared_transit_manager.clone(), shared_display_fsm.clone(), config_reloaded.clone()));
+    rt.spawn(config_reload_task(config_paths, shared_transit_manager.clone(), shared_display_fsm.clone(), reload_requested.clone(), config_reloaded));
+    #[cfg(unix)]
+    rt.spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        let Ok(mut hangup) = signal(SignalKind::hangup()) else {
+            return;
+        };
+        while hangup.recv().await.is_some() {
+            reload_requested.notify_one();
+        }
+    });
+    // GPIO event handles register with the runtime's reactor when opened
+    let handlers = {
+        let _runtime = rt.enter();
+        create_input_handlers(&input_config, http_input)
+    };
+    let input_task = rt.spawn(input_handler_task(shared_display_fsm.clone(), handlers, shutdown.clone()));
 
     // Run the display loop in the main thread
-    run_display_loop(shared_display_fsm);
+    run_display_loop(shared_display_fsm, panel_config, shutdown);
+
+    // Stop the input sources so they can release their devices
+    println!("Shutting down...");
+    let _ = shutdown_sender.send(true);
+    if rt.block_on(async { tokio::time::timeout(Duration::from_secs(2), input_task).await }).is_err() {
+        eprintln!("Timed out cleaning up input sources");
+    }
 
     // Drop the runtime
     rt.shutdown_background();
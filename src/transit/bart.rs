@@ -1,14 +1,23 @@
+use std::time::Duration;
+
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use log::debug;
 use serde::Deserialize;
 
 use crate::config::Stop;
-use super::{Prediction, TransitProvider, TransitState};
+use super::{Direction, LabelFormat, Prediction, TransitProvider, TransitState};
+use super::discovery::StopCandidate;
+use super::http::HttpClient;
+use super::scheduler::{check_rate_limit, RefreshSchedule};
 use crate::display::Color;
 
+pub const DEFAULT_BASE_URL: &str = "https://api.bart.gov/api";
+
 pub struct BartProvider {
+    provider_key: String,
     api_key: String,
-    client: reqwest::Client,
+    base_url: String,
+    http: HttpClient,
 }
 
 #[derive(Debug, Deserialize)]
@@ -39,11 +48,53 @@ struct BartEstimate {
     color: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct StationsResponse {
+    root: StationsRoot,
+}
+
+#[derive(Debug, Deserialize)]
+struct StationsRoot {
+    stations: StationList,
+}
+
+#[derive(Debug, Deserialize)]
+struct StationList {
+    station: Vec<StationInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StationInfo {
+    name: String,
+    abbr: String,
+    gtfs_latitude: String,
+    gtfs_longitude: String,
+}
+
+// Every BART station, for the `stops` command
+pub async fn list_stations(http: &HttpClient, base_url: &str, api_key: &str) -> anyhow::Result<Vec<StopCandidate>> {
+    let url = format!("{}/stn.aspx?cmd=stns&key={}&json=y", base_url, api_key);
+    let response = http.get(&url, None).await?;
+    check_rate_limit(&response)?;
+    let stations: StationsResponse = serde_json::from_slice(&response.body)?;
+
+    Ok(stations.root.stations.station
+        .into_iter()
+        .map(|station| StopCandidate {
+            id: station.abbr,
+            name: station.name,
+            location: StopCandidate::parse_location(&station.gtfs_latitude, &station.gtfs_longitude),
+        })
+        .collect())
+}
+
 impl BartProvider {
-    pub fn new(api_key: String) -> Self {
+    pub fn new(provider_key: String, api_key: String, base_url: String, http: HttpClient) -> Self {
         Self {
+            provider_key,
             api_key,
-            client: reqwest::Client::new(),
+            base_url,
+            http,
         }
     }
 }
@@ -54,20 +105,33 @@ impl TransitProvider for BartProvider {
         "BART"
     }
 
+    fn default_schedule(&self) -> RefreshSchedule {
+        RefreshSchedule {
+            poll_interval: Duration::from_secs(30),
+            requests_per_hour: None,
+        }
+    }
+
     async fn get_updates(&self, stop: Stop) -> anyhow::Result<TransitState> {
         let url = format!(
-            "https://api.bart.gov/api/etd.aspx?cmd=etd&orig={}&key={}&json=y",
-            stop.id, self.api_key
+            "{}/etd.aspx?cmd=etd&orig={}&key={}&json=y",
+            self.base_url, stop.id, self.api_key
         );
 
-        let response = self.client.get(&url).send().await?;
-        let bart_data: BartResponse = response.json().await?;
+        let response = self.http.get(&url, None).await?;
+        check_rate_limit(&response)?;
+        let bart_data: BartResponse = serde_json::from_slice(&response.body)?;
         
         let mut predictions = Vec::new();
         
         for bart_station in bart_data.root.station {
             for etd in bart_station.etd {
                 for estimate in etd.estimate {
+                    // BART reports "North" or "South"; see Direction::file_departure
+                    let Some(direction) = stop.direction.file_departure(Direction::from_native(&estimate.direction)) else {
+                        debug!("Leaving out a BART estimate heading {:?} at {}, which is configured as {}", estimate.direction, stop.id, stop.direction);
+                        continue;
+                    };
                     let minutes = if estimate.minutes == "Leaving" {
                         0
                     } else {
@@ -75,15 +139,17 @@ impl TransitProvider for BartProvider {
                     };
 
                     predictions.push(Prediction {
-                        provider_key: "bart".to_string(),
+                        provider_key: self.provider_key.clone(),
                         station_id: stop.id.clone(),
-                        route_name: format!("{}", estimate.color),
+                        route_name: estimate.color.clone(),
                         destination: etd.abbreviation.clone(),
                         minutes_until_arrival: minutes,
-                        predicted_time: Utc::now() + chrono::Duration::minutes(minutes as i64),
+                        predicted_time: response.fetched_at + chrono::Duration::minutes(minutes as i64),
                         stop_id: stop.id.clone(),
-                        direction: estimate.direction,
+                        direction,
                         color: Color::from_str(&estimate.color).unwrap_or_default(),
+                        label_format: LabelFormat::LineInitial,
+                        scheduled: false,
                     });
                 }
             }
@@ -1,15 +1,21 @@
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
 use crate::config::Stop;
 use super::{BikeInventory, TransitProvider, TransitState};
+use super::discovery::StopCandidate;
+use super::http::HttpClient;
+use super::scheduler::check_rate_limit;
 
-const EBIKES_API_URL: &str = "https://gbfs.lyft.com/gbfs/1.1/bay/fr/ebikes_at_stations.json";
-const STATION_STATUS_URL: &str = "https://gbfs.lyft.com/gbfs/1.1/bay/en/station_status.json";
+pub const DEFAULT_BASE_URL: &str = "https://gbfs.lyft.com/gbfs/1.1/bay";
+const EBIKES_API_PATH: &str = "/fr/ebikes_at_stations.json";
+const STATION_STATUS_PATH: &str = "/en/station_status.json";
+const STATION_INFORMATION_PATH: &str = "/en/station_information.json";
 
 pub struct BayWheelsProvider {
-    client: reqwest::Client,
+    provider_key: String,
+    base_url: String,
+    http: HttpClient,
 }
 
 #[derive(Debug, Deserialize)]
@@ -31,7 +37,6 @@ struct EbikeStation {
 #[derive(Debug, Deserialize)]
 struct Ebike {
     make_and_model: String,
-    battery_charge_percentage: i32,
 }
 
 #[derive(Debug, Deserialize)]
@@ -51,10 +56,47 @@ struct StationStatus {
     num_docks_available: i32,
 }
 
+#[derive(Debug, Deserialize)]
+struct StationInformationResponse {
+    data: StationInformationData,
+}
+
+#[derive(Debug, Deserialize)]
+struct StationInformationData {
+    stations: Vec<StationInformation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StationInformation {
+    station_id: String,
+    name: String,
+    lat: f64,
+    lon: f64,
+}
+
+// Every dock station in the GBFS feed, for the `stops` command
+pub async fn list_stations(http: &HttpClient, base_url: &str) -> anyhow::Result<Vec<StopCandidate>> {
+    let url = format!("{}{}", base_url, STATION_INFORMATION_PATH);
+    let response = http.get(&url, None).await?;
+    check_rate_limit(&response)?;
+    let information: StationInformationResponse = serde_json::from_slice(&response.body)?;
+
+    Ok(information.data.stations
+        .into_iter()
+        .map(|station| StopCandidate {
+            id: station.station_id,
+            name: station.name,
+            location: Some((station.lat, station.lon)),
+        })
+        .collect())
+}
+
 impl BayWheelsProvider {
-    pub fn new() -> Self {
+    pub fn new(provider_key: String, base_url: String, http: HttpClient) -> Self {
         Self {
-            client: reqwest::Client::new(),
+            provider_key,
+            base_url,
+            http,
         }
     }
 
@@ -82,21 +124,25 @@ impl TransitProvider for BayWheelsProvider {
 
     async fn get_updates(&self, stop: Stop) -> anyhow::Result<TransitState> {
         // Fetch both ebike and station status data concurrently
-        let client = self.client.clone();
+        let http = self.http.clone();
+        let ebikes_url = format!("{}{}", self.base_url, EBIKES_API_PATH);
         let ebike_handle = tokio::spawn(async move {
-            let resp = client.get(EBIKES_API_URL).send().await?;
-            resp.json::<EbikeResponse>().await
+            let resp = http.get(&ebikes_url, None).await?;
+            check_rate_limit(&resp)?;
+            Ok::<_, anyhow::Error>(serde_json::from_slice::<EbikeResponse>(&resp.body)?)
         });
         
-        let client = self.client.clone();
+        let http = self.http.clone();
+        let status_url = format!("{}{}", self.base_url, STATION_STATUS_PATH);
         let status_handle = tokio::spawn(async move {
-            let resp = client.get(STATION_STATUS_URL).send().await?;
-            resp.json::<StationStatusResponse>().await
+            let resp = http.get(&status_url, None).await?;
+            check_rate_limit(&resp)?;
+            Ok::<_, anyhow::Error>((serde_json::from_slice::<StationStatusResponse>(&resp.body)?, resp.fetched_at))
         });
 
         // Wait for both requests to complete and handle errors
         let ebike_response = ebike_handle.await.map_err(|e| anyhow::anyhow!("Task failed: {}", e))??;
-        let status_response = status_handle.await.map_err(|e| anyhow::anyhow!("Task failed: {}", e))??;
+        let (status_response, fetched_at) = status_handle.await.map_err(|e| anyhow::anyhow!("Task failed: {}", e))??;
 
         // Get station status first
         let station_status = status_response.data.stations
@@ -115,12 +161,13 @@ impl TransitProvider for BayWheelsProvider {
         };
         
         Ok(TransitState::BikeInventory(BikeInventory {
+            provider_key: self.provider_key.clone(),
             station_name: stop.id.clone(),
             bikes_available: station_status.num_bikes_available,
             docks_available: station_status.num_docks_available,
             ebikes_v1_available: ebikes_v1,
             ebikes_v2_available: ebikes_v2,
-            last_updated: Utc::now(),
+            last_updated: fetched_at,
             stop_id: stop.id.clone()
         }))
     }
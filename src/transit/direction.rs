@@ -0,0 +1,103 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Serialize};
+
+/// Which way a stop's departures are heading. A stop is configured with the one
+/// direction it serves, `Both` to have the provider split its departures by the
+/// direction each vehicle reports, or `None` where direction doesn't apply
+/// (bike docks). Parsing accepts the abbreviations providers use, e.g. "NB" or "IB".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Direction {
+    #[serde(alias = "north", alias = "Northbound", alias = "northbound", alias = "N", alias = "NB")]
+    North,
+    #[serde(alias = "south", alias = "Southbound", alias = "southbound", alias = "S", alias = "SB")]
+    South,
+    #[serde(alias = "east", alias = "Eastbound", alias = "eastbound", alias = "E", alias = "EB")]
+    East,
+    #[serde(alias = "west", alias = "Westbound", alias = "westbound", alias = "W", alias = "WB")]
+    West,
+    #[serde(alias = "inbound", alias = "IB", alias = "I")]
+    Inbound,
+    #[serde(alias = "outbound", alias = "OB", alias = "O")]
+    Outbound,
+    #[serde(alias = "both")]
+    Both,
+    #[serde(alias = "none")]
+    None,
+}
+
+impl Direction {
+    // A direction a vehicle can actually be heading in
+    pub fn is_specific(self) -> bool {
+        !matches!(self, Self::Both | Self::None)
+    }
+
+    // Where a departure reporting `native` is filed at a stop configured with this
+    // direction, or None to leave it out. `Both` files it under its own direction
+    // and leaves it out if it reported none. A configured heading leaves out
+    // departures reporting a different heading of the same kind, e.g. Outbound at
+    // an Inbound stop; one of the other kind can't contradict it, so a West stop
+    // keeps its Inbound and Outbound departures under West.
+    pub fn file_departure(self, native: Option<Direction>) -> Option<Direction> {
+        match (self, native) {
+            (Self::Both, native) => native.filter(|native| native.is_specific()),
+            (configured, Some(native)) if configured.is_specific() && native.is_specific()
+                && configured.is_compass() == native.is_compass() && configured != native => None,
+            (configured, _) => Some(configured),
+        }
+    }
+
+    fn is_compass(self) -> bool {
+        matches!(self, Self::North | Self::South | Self::East | Self::West)
+    }
+
+    // The directions a configured stop's departures are filed under: its own, or
+    // for `Both`, each direction the provider reports
+    pub fn expand(self, native: &[Direction]) -> Vec<Direction> {
+        match self {
+            Self::Both if !native.is_empty() => native.to_vec(),
+            _ => vec![self],
+        }
+    }
+
+    // A provider's own direction value, e.g. BART's "North" or 511's DirectionRef "IB"
+    pub fn from_native(value: &str) -> Option<Self> {
+        value.parse().ok().filter(|direction: &Self| direction.is_specific())
+    }
+
+    // GTFS leaves the meaning of direction_id to each agency; by convention 0 is
+    // outbound and 1 inbound
+    pub fn from_gtfs(direction_id: u32) -> Option<Self> {
+        match direction_id {
+            0 => Some(Self::Outbound),
+            1 => Some(Self::Inbound),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = serde::de::value::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::deserialize(s.trim().into_deserializer())
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::North => "North",
+            Self::South => "South",
+            Self::East => "East",
+            Self::West => "West",
+            Self::Inbound => "Inbound",
+            Self::Outbound => "Outbound",
+            Self::Both => "Both",
+            Self::None => "None",
+        };
+        f.write_str(name)
+    }
+}
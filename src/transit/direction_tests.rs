@@ -0,0 +1,61 @@
+use super::registry::stop_directions;
+use super::Direction;
+
+#[test]
+fn aliases_parse_to_one_direction() {
+    for value in ["North", "north", "Northbound", "NB", "N"] {
+        assert_eq!(value.parse::<Direction>().unwrap(), Direction::North, "{}", value);
+    }
+    assert_eq!("IB".parse::<Direction>().unwrap(), Direction::Inbound);
+    assert_eq!("OB".parse::<Direction>().unwrap(), Direction::Outbound);
+    assert!("Up".parse::<Direction>().is_err());
+}
+
+#[test]
+fn config_values_round_trip_through_display() {
+    #[derive(serde::Deserialize)]
+    struct Stop {
+        direction: Direction,
+    }
+    let stop: Stop = toml::from_str(r#"direction = "westbound""#).unwrap();
+    assert_eq!(stop.direction, Direction::West);
+    assert_eq!(stop.direction.to_string(), "West");
+}
+
+#[test]
+fn native_values_must_be_a_heading() {
+    assert_eq!(Direction::from_native("South"), Some(Direction::South));
+    assert_eq!(Direction::from_native("IB"), Some(Direction::Inbound));
+    assert_eq!(Direction::from_native("Both"), None);
+    assert_eq!(Direction::from_native(""), None);
+    assert_eq!(Direction::from_gtfs(0), Some(Direction::Outbound));
+    assert_eq!(Direction::from_gtfs(2), None);
+}
+
+#[test]
+fn both_files_every_heading() {
+    assert_eq!(Direction::Both.file_departure(Some(Direction::North)), Some(Direction::North));
+    assert_eq!(Direction::Both.file_departure(Some(Direction::None)), None);
+    assert_eq!(Direction::North.file_departure(Some(Direction::North)), Some(Direction::North));
+    assert_eq!(Direction::North.file_departure(Some(Direction::South)), None);
+}
+
+#[test]
+fn departures_are_filed_by_their_own_direction() {
+    assert_eq!(Direction::Both.file_departure(Some(Direction::Outbound)), Some(Direction::Outbound));
+    // Never filed under Both, which no subpage shows
+    assert_eq!(Direction::Both.file_departure(None), None);
+    assert_eq!(Direction::Inbound.file_departure(Some(Direction::Outbound)), None);
+    assert_eq!(Direction::Inbound.file_departure(None), Some(Direction::Inbound));
+    assert_eq!(Direction::North.file_departure(Some(Direction::South)), None);
+    // A compass heading is the stop's own label
+    assert_eq!(Direction::West.file_departure(Some(Direction::Outbound)), Some(Direction::West));
+}
+
+#[test]
+fn both_stops_expand_to_the_provider_directions() {
+    assert_eq!(stop_directions("bart", Direction::Both), vec![Direction::North, Direction::South]);
+    assert_eq!(stop_directions("muni", Direction::Both), vec![Direction::Inbound, Direction::Outbound]);
+    assert_eq!(stop_directions("muni", Direction::West), vec![Direction::West]);
+    assert_eq!(stop_directions("baywheels", Direction::North), vec![Direction::None]);
+}
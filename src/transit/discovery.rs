@@ -0,0 +1,115 @@
+use std::cmp::Ordering;
+use std::fmt::Write;
+
+use super::Direction;
+
+// Mean Earth radius, plenty accurate for "which stops are near me"
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// A stop or station as listed by a provider's station list or stop catalogue.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StopCandidate {
+    pub id: String,
+    pub name: String,
+    // Latitude and longitude, when the provider lists them
+    pub location: Option<(f64, f64)>,
+}
+
+impl StopCandidate {
+    // Several APIs send coordinates as strings
+    pub fn parse_location(latitude: &str, longitude: &str) -> Option<(f64, f64)> {
+        Some((latitude.trim().parse().ok()?, longitude.trim().parse().ok()?))
+    }
+}
+
+// Great-circle distance in metres
+pub fn distance_m(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (from.0.to_radians(), to.0.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (to.1 - from.1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// Narrows a provider's stop list by name and/or distance from a point.
+#[derive(Debug, Clone)]
+pub struct StopSearch {
+    // Every word must appear in the stop name, ignoring case
+    pub name: Option<String>,
+    pub near: Option<(f64, f64)>,
+    pub radius_m: f64,
+    pub limit: usize,
+}
+
+impl StopSearch {
+    // Matching stops with their distance from `near`, nearest first, or in name
+    // order when searching by name alone
+    pub fn run(&self, candidates: Vec<StopCandidate>) -> Vec<(StopCandidate, Option<f64>)> {
+        let words: Vec<String> = self.name.iter().flat_map(|name| name.split_whitespace()).map(str::to_lowercase).collect();
+
+        let mut matches: Vec<(StopCandidate, Option<f64>)> = candidates
+            .into_iter()
+            .filter(|candidate| {
+                let name = candidate.name.to_lowercase();
+                words.iter().all(|word| name.contains(word.as_str()))
+            })
+            .filter_map(|candidate| match self.near {
+                Some(near) => {
+                    let distance = distance_m(near, candidate.location?);
+                    (distance <= self.radius_m).then_some((candidate, Some(distance)))
+                }
+                None => Some((candidate, None)),
+            })
+            .collect();
+
+        matches.sort_by(|(a, a_distance), (b, b_distance)| {
+            a_distance.partial_cmp(b_distance).unwrap_or(Ordering::Equal).then_with(|| a.name.cmp(&b.name))
+        });
+        matches.truncate(self.limit);
+        matches
+    }
+}
+
+/// How a provider's stops are written into config.toml and display.toml.
+#[derive(Debug, Clone)]
+pub struct StopTemplate {
+    pub provider_key: String,
+    // `direction` of the config.toml Stop entry
+    pub stop_direction: Direction,
+    // One display.toml subpage per direction
+    pub subpage_directions: Vec<Direction>,
+    pub page_display_handler_key: &'static str,
+}
+
+impl StopTemplate {
+    // A config.toml `stops` entry and a display.toml page for one stop
+    pub fn format(&self, candidate: &StopCandidate, distance_m: Option<f64>) -> String {
+        let quote = |s: &str| toml::Value::String(s.to_string()).to_string();
+        let mut out = String::new();
+
+        match distance_m {
+            Some(distance) => writeln!(out, "# {} ({}), {:.0} m away", candidate.name, candidate.id, distance),
+            None => writeln!(out, "# {} ({})", candidate.name, candidate.id),
+        }.unwrap();
+
+        writeln!(out, "# config.toml, in [providers.{}] stops:", self.provider_key).unwrap();
+        writeln!(
+            out,
+            "    {{ id = {}, name = {}, direction = {} }},",
+            quote(&candidate.id), quote(&candidate.name), quote(&self.stop_direction.to_string())
+        ).unwrap();
+
+        writeln!(out, "# display.toml:").unwrap();
+        writeln!(out, "[[pages]]").unwrap();
+        writeln!(out, "subpages = [").unwrap();
+        for direction in &self.subpage_directions {
+            writeln!(
+                out,
+                "    {{ transit_identifier = {{ provider_key = {}, station_id = {}, direction = {} }}, page_display_handler_key = {} }},",
+                quote(&self.provider_key), quote(&candidate.id), quote(&direction.to_string()), quote(self.page_display_handler_key)
+            ).unwrap();
+        }
+        writeln!(out, "]").unwrap();
+        out
+    }
+}
@@ -0,0 +1,50 @@
+use crate::config::{Config, DisplayConfig};
+use super::discovery::{distance_m, StopCandidate, StopTemplate};
+use super::Direction;
+
+fn candidate(name: &str) -> StopCandidate {
+    StopCandidate {
+        id: "13565".to_string(),
+        name: name.to_string(),
+        location: Some((37.783321, -122.439238)),
+    }
+}
+
+fn template() -> StopTemplate {
+    StopTemplate {
+        provider_key: "muni".to_string(),
+        stop_direction: Direction::West,
+        subpage_directions: vec![Direction::West],
+        page_display_handler_key: "predictions",
+    }
+}
+
+#[test]
+fn distance_between_bart_stations() {
+    // Montgomery St. to Embarcadero is about half a kilometre
+    let distance = distance_m((37.789405, -122.401066), (37.792874, -122.397020));
+    assert!((distance - 520.0).abs() < 10.0, "{}", distance);
+}
+
+#[test]
+fn printed_entries_load_as_config() {
+    let printed = template().format(&candidate(r#"Geary Blvd & "Divisadero" St"#), Some(12.0));
+    let (config_part, display_part) = printed.split_once("# display.toml:").unwrap();
+
+    let stop_line = config_part.lines().find(|line| line.trim_start().starts_with('{')).unwrap();
+    let config_toml = format!(
+        "[providers.muni]\ntype = \"muni\"\napi_key = \"key\"\nstops = [\n{}\n]\n",
+        stop_line
+    );
+    let config: Config = toml::from_str(&config_toml).unwrap();
+    let stop = &config.providers["muni"].stops[0];
+    assert_eq!(stop.name, r#"Geary Blvd & "Divisadero" St"#);
+    assert_eq!(stop.direction, Direction::West);
+
+    let display_toml = format!(
+        "message_timeout = {{ secs = 5, nanos = 0 }}\nerror_timeout = {{ secs = 30, nanos = 0 }}\n{}",
+        display_part
+    );
+    let display: DisplayConfig = toml::from_str(&display_toml).unwrap();
+    assert_eq!(display.pages[0].subpages[0].transit_identifiers[0].station_id, "13565");
+}
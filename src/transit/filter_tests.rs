@@ -0,0 +1,77 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use crate::display::Color;
+use super::{Direction, LabelFormat, Prediction, PredictionFilter, TransitState};
+
+fn now() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap()
+}
+
+fn prediction(route_name: &str, destination: &str, minutes: i64) -> Prediction {
+    Prediction {
+        provider_key: "muni".to_string(),
+        route_name: route_name.to_string(),
+        destination: destination.to_string(),
+        minutes_until_arrival: minutes as i32,
+        predicted_time: now() + Duration::minutes(minutes),
+        station_id: "14294".to_string(),
+        stop_id: "14294".to_string(),
+        direction: Direction::West,
+        color: Color::default(),
+        label_format: LabelFormat::default(),
+        scheduled: false,
+    }
+}
+
+fn filter(toml: &str) -> PredictionFilter {
+    toml::from_str(toml).unwrap()
+}
+
+fn routes(state: TransitState) -> Vec<String> {
+    match state {
+        TransitState::Predictions(predictions) => predictions.into_iter().map(|pred| pred.route_name).collect(),
+        other => panic!("expected predictions, got {:?}", other),
+    }
+}
+
+fn state() -> TransitState {
+    TransitState::Predictions(vec![
+        prediction("38", "Ocean Beach", 2),
+        prediction("38R", "Ocean Beach", 6),
+        prediction("38", "Fort Mason", 9),
+        prediction("5R", "Ocean Beach", 12),
+    ])
+}
+
+#[test]
+fn only_listed_routes_ignoring_case() {
+    let only_38 = filter(r#"routes = ["38", "38r"]"#);
+    assert_eq!(routes(only_38.apply(&state(), now())), vec!["38", "38R", "38"]);
+}
+
+#[test]
+fn excluded_routes_and_destinations() {
+    let filter = filter(r#"exclude_routes = ["5R"]
+exclude_destinations = ["Fort Mason"]"#);
+    assert_eq!(routes(filter.apply(&state(), now())), vec!["38", "38R"]);
+}
+
+#[test]
+fn only_listed_destinations() {
+    let filter = filter(r#"destinations = ["fort mason"]"#);
+    assert_eq!(routes(filter.apply(&state(), now())), vec!["38"]);
+}
+
+#[test]
+fn departures_sooner_than_walk_time_are_hidden() {
+    let filter = filter(r#"walk_time = { secs = 360, nanos = 0 }"#);
+    assert_eq!(routes(filter.apply(&state(), now())), vec!["38R", "38", "5R"]);
+    // A minute later the 38R can't be caught either
+    assert_eq!(routes(filter.apply(&state(), now() + Duration::minutes(1))), vec!["38", "5R"]);
+}
+
+#[test]
+fn default_filter_keeps_everything() {
+    assert_eq!(PredictionFilter::default().apply(&state(), now()), state());
+    assert_eq!(filter(r#"routes = ["38"]"#).apply(&TransitState::EmptyState, now()), TransitState::EmptyState);
+}
@@ -0,0 +1,548 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeZone, Utc};
+use log::{debug, warn};
+use prost::Message;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::config::Stop;
+use super::{Direction, LabelFormat, Prediction, TransitProvider, TransitState};
+use super::http::HttpClient;
+use super::scheduler::{check_rate_limit, RefreshSchedule};
+use crate::display::Color;
+
+// Subset of gtfs-realtime.proto needed to read TripUpdates feeds.
+// Fields we don't use are skipped by prost when decoding.
+pub(super) mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FeedMessage {
+        #[prost(message, required, tag = "1")]
+        pub header: FeedHeader,
+        #[prost(message, repeated, tag = "2")]
+        pub entity: Vec<FeedEntity>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FeedHeader {
+        #[prost(string, required, tag = "1")]
+        pub gtfs_realtime_version: String,
+        #[prost(uint64, optional, tag = "3")]
+        pub timestamp: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FeedEntity {
+        #[prost(string, required, tag = "1")]
+        pub id: String,
+        #[prost(bool, optional, tag = "2")]
+        pub is_deleted: Option<bool>,
+        #[prost(message, optional, tag = "3")]
+        pub trip_update: Option<TripUpdate>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TripUpdate {
+        #[prost(message, required, tag = "1")]
+        pub trip: TripDescriptor,
+        #[prost(message, repeated, tag = "2")]
+        pub stop_time_update: Vec<StopTimeUpdate>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TripDescriptor {
+        #[prost(string, optional, tag = "1")]
+        pub trip_id: Option<String>,
+        #[prost(string, optional, tag = "5")]
+        pub route_id: Option<String>,
+        #[prost(uint32, optional, tag = "6")]
+        pub direction_id: Option<u32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StopTimeUpdate {
+        #[prost(uint32, optional, tag = "1")]
+        pub stop_sequence: Option<u32>,
+        #[prost(string, optional, tag = "4")]
+        pub stop_id: Option<String>,
+        #[prost(message, optional, tag = "2")]
+        pub arrival: Option<StopTimeEvent>,
+        #[prost(message, optional, tag = "3")]
+        pub departure: Option<StopTimeEvent>,
+        // 0 = SCHEDULED, 1 = SKIPPED, 2 = NO_DATA
+        #[prost(int32, optional, tag = "5")]
+        pub schedule_relationship: Option<i32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StopTimeEvent {
+        #[prost(int32, optional, tag = "1")]
+        pub delay: Option<i32>,
+        #[prost(int64, optional, tag = "2")]
+        pub time: Option<i64>,
+    }
+}
+
+const SCHEDULE_RELATIONSHIP_SKIPPED: i32 = 1;
+
+// Agencies publish new static GTFS every few weeks, so the zip is fetched again once a day
+const STATIC_REFRESH_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Stops refreshed in the same cycle share one download of the TripUpdates feed
+const TRIP_UPDATES_REUSE: Duration = Duration::from_secs(10);
+
+// How far ahead the timetable is searched for the next trip, enough to span a
+// long weekend without service
+const SCHEDULE_LOOKAHEAD_DAYS: i64 = 7;
+
+#[derive(Debug, Deserialize)]
+struct GtfsRoute {
+    route_id: String,
+    #[serde(default)]
+    route_short_name: String,
+    #[serde(default)]
+    route_long_name: String,
+    #[serde(default)]
+    route_color: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GtfsTrip {
+    route_id: String,
+    trip_id: String,
+    #[serde(default)]
+    trip_headsign: String,
+    #[serde(default)]
+    direction_id: Option<u32>,
+    #[serde(default)]
+    service_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GtfsStopTime {
+    trip_id: String,
+    stop_id: String,
+    #[serde(default)]
+    arrival_time: String,
+    #[serde(default)]
+    departure_time: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GtfsCalendar {
+    service_id: String,
+    monday: u8,
+    tuesday: u8,
+    wednesday: u8,
+    thursday: u8,
+    friday: u8,
+    saturday: u8,
+    sunday: u8,
+    start_date: String,
+    end_date: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GtfsCalendarDate {
+    service_id: String,
+    date: String,
+    exception_type: u8,
+}
+
+// When a service runs, from calendar.txt
+#[derive(Debug)]
+struct Service {
+    // Monday first
+    weekdays: [bool; 7],
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+}
+
+// Lookup tables built from the static GTFS zip. The timetable is only kept for
+// the provider's own stops, since stop_times.txt covers every stop of the agency.
+#[derive(Debug, Default)]
+pub(super) struct StaticFeed {
+    routes: HashMap<String, GtfsRoute>,
+    trips: HashMap<String, GtfsTrip>,
+    // Trip and seconds past the start of its service day, by stop
+    stop_times: HashMap<String, Vec<(String, u32)>>,
+    services: HashMap<String, Service>,
+    // Dates calendar_dates.txt adds a service on (true) or removes it from (false)
+    service_exceptions: HashMap<(String, NaiveDate), bool>,
+}
+
+impl StaticFeed {
+    pub(super) fn from_zip(bytes: &[u8], stop_ids: &HashSet<String>) -> anyhow::Result<Self> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+
+        let routes = read_csv::<GtfsRoute>(&mut archive, "routes.txt", |_| true)?
+            .into_iter()
+            .map(|route| (route.route_id.clone(), route))
+            .collect();
+        let trips = read_csv::<GtfsTrip>(&mut archive, "trips.txt", |_| true)?
+            .into_iter()
+            .map(|trip| (trip.trip_id.clone(), trip))
+            .collect();
+
+        let mut stop_times: HashMap<String, Vec<(String, u32)>> = HashMap::new();
+        for stop_time in read_csv::<GtfsStopTime>(&mut archive, "stop_times.txt", |stop_time: &GtfsStopTime| stop_ids.contains(&stop_time.stop_id))? {
+            // Stops between timepoints may leave both times blank
+            let time = if stop_time.departure_time.is_empty() { &stop_time.arrival_time } else { &stop_time.departure_time };
+            if let Some(secs) = parse_gtfs_time(time) {
+                stop_times.entry(stop_time.stop_id).or_default().push((stop_time.trip_id, secs));
+            }
+        }
+
+        // A feed needs at least one of calendar.txt and calendar_dates.txt
+        let mut services = HashMap::new();
+        if archive.file_names().any(|name| name == "calendar.txt") {
+            for calendar in read_csv::<GtfsCalendar>(&mut archive, "calendar.txt", |_| true)? {
+                let service = Service {
+                    weekdays: [calendar.monday, calendar.tuesday, calendar.wednesday, calendar.thursday, calendar.friday, calendar.saturday, calendar.sunday]
+                        .map(|runs| runs == 1),
+                    start_date: parse_gtfs_date(&calendar.start_date)?,
+                    end_date: parse_gtfs_date(&calendar.end_date)?,
+                };
+                services.insert(calendar.service_id, service);
+            }
+        }
+        let mut service_exceptions = HashMap::new();
+        if archive.file_names().any(|name| name == "calendar_dates.txt") {
+            for exception in read_csv::<GtfsCalendarDate>(&mut archive, "calendar_dates.txt", |_| true)? {
+                let date = parse_gtfs_date(&exception.date)?;
+                service_exceptions.insert((exception.service_id, date), exception.exception_type == 1);
+            }
+        }
+
+        Ok(Self { routes, trips, stop_times, services, service_exceptions })
+    }
+
+    fn runs_on(&self, service_id: &str, date: NaiveDate) -> bool {
+        if let Some(added) = self.service_exceptions.get(&(service_id.to_string(), date)) {
+            return *added;
+        }
+        self.services.get(service_id).is_some_and(|service| {
+            service.start_date <= date && date <= service.end_date
+                && service.weekdays[date.weekday().num_days_from_monday() as usize]
+        })
+    }
+
+    // Scheduled departures from a stop after `now`, soonest first. Timetable times
+    // are read in the sign's local time zone, which is assumed to be the agency's.
+    fn scheduled_departures(&self, stop_id: &str, now: DateTime<Utc>) -> Vec<(&GtfsTrip, DateTime<Utc>)> {
+        let Some(stop_times) = self.stop_times.get(stop_id) else {
+            return Vec::new();
+        };
+
+        let today = now.with_timezone(&Local).date_naive();
+        let mut departures = Vec::new();
+        // Starting from yesterday, whose trips can run past midnight
+        for date in (-1..=SCHEDULE_LOOKAHEAD_DAYS).filter_map(|days| today.checked_add_signed(chrono::Duration::days(days))) {
+            // GTFS times count from noon minus 12 hours, which stays right across DST changes
+            let Some(day_start) = date.and_hms_opt(12, 0, 0)
+                .and_then(|noon| Local.from_local_datetime(&noon).earliest())
+                .map(|noon| noon.with_timezone(&Utc) - chrono::Duration::hours(12)) else {
+                continue;
+            };
+            for (trip_id, secs) in stop_times {
+                let Some(trip) = self.trips.get(trip_id) else {
+                    continue;
+                };
+                let departure = day_start + chrono::Duration::seconds(*secs as i64);
+                if departure > now && self.runs_on(&trip.service_id, date) {
+                    departures.push((trip, departure));
+                }
+            }
+        }
+        departures.sort_by_key(|(_, departure)| *departure);
+        departures
+    }
+
+    // Route name, destination and color shown for a trip
+    fn describe(&self, route_id: &str, trip: Option<&GtfsTrip>) -> (String, String, Color) {
+        let route = self.routes.get(route_id);
+        let route_name = match route {
+            Some(route) if !route.route_short_name.is_empty() => route.route_short_name.clone(),
+            Some(route) => route.route_long_name.clone(),
+            None => route_id.to_string(),
+        };
+        let destination = match trip {
+            Some(trip) if !trip.trip_headsign.is_empty() => trip.trip_headsign.clone(),
+            _ => route.map(|route| route.route_long_name.clone()).unwrap_or_default(),
+        };
+        let color = route
+            .and_then(|route| Color::from_hex(&route.route_color))
+            .unwrap_or_default();
+        (route_name, destination, color)
+    }
+}
+
+// "HH:MM:SS" past the start of the service day; hours run past 24 for trips after midnight
+fn parse_gtfs_time(time: &str) -> Option<u32> {
+    let mut parts = time.split(':').map(|part| part.parse::<u32>().ok());
+    let (Some(Some(hours)), Some(Some(minutes)), Some(Some(seconds)), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return None;
+    };
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+fn parse_gtfs_date(date: &str) -> anyhow::Result<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y%m%d").map_err(|e| anyhow::anyhow!("Invalid GTFS date '{}': {}", date, e))
+}
+
+// Reads the rows of one file in the zip that `keep` accepts
+fn read_csv<T: for<'de> Deserialize<'de>>(
+    archive: &mut zip::ZipArchive<Cursor<&[u8]>>,
+    name: &str,
+    keep: impl Fn(&T) -> bool,
+) -> anyhow::Result<Vec<T>> {
+    let mut contents = String::new();
+    archive.by_name(name)?.read_to_string(&mut contents)?;
+
+    // Some agencies ship their feeds with a UTF-8 BOM
+    let contents = contents.strip_prefix('\u{FEFF}').unwrap_or(&contents);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(contents.as_bytes());
+    let mut rows = Vec::new();
+    for row in reader.deserialize() {
+        let row: T = row?;
+        if keep(&row) {
+            rows.push(row);
+        }
+    }
+    Ok(rows)
+}
+
+/// Generic provider for any agency publishing a GTFS-Realtime TripUpdates feed
+/// alongside a static GTFS zip. Both locations may be `http(s)://` URLs or local
+/// paths (optionally prefixed with `file://`).
+// The file a feed location names, or None for an http(s) URL
+pub fn local_path(location: &str) -> Option<&str> {
+    match location.starts_with("http://") || location.starts_with("https://") {
+        true => None,
+        false => Some(location.strip_prefix("file://").unwrap_or(location)),
+    }
+}
+
+// A trip updates feed, the time it was fetched as, and when it was downloaded
+type CachedTripUpdates = (Arc<proto::FeedMessage>, DateTime<Utc>, Instant);
+
+pub struct GtfsRealtimeProvider {
+    provider_key: String,
+    trip_updates_url: String,
+    static_url: String,
+    // The configured stops, whose timetables are kept from the static feed
+    stop_ids: HashSet<String>,
+    http: HttpClient,
+    // With when each was loaded. The locks are held while downloading, so stops
+    // fetched together wait for one download rather than each starting their own.
+    static_feed: Mutex<Option<(Arc<StaticFeed>, Instant)>>,
+    trip_updates: Mutex<Option<CachedTripUpdates>>,
+}
+
+impl GtfsRealtimeProvider {
+    pub fn new(provider_key: String, trip_updates_url: String, static_url: String, stop_ids: HashSet<String>, http: HttpClient) -> Self {
+        Self {
+            provider_key,
+            trip_updates_url,
+            static_url,
+            stop_ids,
+            http,
+            static_feed: Mutex::new(None),
+            trip_updates: Mutex::new(None),
+        }
+    }
+
+    // Returns the feed contents and when they were fetched
+    async fn fetch_bytes(&self, location: &str) -> anyhow::Result<(Vec<u8>, DateTime<Utc>)> {
+        let Some(path) = local_path(location) else {
+            let response = self.http.get(location, None).await?;
+            check_rate_limit(&response)?;
+            if !response.is_success() {
+                return Err(anyhow::anyhow!(
+                    "GTFS feed {} returned error status: {}",
+                    location,
+                    response.status
+                ));
+            }
+            return Ok((response.body, response.fetched_at));
+        };
+        Ok((tokio::fs::read(path).await?, Utc::now()))
+    }
+
+    // Loaded on first use and again once STATIC_REFRESH_INTERVAL has passed. If
+    // reloading fails, the feed already loaded keeps being used.
+    async fn static_feed(&self) -> anyhow::Result<Arc<StaticFeed>> {
+        let mut cached = self.static_feed.lock().await;
+        if let Some((static_feed, loaded_at)) = cached.as_ref() {
+            if loaded_at.elapsed() < STATIC_REFRESH_INTERVAL {
+                return Ok(static_feed.clone());
+            }
+        }
+
+        debug!("Loading static GTFS from {}", self.static_url);
+        let loaded = match self.fetch_bytes(&self.static_url).await {
+            Ok((bytes, _)) => StaticFeed::from_zip(&bytes, &self.stop_ids),
+            Err(e) => Err(e),
+        };
+        match (loaded, cached.as_mut()) {
+            (Ok(static_feed), _) => {
+                let static_feed = Arc::new(static_feed);
+                *cached = Some((static_feed.clone(), Instant::now()));
+                Ok(static_feed)
+            }
+            (Err(e), Some((static_feed, loaded_at))) => {
+                warn!("Keeping static GTFS loaded earlier, reloading {} failed: {}", self.static_url, e);
+                // Try again after another interval rather than on every request
+                *loaded_at = Instant::now();
+                Ok(static_feed.clone())
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    // The decoded TripUpdates feed and when it was fetched, reusing a download
+    // made within the last TRIP_UPDATES_REUSE for another stop
+    async fn trip_updates(&self) -> anyhow::Result<(Arc<proto::FeedMessage>, DateTime<Utc>)> {
+        let mut cached = self.trip_updates.lock().await;
+        if let Some((feed, fetched_at, downloaded_at)) = cached.as_ref() {
+            if downloaded_at.elapsed() < TRIP_UPDATES_REUSE {
+                return Ok((feed.clone(), *fetched_at));
+            }
+        }
+
+        let (bytes, fetched_at) = self.fetch_bytes(&self.trip_updates_url).await?;
+        let feed = Arc::new(proto::FeedMessage::decode(bytes.as_slice())?);
+        *cached = Some((feed.clone(), fetched_at, Instant::now()));
+        Ok((feed, fetched_at))
+    }
+
+    pub(super) fn predictions_for_stop(
+        &self,
+        feed: &proto::FeedMessage,
+        static_feed: &StaticFeed,
+        stop: &Stop,
+        now: DateTime<Utc>,
+    ) -> Vec<Prediction> {
+        let mut predictions = Vec::new();
+
+        for entity in &feed.entity {
+            if entity.is_deleted.unwrap_or(false) {
+                continue;
+            }
+            let Some(trip_update) = &entity.trip_update else {
+                continue;
+            };
+
+            let trip = trip_update.trip.trip_id.as_ref().and_then(|id| static_feed.trips.get(id));
+            let route_id = trip_update.trip.route_id.clone()
+                .or_else(|| trip.map(|trip| trip.route_id.clone()))
+                .unwrap_or_default();
+            // Filed by direction_id, which many feeds leave to the static trip;
+            // see Direction::file_departure
+            let direction_id = trip_update.trip.direction_id.or_else(|| trip.and_then(|trip| trip.direction_id));
+            let Some(direction) = stop.direction.file_departure(direction_id.and_then(Direction::from_gtfs)) else {
+                if trip_update.stop_time_update.iter().any(|update| update.stop_id.as_deref() == Some(stop.id.as_str())) {
+                    debug!(
+                        "Leaving out trip {} at {} stop {} with direction_id {:?}, which doesn't match its configured direction {}",
+                        trip_update.trip.trip_id.as_deref().unwrap_or("without an id"), self.provider_key, stop.id, direction_id, stop.direction
+                    );
+                }
+                continue;
+            };
+
+            for update in &trip_update.stop_time_update {
+                if update.stop_id.as_deref() != Some(stop.id.as_str()) {
+                    continue;
+                }
+                if update.schedule_relationship == Some(SCHEDULE_RELATIONSHIP_SKIPPED) {
+                    continue;
+                }
+
+                // Only absolute times are usable; delay-only updates need the static schedule
+                let Some(timestamp) = update.arrival.as_ref().and_then(|event| event.time)
+                    .or_else(|| update.departure.as_ref().and_then(|event| event.time)) else {
+                    continue;
+                };
+                let Some(predicted_time) = DateTime::from_timestamp(timestamp, 0) else {
+                    continue;
+                };
+                if predicted_time < now {
+                    continue;
+                }
+
+                let (route_name, destination, color) = static_feed.describe(&route_id, trip);
+                predictions.push(Prediction {
+                    provider_key: self.provider_key.clone(),
+                    station_id: stop.id.clone(),
+                    route_name,
+                    destination,
+                    minutes_until_arrival: predicted_time.signed_duration_since(now).num_minutes() as i32,
+                    predicted_time,
+                    stop_id: stop.id.clone(),
+                    direction,
+                    color,
+                    label_format: LabelFormat::RouteDestination,
+                    scheduled: false,
+                });
+            }
+        }
+
+        // Where a direction has no realtime departures, its next scheduled trip
+        // says when service resumes
+        let mut with_departures: HashSet<Direction> = predictions.iter().map(|prediction| prediction.direction).collect();
+        for (trip, departure) in static_feed.scheduled_departures(&stop.id, now) {
+            let Some(direction) = stop.direction.file_departure(trip.direction_id.and_then(Direction::from_gtfs)) else {
+                continue;
+            };
+            if !with_departures.insert(direction) {
+                continue;
+            }
+            let (route_name, destination, color) = static_feed.describe(&trip.route_id, Some(trip));
+            predictions.push(Prediction {
+                provider_key: self.provider_key.clone(),
+                station_id: stop.id.clone(),
+                route_name,
+                destination,
+                minutes_until_arrival: departure.signed_duration_since(now).num_minutes() as i32,
+                predicted_time: departure,
+                stop_id: stop.id.clone(),
+                direction,
+                color,
+                label_format: LabelFormat::RouteDestination,
+                scheduled: true,
+            });
+        }
+
+        predictions
+    }
+}
+
+#[async_trait]
+impl TransitProvider for GtfsRealtimeProvider {
+    fn name(&self) -> &'static str {
+        "GTFS-Realtime"
+    }
+
+    fn default_schedule(&self) -> RefreshSchedule {
+        RefreshSchedule {
+            poll_interval: Duration::from_secs(30),
+            requests_per_hour: None,
+        }
+    }
+
+    async fn get_updates(&self, stop: Stop) -> anyhow::Result<TransitState> {
+        let static_feed = self.static_feed().await?;
+        let (feed, fetched_at) = self.trip_updates().await?;
+
+        let predictions = self.predictions_for_stop(&feed, &static_feed, &stop, fetched_at);
+
+        Ok(TransitState::Predictions(predictions))
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+// Index of a recording, one RecordedResponse per line; bodies live alongside in BODIES_DIR
+const INDEX_FILE: &str = "responses.jsonl";
+const BODIES_DIR: &str = "bodies";
+
+// Query parameters carrying credentials. They are left out of recordings, and
+// replayed requests are matched without them, so replaying needs no API keys.
+const SECRET_PARAMS: &[&str] = &["key", "api_key", "apikey", "token"];
+
+// Upper bound on a whole request, so one unresponsive API can't hold up a refresh
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: reqwest::StatusCode,
+    pub retry_after: Option<String>,
+    pub body: Vec<u8>,
+    // When the response arrived. Replayed responses keep the time they were recorded,
+    // so providers should measure relative times from this rather than Utc::now()
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        self.status.is_success()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedResponse {
+    url: String,
+    status: u16,
+    retry_after: Option<String>,
+    fetched_at: DateTime<Utc>,
+    body_file: String,
+}
+
+/// HTTP client shared by the providers. Besides fetching live, it can save every
+/// response to a directory (`--record`) or serve a saved directory back in place
+/// of the network (`--replay`).
+#[derive(Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    mode: Arc<Mode>,
+}
+
+enum Mode {
+    Live,
+    Record(Recorder),
+    Replay(Replay),
+}
+
+impl HttpClient {
+    pub fn live() -> Self {
+        Self { client: Self::client(), mode: Arc::new(Mode::Live) }
+    }
+
+    pub fn record(dir: &Path) -> anyhow::Result<Self> {
+        Ok(Self { client: Self::client(), mode: Arc::new(Mode::Record(Recorder::new(dir)?)) })
+    }
+
+    pub fn replay(dir: &Path) -> anyhow::Result<Self> {
+        Ok(Self { client: Self::client(), mode: Arc::new(Mode::Replay(Replay::load(dir)?)) })
+    }
+
+    fn client() -> reqwest::Client {
+        reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .expect("HTTP client builds with default TLS settings")
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(*self.mode, Mode::Replay(_))
+    }
+
+    // How far replayed responses are shifted forward to line up with the current time
+    pub fn time_offset(&self) -> chrono::Duration {
+        match &*self.mode {
+            Mode::Replay(replay) => replay.replay_start - replay.recording_start,
+            _ => chrono::Duration::zero(),
+        }
+    }
+
+    pub async fn get(&self, url: &str, accept: Option<&str>) -> anyhow::Result<HttpResponse> {
+        match &*self.mode {
+            Mode::Live => self.fetch(url, accept).await,
+            Mode::Record(recorder) => {
+                let response = self.fetch(url, accept).await?;
+                if let Err(e) = recorder.save(url, &response) {
+                    debug!("Failed to record response for {}: {}", recording_key(url), e);
+                }
+                Ok(response)
+            }
+            Mode::Replay(replay) => replay.get(url).await,
+        }
+    }
+
+    async fn fetch(&self, url: &str, accept: Option<&str>) -> anyhow::Result<HttpResponse> {
+        let mut request = self.client.get(url);
+        if let Some(accept) = accept {
+            request = request.header(reqwest::header::ACCEPT, accept);
+        }
+
+        // reqwest's errors quote the URL, keys and all, and these end up on the sign
+        let response = request.send().await.map_err(reqwest::Error::without_url)?;
+        let status = response.status();
+        let retry_after = response.headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = response.bytes().await.map_err(reqwest::Error::without_url)?.to_vec();
+
+        Ok(HttpResponse { status, retry_after, body, fetched_at: Utc::now() })
+    }
+}
+
+struct Recorder {
+    dir: PathBuf,
+    // Held while writing so index lines and body numbering stay in step
+    next_seq: Mutex<usize>,
+}
+
+impl Recorder {
+    fn new(dir: &Path) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir.join(BODIES_DIR))?;
+        // Continue numbering when appending to an existing recording
+        let existing = std::fs::read_to_string(dir.join(INDEX_FILE))
+            .map(|index| index.lines().count())
+            .unwrap_or(0);
+        Ok(Self { dir: dir.to_path_buf(), next_seq: Mutex::new(existing) })
+    }
+
+    fn save(&self, url: &str, response: &HttpResponse) -> anyhow::Result<()> {
+        let mut seq = self.next_seq.lock().unwrap_or_else(|e| e.into_inner());
+        let body_file = format!("{}/{:06}.bin", BODIES_DIR, *seq);
+        std::fs::write(self.dir.join(&body_file), scrub_body(url, &response.body))?;
+
+        let recorded = RecordedResponse {
+            url: recording_key(url),
+            status: response.status.as_u16(),
+            retry_after: response.retry_after.clone(),
+            fetched_at: response.fetched_at,
+            body_file,
+        };
+        let mut index = OpenOptions::new().create(true).append(true).open(self.dir.join(INDEX_FILE))?;
+        writeln!(index, "{}", serde_json::to_string(&recorded)?)?;
+
+        *seq += 1;
+        Ok(())
+    }
+}
+
+struct Replay {
+    dir: PathBuf,
+    // Keyed by URL without credentials, each list in recorded order
+    responses: HashMap<String, Vec<RecordedResponse>>,
+    recording_start: DateTime<Utc>,
+    replay_start: DateTime<Utc>,
+}
+
+impl Replay {
+    fn load(dir: &Path) -> anyhow::Result<Self> {
+        let index_path = dir.join(INDEX_FILE);
+        let index = std::fs::read_to_string(&index_path)
+            .map_err(|e| anyhow::anyhow!("Failed to read recording {}: {}", index_path.display(), e))?;
+
+        let mut responses: HashMap<String, Vec<RecordedResponse>> = HashMap::new();
+        for (line_idx, line) in index.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let recorded: RecordedResponse = serde_json::from_str(line)
+                .map_err(|e| anyhow::anyhow!("{} line {}: {}", index_path.display(), line_idx + 1, e))?;
+            responses.entry(recorded.url.clone()).or_default().push(recorded);
+        }
+        for recorded in responses.values_mut() {
+            recorded.sort_by_key(|recorded| recorded.fetched_at);
+        }
+
+        let recording_start = responses.values()
+            .filter_map(|recorded| recorded.first())
+            .map(|recorded| recorded.fetched_at)
+            .min()
+            .ok_or_else(|| anyhow::anyhow!("Recording {} has no responses", dir.display()))?;
+
+        Ok(Self { dir: dir.to_path_buf(), responses, recording_start, replay_start: Utc::now() })
+    }
+
+    // Serves the latest response recorded by the equivalent point in the recording,
+    // or the earliest one if replay hasn't reached the first response for this URL yet.
+    // After the recording ends, its last responses keep being served.
+    async fn get(&self, url: &str) -> anyhow::Result<HttpResponse> {
+        let key = recording_key(url);
+        let recorded = self.responses.get(&key)
+            .ok_or_else(|| anyhow::anyhow!("No recorded response for {}", key))?;
+
+        let recording_now = self.recording_start + (Utc::now() - self.replay_start);
+        let response = recorded.iter()
+            .rev()
+            .find(|recorded| recorded.fetched_at <= recording_now)
+            .unwrap_or(&recorded[0]);
+
+        Ok(HttpResponse {
+            status: reqwest::StatusCode::from_u16(response.status)?,
+            retry_after: response.retry_after.clone(),
+            body: tokio::fs::read(self.dir.join(&response.body_file)).await?,
+            fetched_at: response.fetched_at,
+        })
+    }
+}
+
+// Hides the value of every credential parameter in a message, such as an error
+// quoting a request URL, before it is shown on the sign or served by the API
+pub fn redact_secrets(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(idx) = rest.find(['?', '&']) {
+        redacted.push_str(&rest[..=idx]);
+        rest = &rest[idx + 1..];
+
+        let Some(name) = SECRET_PARAMS.iter().find(|name| rest.strip_prefix(**name).is_some_and(|after| after.starts_with('='))) else {
+            continue;
+        };
+        let value_start = name.len() + 1;
+        let value_end = rest[value_start..]
+            .find(|c: char| c == '&' || c == ')' || c == '"' || c.is_whitespace())
+            .map_or(rest.len(), |end| value_start + end);
+        redacted.push_str(&rest[..value_start]);
+        redacted.push_str("REDACTED");
+        rest = &rest[value_end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+// Some APIs echo the request back in the response, key included (BART's `uri`),
+// so the request's credential values are blanked out wherever they appear
+pub(super) fn scrub_body(url: &str, body: &[u8]) -> Vec<u8> {
+    let Ok(parsed) = reqwest::Url::parse(url) else {
+        return body.to_vec();
+    };
+
+    let mut body = body.to_vec();
+    for (_, secret) in parsed.query_pairs().filter(|(name, value)| SECRET_PARAMS.contains(&name.as_ref()) && !value.is_empty()) {
+        let secret = secret.as_bytes();
+        let mut scrubbed = Vec::with_capacity(body.len());
+        let mut rest = body.as_slice();
+        while let Some(idx) = rest.windows(secret.len()).position(|window| window == secret) {
+            scrubbed.extend_from_slice(&rest[..idx]);
+            scrubbed.extend_from_slice(b"REDACTED");
+            rest = &rest[idx + secret.len()..];
+        }
+        scrubbed.extend_from_slice(rest);
+        body = scrubbed;
+    }
+    body
+}
+
+fn recording_key(url: &str) -> String {
+    let Ok(mut parsed) = reqwest::Url::parse(url) else {
+        return url.to_string();
+    };
+
+    let params: Vec<(String, String)> = parsed.query_pairs()
+        .filter(|(name, _)| !SECRET_PARAMS.contains(&name.as_ref()))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if params.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(params);
+    }
+    parsed.to_string()
+}
@@ -0,0 +1,34 @@
+use super::http::{redact_secrets, scrub_body, HttpClient};
+
+#[test]
+fn secrets_are_redacted_wherever_they_appear() {
+    assert_eq!(
+        redact_secrets("error sending request for url (https://api.bart.gov/api/etd.aspx?cmd=etd&orig=MONT&key=MW9S-E7SL&json=y)"),
+        "error sending request for url (https://api.bart.gov/api/etd.aspx?cmd=etd&orig=MONT&key=REDACTED&json=y)",
+    );
+    assert_eq!(
+        redact_secrets("http://api.511.org/transit/StopMonitoring?api_key=abc123"),
+        "http://api.511.org/transit/StopMonitoring?api_key=REDACTED",
+    );
+    // Only whole parameter names count
+    assert_eq!(redact_secrets("?keyword=a&monkey=b"), "?keyword=a&monkey=b");
+    assert_eq!(redact_secrets("Station not found: 3144f47a"), "Station not found: 3144f47a");
+}
+
+#[test]
+fn recorded_bodies_drop_echoed_keys() {
+    let body = br#"{"uri":"http://api.bart.gov/api/etd.aspx?cmd=etd&orig=MONT&key=MW9S-E7SL&json=y","key":"MW9S-E7SL"}"#;
+    assert_eq!(
+        scrub_body("https://api.bart.gov/api/etd.aspx?cmd=etd&orig=MONT&key=MW9S-E7SL&json=y", body),
+        br#"{"uri":"http://api.bart.gov/api/etd.aspx?cmd=etd&orig=MONT&key=REDACTED&json=y","key":"REDACTED"}"#.to_vec(),
+    );
+    // Without a key in the request, nothing is touched
+    assert_eq!(scrub_body("https://gbfs.lyft.com/gbfs/1.1/bay/en/station_status.json", b"MONT"), b"MONT".to_vec());
+}
+
+#[tokio::test]
+async fn request_errors_leave_out_the_url() {
+    // Nothing listens on port 1, so the connection is refused
+    let error = HttpClient::live().get("http://127.0.0.1:1/etd.aspx?key=MW9S-E7SL", None).await.unwrap_err();
+    assert!(!error.to_string().contains("MW9S-E7SL"), "{}", error);
+}
@@ -14,16 +14,18 @@ use embedded_graphics::{
 };
 
 use crate::config::Stop;
-use crate::display::{Color, Display, DisplayContext};
+use scheduler::RefreshSchedule;
+use crate::display::{layout, Color, Display, DisplayContext};
+pub use direction::Direction;
 
 #[derive(Eq, Hash, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct TransitIdentifier {
     pub provider_key: String,
     pub station_id: String,
-    pub direction: String,
+    pub direction: Direction,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Prediction {
     pub provider_key: String,
     pub route_name: String,
@@ -32,8 +34,26 @@ pub struct Prediction {
     pub predicted_time: DateTime<Utc>,
     pub station_id: String,
     pub stop_id: String,
-    pub direction: String,
-    pub color: Color
+    pub direction: Direction,
+    pub color: Color,
+    pub label_format: LabelFormat,
+    // From the static timetable rather than realtime data. Only used to say when
+    // service resumes, never listed as a departure.
+    #[serde(default)]
+    pub scheduled: bool,
+}
+
+/// How a provider's departures are labelled on the sign, set by the provider
+/// rather than looked up from its key, which is free-form in config.toml
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LabelFormat {
+    /// The line's initial and the destination, e.g. "Y to ANTC" for BART's line colors
+    LineInitial,
+    /// Route and direction, e.g. "38R Outbound", for long 511.org destination names
+    RouteDirection,
+    /// Route and destination, e.g. "12 to Ferry Building"
+    #[default]
+    RouteDestination,
 }
 
 impl Prediction {
@@ -41,21 +61,98 @@ impl Prediction {
         TransitIdentifier {
             provider_key: self.provider_key.clone(),
             station_id: self.station_id.clone(),
-            direction: self.direction.clone(),
+            direction: self.direction,
+        }
+    }
+
+    // Whole minutes until predicted_time, recomputed against the given clock
+    pub fn minutes_until(&self, now: DateTime<Utc>) -> i64 {
+        self.predicted_time.signed_duration_since(now).num_seconds().max(0) / 60
+    }
+
+    // Whether predicted_time is still ahead; departures drop off the sign once it passes
+    pub fn is_upcoming(&self, now: DateTime<Utc>) -> bool {
+        self.predicted_time >= now
+    }
+
+    // A realtime departure that hasn't left yet, which is what the sign lists
+    pub fn is_shown(&self, now: DateTime<Utc>) -> bool {
+        !self.scheduled && self.is_upcoming(now)
+    }
+
+    // Counting down, a departure reads "N min" in whole minutes, then "Arr" for
+    // its last minute and "Now" for the last NOW_THRESHOLD_SECS, in the order
+    // Muni's own signs go from arriving to boarding. Past departures aren't
+    // listed (see is_upcoming), so they never reach here.
+    pub fn arrival_label(&self, now: DateTime<Utc>) -> String {
+        let seconds = self.predicted_time.signed_duration_since(now).num_seconds();
+        if seconds < NOW_THRESHOLD_SECS {
+            "Now".to_string()
+        } else if seconds < 60 {
+            "Arr".to_string()
+        } else {
+            format!("{} min", self.minutes_until(now))
+        }
+    }
+
+    // Route/destination text shown to the left of the arrival time
+    pub fn display_label(&self) -> String {
+        match self.label_format {
+            LabelFormat::LineInitial => format!("{} to {}", self.route_name.chars().take(1).collect::<String>(), self.destination),
+            LabelFormat::RouteDirection => format!("{} {}", self.route_name, self.direction),
+            LabelFormat::RouteDestination => format!("{} to {}", self.route_name, self.destination),
         }
     }
+}
 
-    pub fn to_display_string(&self) -> String {
-        match self.provider_key.as_str() {
-            "bart" => format!("{} to {}: {} min", self.route_name.chars().take(1).collect::<String>(), self.destination, self.minutes_until_arrival),
-            "muni" => format!("{} {}: {} min", self.route_name, self.direction, self.minutes_until_arrival),
-            _ => "Unsupported".to_string()
+/// Narrows a subpage's predictions to the ones worth showing. Routes and
+/// destinations are compared with the names the provider reports, ignoring
+/// case (BART uses line colors and station abbreviations, e.g. "RED" and
+/// "RICH"); empty lists match everything.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PredictionFilter {
+    pub routes: Vec<String>,
+    pub exclude_routes: Vec<String>,
+    pub destinations: Vec<String>,
+    pub exclude_destinations: Vec<String>,
+    // Departures sooner than this can't be caught from the sign
+    pub walk_time: Option<std::time::Duration>,
+}
+
+impl PredictionFilter {
+    pub fn matches(&self, prediction: &Prediction, now: DateTime<Utc>) -> bool {
+        let listed = |names: &[String], name: &str| names.iter().any(|listed| listed.eq_ignore_ascii_case(name));
+        let catchable = self.walk_time
+            .and_then(|walk_time| chrono::Duration::from_std(walk_time).ok())
+            .is_none_or(|walk_time| prediction.predicted_time.signed_duration_since(now) >= walk_time);
+
+        (self.routes.is_empty() || listed(&self.routes, &prediction.route_name))
+            && !listed(&self.exclude_routes, &prediction.route_name)
+            && (self.destinations.is_empty() || listed(&self.destinations, &prediction.destination))
+            && !listed(&self.exclude_destinations, &prediction.destination)
+            && catchable
+    }
+
+    // The state with only matching predictions; other states pass through
+    pub fn apply(&self, state: &TransitState, now: DateTime<Utc>) -> TransitState {
+        match state {
+            TransitState::Predictions(predictions) if *self != Self::default() => TransitState::Predictions(
+                predictions.iter().filter(|pred| self.matches(pred, now)).cloned().collect()
+            ),
+            _ => state.clone(),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Within this many seconds of predicted_time a departure shows as "Now" rather than "Arr"
+const NOW_THRESHOLD_SECS: i64 = 30;
+// Pixels between the end of a prediction's label and its arrival time
+const ARRIVAL_GAP: i32 = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BikeInventory {
+    pub provider_key: String,
     pub station_name: String,
     pub bikes_available: i32,
     pub docks_available: i32,
@@ -68,14 +165,14 @@ pub struct BikeInventory {
 impl BikeInventory {
     pub fn to_identifier(&self) -> TransitIdentifier {
         TransitIdentifier {
-            provider_key: "baywheels".to_string(),
+            provider_key: self.provider_key.clone(),
             station_id: self.station_name.clone(),
-            direction: "None".to_string(),
+            direction: Direction::None,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub enum TransitState {
     Predictions(Vec<Prediction>), // For BART and Muni
     BikeInventory(BikeInventory), // For BayWheels,
@@ -84,6 +181,64 @@ pub enum TransitState {
 }
 
 impl TransitState {
+    // Moves every absolute time by offset, used to bring replayed responses up to date
+    pub fn shift_times(&mut self, offset: chrono::Duration) {
+        match self {
+            TransitState::Predictions(predictions) => {
+                for prediction in predictions {
+                    prediction.predicted_time += offset;
+                }
+            }
+            TransitState::BikeInventory(inventory) => {
+                inventory.last_updated += offset;
+            }
+            TransitState::EmptyState => {}
+        }
+    }
+
+    // False for predictions with no realtime departure within the horizon. Other
+    // states, including one that hasn't loaded yet, count as in service.
+    pub fn has_service_within(&self, now: DateTime<Utc>, horizon: chrono::Duration) -> bool {
+        match self {
+            TransitState::Predictions(predictions) => predictions
+                .iter()
+                .any(|pred| pred.is_shown(now) && now.checked_add_signed(horizon).is_none_or(|limit| pred.predicted_time <= limit)),
+            _ => true,
+        }
+    }
+
+    // Earliest prediction that hasn't left yet, however far away, including the
+    // next scheduled trip where the provider has a timetable
+    pub fn next_departure(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            TransitState::Predictions(predictions) => predictions
+                .iter()
+                .filter(|pred| pred.is_upcoming(now))
+                .map(|pred| pred.predicted_time)
+                .min(),
+            _ => None,
+        }
+    }
+
+    // Combines the predictions of several stops, sorted by time. Stops that haven't
+    // loaded yet are left out; if none have, the result is still loading.
+    pub fn merge<'a>(states: impl IntoIterator<Item = &'a TransitState>) -> TransitState {
+        let mut loaded = false;
+        let mut merged = Vec::new();
+        for state in states {
+            if let TransitState::Predictions(predictions) = state {
+                loaded = true;
+                merged.extend(predictions.iter().cloned());
+            }
+        }
+
+        if !loaded {
+            return TransitState::EmptyState;
+        }
+        merged.sort_by_key(|pred| pred.predicted_time);
+        TransitState::Predictions(merged)
+    }
+
     pub fn to_state_updates(&self) -> Box<dyn Iterator<Item = (TransitIdentifier, TransitState)> + '_> {
         match self {
             TransitState::Predictions(predictions) => {
@@ -94,7 +249,7 @@ impl TransitState {
                         .into_group_map()
                         .into_iter()
                         .map(|(identifier, predictions)| {
-                            let ordered_predictions = predictions.into_iter().sorted_by(|a, b| a.minutes_until_arrival.cmp(&b.minutes_until_arrival)).collect();
+                            let ordered_predictions = predictions.into_iter().sorted_by_key(|pred| pred.predicted_time).collect();
                             (identifier, TransitState::Predictions(ordered_predictions))
                         })
                 )
@@ -106,29 +261,38 @@ impl TransitState {
         }
     }
 
-    pub fn console_display(&self, transit_identifier: TransitIdentifier) {
+    pub fn console_display(&self, transit_identifiers: &[TransitIdentifier]) {
         match self {
-            TransitState::Predictions(predictions) => Self::console_display_predictions(transit_identifier, predictions),
-            TransitState::BikeInventory(inventory) => Self::console_display_bike_inventory(transit_identifier, inventory),
+            TransitState::Predictions(predictions) => Self::console_display_predictions(transit_identifiers, predictions),
+            TransitState::BikeInventory(inventory) => Self::console_display_bike_inventory(transit_identifiers, inventory),
             TransitState::EmptyState => println!("No data available"),
         }
     }
 
-    fn console_display_predictions(transit_identifier: TransitIdentifier, predictions: &[Prediction]) {
-        println!("\n{} - {} - {}", transit_identifier.station_id, transit_identifier.provider_key, transit_identifier.direction);
-        if predictions.is_empty() {
+    fn console_display_header(transit_identifiers: &[TransitIdentifier]) {
+        println!();
+        for identifier in transit_identifiers {
+            println!("{} - {} - {}", identifier.station_id, identifier.provider_key, identifier.direction);
+        }
+    }
+
+    fn console_display_predictions(transit_identifiers: &[TransitIdentifier], predictions: &[Prediction]) {
+        Self::console_display_header(transit_identifiers);
+        let now = Utc::now();
+        let upcoming: Vec<_> = predictions.iter().filter(|pred| pred.is_shown(now)).collect();
+        if upcoming.is_empty() {
             println!("  No upcoming departures");
             return;
         }
 
-        for pred in predictions.iter() {
-            println!("  {} to {} - {} minutes",
-                     pred.route_name, pred.destination, pred.minutes_until_arrival)
+        for pred in upcoming {
+            println!("  {} to {} - {}",
+                     pred.route_name, pred.destination, pred.arrival_label(now))
         }
     }
 
-    fn console_display_bike_inventory(transit_identifier: TransitIdentifier, inventory: &BikeInventory) {
-        println!("\n{} - {} - {}", transit_identifier.station_id, transit_identifier.provider_key, transit_identifier.direction);
+    fn console_display_bike_inventory(transit_identifiers: &[TransitIdentifier], inventory: &BikeInventory) {
+        Self::console_display_header(transit_identifiers);
         println!("  Bikes: {}", inventory.bikes_available);
         println!("  Docks: {}", inventory.docks_available);
         println!("  eBikes (v1): {}", inventory.ebikes_v1_available);
@@ -136,7 +300,36 @@ impl TransitState {
         println!("  Last Updated: {}", inventory.last_updated.with_timezone(&Local).format("%I:%M %p"));
     }
 
-    pub fn draw<C : DisplayContext>(&self, display: &mut Display<C>, page_idx: usize, subpage_idx: usize)
+    // Draws the subpage's predictions through its filter. "No service until" the
+    // next departure is only drawn when the stops themselves have nothing within
+    // `service_horizon` (or at all, if unset); when the filter removed everything
+    // there is, that is said instead.
+    pub fn draw<C>(
+        &self,
+        display: &mut Display<C>,
+        filter: &PredictionFilter,
+        page_idx: usize,
+        subpage_idx: usize,
+        service_horizon: Option<chrono::Duration>,
+    )
+    where
+        C: DisplayContext,
+        C::Display: DrawTarget<Color = Rgb888>,
+        <C::Display as DrawTarget>::Error: std::fmt::Debug
+{
+        self.draw_at(display, filter, page_idx, subpage_idx, service_horizon, Utc::now());
+    }
+
+    // Draws as of the given time, which countdowns are computed against
+    pub fn draw_at<C>(
+        &self,
+        display: &mut Display<C>,
+        filter: &PredictionFilter,
+        _page_idx: usize,
+        _subpage_idx: usize,
+        service_horizon: Option<chrono::Duration>,
+        now: DateTime<Utc>,
+    )
     where
         C: DisplayContext,
         C::Display: DrawTarget<Color = Rgb888>,
@@ -144,9 +337,19 @@ impl TransitState {
 {
         // debug!("Drawing transit state: {:?}", self);
         match self {
+            TransitState::Predictions(_) if !self.has_service_within(now, service_horizon.unwrap_or(chrono::Duration::MAX)) => {
+                debug!("No service, next departure {:?}", self.next_departure(now));
+                Self::draw_no_service(display, self.next_departure(now).map(|time| time.with_timezone(&Local)), now.with_timezone(&Local));
+            },
             TransitState::Predictions(predictions) => {
-                debug!("Drawing predictions: {} items", predictions.len());
-                Self::draw_predictions(display, predictions);
+                let shown: Vec<Prediction> = predictions.iter().filter(|pred| filter.matches(pred, now)).cloned().collect();
+                if shown.iter().any(|pred| pred.is_shown(now)) {
+                    debug!("Drawing predictions: {} items", shown.len());
+                    Self::draw_predictions(display, &shown, now);
+                } else {
+                    debug!("Every departure filtered out");
+                    Self::draw_filtered_out(display);
+                }
             },
             TransitState::BikeInventory(inventory) => {
                 debug!("Drawing bike inventory for {}", inventory.station_name);
@@ -161,7 +364,8 @@ impl TransitState {
 
     fn draw_predictions<C>(
         display: &mut Display<C>,
-        predictions: &[Prediction]
+        predictions: &[Prediction],
+        now: DateTime<Utc>
     ) where
         C: DisplayContext,
         C::Display: DrawTarget<Color = Rgb888>,
@@ -169,8 +373,9 @@ impl TransitState {
     {        
         debug!("Starting to draw predictions");
         
-        // Get y_offset before borrowing target
+        // Get y_offset and animation time before borrowing target
         let y_offset = display.y_offset;
+        let animation_time = display.animation_time();
         
         // Get display dimensions from the target
         let target = display.target_mut();
@@ -190,31 +395,31 @@ impl TransitState {
         
         debug!("Display cleared, drawing {} predictions", predictions.len());
         
-        // Predictions are sorted by minutes_until_arrival, take the first two
-        let predictions_to_show = predictions.iter().take(2);
+        // Predictions are sorted by predicted_time, take as many that haven't left yet as fit
+        let predictions_to_show = predictions.iter().filter(|pred| pred.is_shown(now)).take(layout::lines_that_fit(bounds, y_offset));
 
-        // Draw each prediction
+        // Draw each prediction: the arrival time is pinned to the right edge and
+        // the label scrolls in the space left of it when it doesn't fit
+        let right_x = bounds.size.width as i32 - 1;
         for (i, pred) in predictions_to_show.enumerate() {
-            let y_pos = (i as i32) * 8 + y_offset; // Using stored y_offset
-            let display_text = pred.to_display_string();
-            debug!("Drawing prediction at y={}: {}", y_pos, display_text);
-            
-            // Draw route name, destination, and arrival time
-            let text = Text::new(
-                display_text.as_str(),
-                Point::new(1, y_pos),
-                MonoTextStyle::new(&FONT_5X7, Rgb888::new(pred.color.red, pred.color.green, pred.color.blue))
-            );
-            
-            if let Err(e) = text.draw(target) {
-                debug!("Failed to draw text: {:?}", e);
-            }
+            let y_pos = (i as i32) * layout::LINE_HEIGHT + y_offset; // Using stored y_offset
+            let label = pred.display_label();
+            let arrival = pred.arrival_label(now);
+            debug!("Drawing prediction at y={}: {} {}", y_pos, label, arrival);
+
+            let style = MonoTextStyle::new(&FONT_5X7, Rgb888::new(pred.color.red, pred.color.green, pred.color.blue));
+            layout::draw_right_aligned(target, &arrival, right_x, y_pos, style);
+
+            let arrival_width = layout::text_width(&arrival, &style);
+            let label_width = (right_x - arrival_width as i32 - ARRIVAL_GAP).max(0) as u32;
+            let label_region = layout::line_region(1, y_pos, label_width);
+            layout::draw_marquee_text(target, &label, label_region, y_pos, style, animation_time);
         }
         
         debug!("Finished drawing predictions");
     }
 
-    fn draw_bike_inventory<C : DisplayContext>(
+    fn draw_bike_inventory<C>(
         display: &mut Display<C>,
         inventory: &BikeInventory
     ) where
@@ -223,9 +428,10 @@ impl TransitState {
         <C::Display as DrawTarget>::Error: std::fmt::Debug
     {
         // Clear display by drawing black rectangle
+        let bounds = display.target_mut().bounding_box();
         embedded_graphics::primitives::Rectangle::new(
             Point::new(0, 0),
-            Size::new(96, 16) // Assuming 96x16 display, adjust as needed
+            bounds.size
         )
         .into_styled(PrimitiveStyle::with_fill(Rgb888::BLACK))
         .draw(display.target_mut())
@@ -242,14 +448,81 @@ impl TransitState {
 
         Text::new(
             &format!("Docks: {}", inventory.docks_available),
-            Point::new(1, display.y_offset + 8),
+            Point::new(1, display.y_offset + layout::LINE_HEIGHT),
             MonoTextStyle::new(&FONT_5X7, Rgb888::new(255, 255, 255))
         )
         .draw(display.target_mut())
         .unwrap();
     }
 
-    fn draw_loading<C : DisplayContext>(
+    fn draw_no_service<C>(
+        display: &mut Display<C>,
+        next_departure: Option<DateTime<Local>>,
+        now: DateTime<Local>,
+    ) where
+        C: DisplayContext,
+        C::Display: DrawTarget<Color = Rgb888>,
+        <C::Display as DrawTarget>::Error: std::fmt::Debug
+    {
+        // Clear display by drawing black rectangle
+        let bounds = display.target_mut().bounding_box();
+        embedded_graphics::primitives::Rectangle::new(
+            Point::new(0, 0),
+            bounds.size
+        )
+        .into_styled(PrimitiveStyle::with_fill(Rgb888::BLACK))
+        .draw(display.target_mut())
+        .unwrap();
+
+        let style = MonoTextStyle::new(&FONT_5X7, Rgb888::new(255, 255, 255));
+        Text::new("No service", Point::new(1, display.y_offset), style)
+            .draw(display.target_mut())
+            .unwrap();
+
+        if let Some(next_departure) = next_departure {
+            // The timetable looks up to a week ahead, so name the day unless it's today
+            let format = match next_departure.date_naive() == now.date_naive() {
+                true => "%H:%M",
+                false => "%a %H:%M",
+            };
+            Text::new(
+                &format!("until {}", next_departure.format(format)),
+                Point::new(1, display.y_offset + layout::LINE_HEIGHT),
+                style
+            )
+            .draw(display.target_mut())
+            .unwrap();
+        }
+    }
+
+    // Departures exist, but none the subpage's routes, destinations or walk time allow
+    fn draw_filtered_out<C>(
+        display: &mut Display<C>
+    ) where
+        C: DisplayContext,
+        C::Display: DrawTarget<Color = Rgb888>,
+        <C::Display as DrawTarget>::Error: std::fmt::Debug
+    {
+        // Clear display by drawing black rectangle
+        let bounds = display.target_mut().bounding_box();
+        embedded_graphics::primitives::Rectangle::new(
+            Point::new(0, 0),
+            bounds.size
+        )
+        .into_styled(PrimitiveStyle::with_fill(Rgb888::BLACK))
+        .draw(display.target_mut())
+        .unwrap();
+
+        let style = MonoTextStyle::new(&FONT_5X7, Rgb888::new(255, 255, 255));
+        Text::new("No matching", Point::new(1, display.y_offset), style)
+            .draw(display.target_mut())
+            .unwrap();
+        Text::new("departures", Point::new(1, display.y_offset + layout::LINE_HEIGHT), style)
+            .draw(display.target_mut())
+            .unwrap();
+    }
+
+    fn draw_loading<C>(
         display: &mut Display<C>
     ) where
         C: DisplayContext,
@@ -257,9 +530,10 @@ impl TransitState {
         <C::Display as DrawTarget>::Error: std::fmt::Debug
     {
         // Clear display by drawing black rectangle
+        let bounds = display.target_mut().bounding_box();
         embedded_graphics::primitives::Rectangle::new(
             Point::new(0, 0),
-            Size::new(96, 16) // Assuming 96x16 display, adjust as needed
+            bounds.size
         )
         .into_styled(PrimitiveStyle::with_fill(Rgb888::BLACK))
         .draw(display.target_mut())
@@ -277,12 +551,42 @@ impl TransitState {
 }
 
 #[async_trait]
-pub trait TransitProvider {
+pub trait TransitProvider: Send + Sync {
     async fn get_updates(&self, stop: Stop) -> anyhow::Result<TransitState>;
     fn name(&self) -> &'static str;
+
+    // Polling cadence and request budget used unless overridden in config.toml
+    fn default_schedule(&self) -> RefreshSchedule {
+        RefreshSchedule::default()
+    }
 }
 
 pub mod bart;
 pub mod muni;
 pub mod baywheels;
+pub mod direction;
+pub mod discovery;
+pub mod gtfs_rt;
+pub mod http;
+pub mod registry;
+pub mod scheduler;
 pub mod state;
+
+#[cfg(test)]
+mod snapshot_tests;
+#[cfg(test)]
+mod fixture_tests;
+#[cfg(test)]
+mod filter_tests;
+#[cfg(test)]
+mod prediction_tests;
+#[cfg(test)]
+mod discovery_tests;
+#[cfg(test)]
+mod state_tests;
+#[cfg(test)]
+mod scheduler_tests;
+#[cfg(test)]
+mod http_tests;
+#[cfg(test)]
+mod direction_tests;
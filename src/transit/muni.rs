@@ -1,14 +1,25 @@
+use std::time::Duration;
+
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::Utc;
+use log::debug;
 use serde::Deserialize;
 
 use crate::config::Stop;
-use super::{Prediction, TransitProvider, TransitState};
+use super::{Direction, LabelFormat, Prediction, TransitProvider, TransitState};
+use super::discovery::StopCandidate;
+use super::http::HttpClient;
+use super::scheduler::{check_rate_limit, RefreshSchedule};
 use crate::display::Color;
 
+pub const DEFAULT_BASE_URL: &str = "https://api.511.org/transit";
+
 pub struct MuniProvider {
+    provider_key: String,
     api_key: String,
-    client: reqwest::Client,
+    agency: String,
+    base_url: String,
+    http: HttpClient,
 }
 
 #[derive(Debug, Deserialize)]
@@ -106,12 +117,88 @@ struct MonitoredCall {
     Distances: String,
 }
 
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct StopsResponse {
+    Contents: StopsContents,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct StopsContents {
+    dataObjects: StopsDataObjects,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct StopsDataObjects {
+    ScheduledStopPoint: Vec<ScheduledStopPoint>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct ScheduledStopPoint {
+    id: String,
+    Name: String,
+    Location: Option<StopLocation>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct StopLocation {
+    Latitude: String,
+    Longitude: String,
+}
+
+// Every stop an agency serves according to 511.org, for the `stops` command.
+// The ids are the stop codes StopMonitoring expects.
+pub async fn list_stops(http: &HttpClient, base_url: &str, api_key: &str, agency: &str) -> anyhow::Result<Vec<StopCandidate>> {
+    let url = format!("{}/stops?api_key={}&operator_id={}&format=json", base_url, api_key, agency);
+    let response = http.get(&url, Some("application/json")).await?;
+    check_rate_limit(&response)?;
+    if !response.is_success() {
+        return Err(anyhow::anyhow!("511 stops API returned error status: {}", response.status));
+    }
+
+    // Like StopMonitoring, the response starts with a byte order mark
+    let response_text = String::from_utf8_lossy(&response.body);
+    let cleaned_text = response_text
+        .strip_prefix('\u{FEFF}')
+        .unwrap_or(&response_text)
+        .trim();
+    let stops: StopsResponse = serde_json::from_str(cleaned_text)?;
+
+    Ok(stops.Contents.dataObjects.ScheduledStopPoint
+        .into_iter()
+        .map(|stop| StopCandidate {
+            location: stop.Location.and_then(|location| StopCandidate::parse_location(&location.Latitude, &location.Longitude)),
+            id: stop.id,
+            name: stop.Name,
+        })
+        .collect())
+}
+
 impl MuniProvider {
-    pub fn new(api_key: String) -> Self {
+    pub fn new(provider_key: String, api_key: String, agency: String, base_url: String, http: HttpClient) -> Self {
         Self {
+            provider_key,
             api_key,
-            client: reqwest::Client::new(),
+            agency,
+            base_url,
+            http,
+        }
+    }
+
+    // Files a departure by its DirectionRef (IB or OB for Muni); see Direction::file_departure
+    fn direction(&self, stop: &Stop, direction_ref: &str) -> Option<Direction> {
+        let direction = stop.direction.file_departure(Direction::from_native(direction_ref));
+        if direction.is_none() {
+            debug!(
+                "Leaving out a {} departure at stop {} with DirectionRef {:?}, which doesn't match its configured direction {}",
+                self.provider_key, stop.id, direction_ref, stop.direction
+            );
         }
+        direction
     }
 }
 
@@ -121,28 +208,32 @@ impl TransitProvider for MuniProvider {
         "Muni"
     }
 
+    fn default_schedule(&self) -> RefreshSchedule {
+        RefreshSchedule {
+            poll_interval: Duration::from_secs(60),
+            // 511.org allows 60 requests per hour per API key
+            requests_per_hour: Some(60),
+        }
+    }
+
     async fn get_updates(&self, stop: Stop) -> anyhow::Result<TransitState> {
         let url = format!(
-            "https://api.511.org/transit/StopMonitoring?api_key={}&agency=SF&stopCode={}&format=json",
-            self.api_key, stop.id
+            "{}/StopMonitoring?api_key={}&agency={}&stopCode={}&format=json",
+            self.base_url, self.api_key, self.agency, stop.id
         );
 
-        let response = self.client.get(&url)
-            .header("accept", "application/json")
-            .send()
-            .await?;
+        let response = self.http.get(&url, Some("application/json")).await?;
+        check_rate_limit(&response)?;
         
-        if !response.status().is_success() {
+        if !response.is_success() {
             return Err(anyhow::anyhow!(
                 "Muni API returned error status: {}",
-                response.status()
+                response.status
             ));
         }
 
-        let bytes = response.bytes().await?;
-        
         // Convert to string and remove BOM if present
-        let response_text = String::from_utf8_lossy(&bytes);
+        let response_text = String::from_utf8_lossy(&response.body);
         let cleaned_text = response_text
             .strip_prefix('\u{FEFF}')
             .unwrap_or(&response_text)
@@ -150,24 +241,30 @@ impl TransitProvider for MuniProvider {
         
         let siri_data: SiriResponse = serde_json::from_str(cleaned_text)?;
         let mut predictions = Vec::new();
+        let line_prefix = format!("{}:", self.agency);
         
         for visit in &siri_data.ServiceDelivery.StopMonitoringDelivery.MonitoredStopVisit {
             let journey = &visit.MonitoredVehicleJourney;
+            let Some(direction) = self.direction(&stop, &journey.DirectionRef) else {
+                continue;
+            };
             let arrival_time = chrono::DateTime::parse_from_rfc3339(&journey.MonitoredCall.ExpectedArrivalTime)?;
-            let now = Utc::now();
+            let now = response.fetched_at;
             let duration = arrival_time.signed_duration_since(now);
             let minutes = duration.num_minutes();
             
             predictions.push(Prediction {
-                provider_key: "muni".to_string(),
+                provider_key: self.provider_key.clone(),
                 station_id: stop.id.clone(),
-                route_name: format!("{}", journey.LineRef.trim_start_matches("SF:")),
+                route_name: journey.LineRef.trim_start_matches(line_prefix.as_str()).to_string(),
                 destination: journey.DestinationName.clone(),
                 minutes_until_arrival: minutes as i32,
                 predicted_time: arrival_time.with_timezone(&Utc),
                 stop_id: stop.id.clone(),
-                direction: stop.direction.clone(),
-                color: Color::from_str("PURPLE").unwrap_or_default()
+                direction,
+                color: Color::from_str("PURPLE").unwrap_or_default(),
+                label_format: LabelFormat::RouteDirection,
+                scheduled: false,
             });
         }
 
@@ -0,0 +1,55 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use crate::display::Color;
+use super::{Direction, LabelFormat, Prediction};
+
+fn now() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap()
+}
+
+fn departing_in(seconds: i64) -> Prediction {
+    Prediction {
+        provider_key: "muni".to_string(),
+        route_name: "24".to_string(),
+        destination: "Divisadero".to_string(),
+        minutes_until_arrival: (seconds / 60) as i32,
+        predicted_time: now() + Duration::seconds(seconds),
+        station_id: "14294".to_string(),
+        stop_id: "14294".to_string(),
+        direction: Direction::West,
+        color: Color::default(),
+        label_format: LabelFormat::default(),
+        scheduled: false,
+    }
+}
+
+#[test]
+fn countdown_runs_from_minutes_to_arr_to_now() {
+    let labels: Vec<(i64, String)> = [150, 119, 60, 59, 30, 29, 0]
+        .into_iter()
+        .map(|seconds| (seconds, departing_in(seconds).arrival_label(now())))
+        .collect();
+    assert_eq!(labels, vec![
+        (150, "2 min".to_string()),
+        (119, "1 min".to_string()),
+        (60, "1 min".to_string()),
+        (59, "Arr".to_string()),
+        (30, "Arr".to_string()),
+        (29, "Now".to_string()),
+        (0, "Now".to_string()),
+    ]);
+}
+
+#[test]
+fn departures_drop_off_once_they_have_left() {
+    assert!(departing_in(0).is_upcoming(now()));
+    assert!(!departing_in(-1).is_upcoming(now()));
+}
+
+#[test]
+fn minutes_are_recomputed_against_the_clock() {
+    let prediction = departing_in(5 * 60);
+    assert_eq!(prediction.minutes_until(now()), 5);
+    assert_eq!(prediction.minutes_until(now() + Duration::seconds(61)), 3);
+    assert_eq!(prediction.minutes_until(now() + Duration::minutes(10)), 0);
+}
@@ -0,0 +1,108 @@
+use std::env;
+use std::sync::Arc;
+
+use crate::config::ProviderConfig;
+use super::{Direction, TransitProvider};
+use super::http::HttpClient;
+use super::{bart, baywheels, muni};
+use super::bart::BartProvider;
+use super::baywheels::BayWheelsProvider;
+use super::gtfs_rt::GtfsRealtimeProvider;
+use super::muni::MuniProvider;
+
+// Shared so stops can be fetched without holding the state manager's lock
+pub type SharedTransitProvider = Arc<dyn TransitProvider>;
+
+// Every `type` create_provider understands
+pub const PROVIDER_TYPES: [&str; 4] = ["bart", "muni", "baywheels", "gtfs_rt"];
+
+// The directions a provider files a configured stop's updates under: the stop's
+// own direction, or for a stop configured as Both, each direction the provider reports
+pub fn stop_directions(provider_type: &str, stop_direction: Direction) -> Vec<Direction> {
+    match provider_type {
+        "bart" => stop_direction.expand(&[Direction::North, Direction::South]),
+        "muni" | "gtfs_rt" => stop_direction.expand(&[Direction::Inbound, Direction::Outbound]),
+        // Bike docks have no direction
+        "baywheels" => vec![Direction::None],
+        _ => vec![stop_direction],
+    }
+}
+
+// The directions a stop of this provider type can be configured with, or None
+// where direction is ignored. Muni and GTFS stops may use a compass heading as a
+// label for the Inbound or Outbound departures they serve.
+pub fn configurable_directions(provider_type: &str) -> Option<&'static [Direction]> {
+    match provider_type {
+        "bart" => Some(&[Direction::North, Direction::South, Direction::Both]),
+        "muni" | "gtfs_rt" => Some(&[
+            Direction::North, Direction::South, Direction::East, Direction::West,
+            Direction::Inbound, Direction::Outbound, Direction::Both,
+        ]),
+        _ => None,
+    }
+}
+
+// Builds the provider implementation for a [providers.<provider_key>] table based on its `type`
+pub fn create_provider(provider_key: &str, config: &ProviderConfig, http: &HttpClient) -> anyhow::Result<SharedTransitProvider> {
+    let provider: SharedTransitProvider = match config.provider_type.as_str() {
+        "bart" => Arc::new(BartProvider::new(
+            provider_key.to_string(),
+            api_key(provider_key, config, "BART_API_KEY", http)?,
+            base_url(config, bart::DEFAULT_BASE_URL),
+            http.clone(),
+        )),
+        "muni" => Arc::new(MuniProvider::new(
+            provider_key.to_string(),
+            api_key(provider_key, config, "MUNI_API_KEY", http)?,
+            config.setting_str("agency").unwrap_or("SF").to_string(),
+            base_url(config, muni::DEFAULT_BASE_URL),
+            http.clone(),
+        )),
+        "baywheels" => Arc::new(BayWheelsProvider::new(
+            provider_key.to_string(),
+            base_url(config, baywheels::DEFAULT_BASE_URL),
+            http.clone(),
+        )),
+        "gtfs_rt" => Arc::new(GtfsRealtimeProvider::new(
+            provider_key.to_string(),
+            required_setting(provider_key, config, "trip_updates_url")?,
+            required_setting(provider_key, config, "static_url")?,
+            config.stops.iter().map(|stop| stop.id.clone()).collect(),
+            http.clone(),
+        )),
+        other => return Err(anyhow::anyhow!(
+            "Unknown provider type '{}' for [providers.{}]", other, provider_key
+        )),
+    };
+
+    Ok(provider)
+}
+
+// Providers with hosted APIs accept `base_url` to point them elsewhere, e.g. a local stub server
+fn base_url(config: &ProviderConfig, default: &str) -> String {
+    config.setting_str("base_url").unwrap_or(default).trim_end_matches('/').to_string()
+}
+
+fn required_setting(provider_key: &str, config: &ProviderConfig, name: &str) -> anyhow::Result<String> {
+    config.setting_str(name)
+        .map(str::to_string)
+        .ok_or_else(|| anyhow::anyhow!("[providers.{}] is missing required setting '{}'", provider_key, name))
+}
+
+// API keys come from `api_key` in the provider table (which `api_key_file` is
+// read into when the configuration is loaded), or else the environment
+// variable named by `api_key_env` (falling back to the provider type's default).
+// Replayed responses are matched without their keys, so replay needs none.
+fn api_key(provider_key: &str, config: &ProviderConfig, default_env: &str, http: &HttpClient) -> anyhow::Result<String> {
+    if let Some(key) = config.setting_str("api_key") {
+        return Ok(key.to_string());
+    }
+
+    let env_var = config.setting_str("api_key_env").unwrap_or(default_env);
+    if http.is_replay() {
+        return Ok(env::var(env_var).unwrap_or_default());
+    }
+    env::var(env_var).map_err(|_| anyhow::anyhow!(
+        "{} must be set for [providers.{}]", env_var, provider_key
+    ))
+}
@@ -0,0 +1,164 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::config::ProviderConfig;
+use super::http::HttpResponse;
+
+// Never back off longer than this, even after repeated 429s
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// How often a provider wants to be polled and how many requests it may make per hour.
+#[derive(Debug, Clone, Copy)]
+pub struct RefreshSchedule {
+    pub poll_interval: Duration,
+    pub requests_per_hour: Option<u32>,
+}
+
+impl Default for RefreshSchedule {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(60),
+            requests_per_hour: None,
+        }
+    }
+}
+
+impl RefreshSchedule {
+    // Provider defaults, overridden by `poll_interval_secs` / `requests_per_hour` in its config table
+    pub fn from_config(defaults: RefreshSchedule, config: &ProviderConfig) -> Self {
+        let poll_interval = config.setting_int("poll_interval_secs")
+            .map(|secs| Duration::from_secs(secs.max(1) as u64))
+            .unwrap_or(defaults.poll_interval);
+        let requests_per_hour = config.setting_int("requests_per_hour")
+            .map(|budget| budget.max(1) as u32)
+            .or(defaults.requests_per_hour);
+
+        Self { poll_interval, requests_per_hour }
+    }
+
+    // Each stop costs one request, so the budget is shared across all of a provider's stops
+    pub fn stop_interval(&self, stop_count: usize) -> Duration {
+        match self.requests_per_hour {
+            Some(budget) => {
+                let budget_interval = Duration::from_secs(3600) * stop_count.max(1) as u32 / budget;
+                self.poll_interval.max(budget_interval)
+            }
+            None => self.poll_interval,
+        }
+    }
+}
+
+/// Returned by providers when the upstream API responds with HTTP 429.
+#[derive(Debug)]
+pub struct RateLimited {
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.retry_after {
+            Some(retry_after) => write!(f, "rate limited, retry after {}s", retry_after.as_secs()),
+            None => write!(f, "rate limited"),
+        }
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+// Turns a 429 response into a RateLimited error, honoring Retry-After when given in seconds
+pub fn check_rate_limit(response: &HttpResponse) -> anyhow::Result<()> {
+    if response.status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Ok(());
+    }
+
+    let retry_after = response.retry_after
+        .as_deref()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs);
+
+    Err(RateLimited { retry_after }.into())
+}
+
+/// Tracks when each of a provider's stops is next due, spreading requests
+/// evenly across the stop interval and backing off when rate limited.
+pub struct ProviderScheduler {
+    stop_interval: Duration,
+    // None until the stop has been fetched once, so every stop loads at startup
+    next_due: Vec<Option<Instant>>,
+    backoff: Option<Duration>,
+    blocked_until: Option<Instant>,
+}
+
+impl ProviderScheduler {
+    pub fn new(schedule: RefreshSchedule, stop_count: usize) -> Self {
+        Self {
+            stop_interval: schedule.stop_interval(stop_count),
+            next_due: vec![None; stop_count],
+            backoff: None,
+            blocked_until: None,
+        }
+    }
+
+    pub fn stop_interval(&self) -> Duration {
+        self.stop_interval
+    }
+
+    pub fn due_stops(&self, now: Instant) -> Vec<usize> {
+        if self.blocked_until.is_some_and(|until| now < until) {
+            return Vec::new();
+        }
+
+        self.next_due
+            .iter()
+            .enumerate()
+            .filter(|(_, due)| due.is_none_or(|due| due <= now))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        let next_stop = self.next_due.iter().map(|due| due.unwrap_or_else(Instant::now)).min()?;
+        Some(match self.blocked_until {
+            Some(until) => next_stop.max(until),
+            None => next_stop,
+        })
+    }
+
+    // A request that was already out when a 429 blocked the provider says nothing
+    // about whether the block has lifted, so only one made after it clears the backoff
+    pub fn record_success(&mut self, stop_idx: usize, requested_at: Instant, now: Instant) {
+        if self.blocked_until.is_none_or(|until| requested_at >= until) {
+            self.backoff = None;
+            self.blocked_until = None;
+        }
+        self.schedule_next(stop_idx, now);
+    }
+
+    pub fn record_failure(&mut self, stop_idx: usize, now: Instant) {
+        self.schedule_next(stop_idx, now);
+    }
+
+    pub fn record_rate_limited(&mut self, stop_idx: usize, now: Instant, rate_limited: &RateLimited) {
+        let backoff = rate_limited.retry_after.unwrap_or_else(|| match self.backoff {
+            Some(previous) => previous * 2,
+            None => self.stop_interval,
+        }).min(MAX_BACKOFF);
+        debug!("Rate limited, backing off for {}s", backoff.as_secs());
+
+        self.backoff = Some(backoff);
+        self.blocked_until = Some(now + backoff);
+        self.schedule_next(stop_idx, now);
+    }
+
+    fn schedule_next(&mut self, stop_idx: usize, now: Instant) {
+        let stop_count = self.next_due.len() as u32;
+        let next = match self.next_due[stop_idx] {
+            // Stagger the first refresh of each stop so later requests spread across the interval
+            None => now + self.stop_interval * (stop_idx as u32 + 1) / stop_count,
+            Some(_) => now + self.stop_interval,
+        };
+        self.next_due[stop_idx] = Some(next);
+    }
+}
@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+
+use super::scheduler::{ProviderScheduler, RateLimited, RefreshSchedule};
+
+fn schedule(poll_interval_secs: u64, requests_per_hour: Option<u32>) -> RefreshSchedule {
+    RefreshSchedule {
+        poll_interval: Duration::from_secs(poll_interval_secs),
+        requests_per_hour,
+    }
+}
+
+#[test]
+fn budget_is_split_across_stops() {
+    // 511.org's 60 requests an hour across six stops leaves one request per stop every 6 minutes
+    assert_eq!(schedule(60, Some(60)).stop_interval(6), Duration::from_secs(360));
+    // A budget with room to spare still polls no faster than poll_interval
+    assert_eq!(schedule(60, Some(600)).stop_interval(2), Duration::from_secs(60));
+    assert_eq!(schedule(30, None).stop_interval(10), Duration::from_secs(30));
+}
+
+#[test]
+fn every_stop_is_due_at_startup_then_staggered() {
+    let now = Instant::now();
+    let mut scheduler = ProviderScheduler::new(schedule(60, None), 3);
+    assert_eq!(scheduler.due_stops(now), vec![0, 1, 2]);
+
+    for stop_idx in 0..3 {
+        scheduler.record_success(stop_idx, now, now);
+    }
+    assert_eq!(scheduler.due_stops(now + Duration::from_secs(19)), Vec::<usize>::new());
+    assert_eq!(scheduler.due_stops(now + Duration::from_secs(20)), vec![0]);
+    assert_eq!(scheduler.due_stops(now + Duration::from_secs(40)), vec![0, 1]);
+    assert_eq!(scheduler.due_stops(now + Duration::from_secs(60)), vec![0, 1, 2]);
+    assert_eq!(scheduler.next_wakeup(), Some(now + Duration::from_secs(20)));
+}
+
+#[test]
+fn failures_wait_a_full_interval() {
+    let now = Instant::now();
+    let mut scheduler = ProviderScheduler::new(schedule(60, None), 1);
+    scheduler.record_success(0, now, now);
+    scheduler.record_failure(0, now + Duration::from_secs(60));
+    assert_eq!(scheduler.next_wakeup(), Some(now + Duration::from_secs(120)));
+}
+
+#[test]
+fn rate_limiting_backs_off_every_stop() {
+    let now = Instant::now();
+    let mut scheduler = ProviderScheduler::new(schedule(60, None), 2);
+
+    scheduler.record_rate_limited(0, now, &RateLimited { retry_after: None });
+    // Stop 1 had never been fetched, but waits out the backoff too
+    assert_eq!(scheduler.due_stops(now + Duration::from_secs(59)), Vec::<usize>::new());
+    assert_eq!(scheduler.due_stops(now + Duration::from_secs(60)), vec![0, 1]);
+
+    // Without Retry-After the backoff doubles each time
+    let later = now + Duration::from_secs(60);
+    scheduler.record_rate_limited(1, later, &RateLimited { retry_after: None });
+    assert_eq!(scheduler.due_stops(later + Duration::from_secs(119)), Vec::<usize>::new());
+    assert_eq!(scheduler.due_stops(later + Duration::from_secs(120)), vec![0, 1]);
+}
+
+#[test]
+fn retry_after_is_honored_and_capped() {
+    let now = Instant::now();
+    let mut scheduler = ProviderScheduler::new(schedule(60, None), 1);
+
+    scheduler.record_rate_limited(0, now, &RateLimited { retry_after: Some(Duration::from_secs(300)) });
+    assert_eq!(scheduler.next_wakeup(), Some(now + Duration::from_secs(300)));
+
+    scheduler.record_rate_limited(0, now, &RateLimited { retry_after: Some(Duration::from_secs(24 * 3600)) });
+    assert_eq!(scheduler.next_wakeup(), Some(now + Duration::from_secs(3600)));
+}
+
+#[test]
+fn success_clears_the_backoff() {
+    let now = Instant::now();
+    let mut scheduler = ProviderScheduler::new(schedule(60, None), 1);
+    scheduler.record_rate_limited(0, now, &RateLimited { retry_after: None });
+    scheduler.record_rate_limited(0, now, &RateLimited { retry_after: None });
+
+    let later = now + Duration::from_secs(120);
+    scheduler.record_success(0, later, later);
+    scheduler.record_rate_limited(0, later, &RateLimited { retry_after: None });
+    // Back to one stop interval rather than doubling again
+    assert_eq!(scheduler.next_wakeup(), Some(later + Duration::from_secs(60)));
+}
+
+#[test]
+fn requests_already_out_keep_the_block() {
+    let now = Instant::now();
+    let mut scheduler = ProviderScheduler::new(schedule(60, None), 2);
+
+    // Stop 1 was requested alongside stop 0 and came back fine in the same batch
+    let sent = now - Duration::from_secs(1);
+    scheduler.record_rate_limited(0, now, &RateLimited { retry_after: Some(Duration::from_secs(300)) });
+    scheduler.record_success(1, sent, now);
+    assert_eq!(scheduler.due_stops(now + Duration::from_secs(299)), Vec::<usize>::new());
+    assert_eq!(scheduler.next_wakeup(), Some(now + Duration::from_secs(300)));
+
+    // A success from before the block also leaves the backoff doubling
+    let later = now + Duration::from_secs(300);
+    scheduler.record_rate_limited(0, later, &RateLimited { retry_after: None });
+    assert_eq!(scheduler.next_wakeup(), Some(later + Duration::from_secs(600)));
+
+    // Once a request made after the block succeeds, it starts over
+    let after = later + Duration::from_secs(600);
+    scheduler.record_success(0, after, after);
+    scheduler.record_rate_limited(0, after, &RateLimited { retry_after: None });
+    assert_eq!(scheduler.next_wakeup(), Some(after + Duration::from_secs(60)));
+}
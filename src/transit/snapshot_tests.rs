@@ -0,0 +1,191 @@
+// Golden-image tests for the page renderers. Each case draws a TransitState onto
+// an in-memory 96x16 framebuffer and compares it with tests/snapshots/<name>.png.
+// Run with UPDATE_SNAPSHOTS=1 to (re)generate the reference images after an
+// intentional layout change; mismatching frames are written to
+// target/snapshots/<name>.actual.png for inspection.
+
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, Local, TimeZone, Utc};
+use embedded_graphics::prelude::*;
+
+use crate::config::PanelConfig;
+use crate::display::framebuffer::Framebuffer;
+use crate::display::{get_framebuffer_display, Color};
+use super::{BikeInventory, LabelFormat, Prediction, PredictionFilter, TransitState};
+
+fn now() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap()
+}
+
+fn render(state: &TransitState) -> Framebuffer {
+    render_filtered(state, &PredictionFilter::default())
+}
+
+fn render_filtered(state: &TransitState, filter: &PredictionFilter) -> Framebuffer {
+    let mut display = get_framebuffer_display(&PanelConfig::default(), None);
+    state.draw_at(&mut display, filter, 0, 0, None, now());
+    display.target_mut().clone()
+}
+
+fn assert_snapshot(name: &str, frame: &Framebuffer) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let reference = root.join("tests/snapshots").join(format!("{}.png", name));
+
+    if std::env::var("UPDATE_SNAPSHOTS").is_ok() {
+        std::fs::create_dir_all(reference.parent().unwrap()).unwrap();
+        frame.write_png(&reference).unwrap();
+        return;
+    }
+
+    let expected = Framebuffer::read_png(&reference)
+        .unwrap_or_else(|e| panic!("Missing snapshot {} ({}); run with UPDATE_SNAPSHOTS=1", reference.display(), e));
+    if &expected == frame {
+        return;
+    }
+
+    let actual = root.join("target/snapshots").join(format!("{}.actual.png", name));
+    std::fs::create_dir_all(actual.parent().unwrap()).unwrap();
+    frame.write_png(&actual).unwrap();
+
+    let differing = frame.bounding_box().points()
+        .filter(|point| expected.pixel(*point) != frame.pixel(*point))
+        .count();
+    panic!("Snapshot {} differs in {} pixels; actual frame written to {}", name, differing, actual.display());
+}
+
+fn prediction(provider_key: &str, route_name: &str, destination: &str, direction: &str, minutes: i64, color: &str) -> Prediction {
+    let predicted_time = now() + Duration::minutes(minutes);
+    Prediction {
+        provider_key: provider_key.to_string(),
+        route_name: route_name.to_string(),
+        destination: destination.to_string(),
+        minutes_until_arrival: minutes as i32,
+        predicted_time,
+        station_id: "MONT".to_string(),
+        stop_id: "MONT".to_string(),
+        direction: direction.parse().unwrap(),
+        color: Color::from_str(color).unwrap_or_default(),
+        label_format: match provider_key {
+            "bart" => LabelFormat::LineInitial,
+            "muni" => LabelFormat::RouteDirection,
+            _ => LabelFormat::RouteDestination,
+        },
+        scheduled: false,
+    }
+}
+
+#[test]
+fn bart_predictions() {
+    let state = TransitState::Predictions(vec![
+        prediction("bart", "YELLOW", "ANTC", "North", 3, "YELLOW"),
+        prediction("bart", "RED", "RICH", "North", 12, "RED"),
+        prediction("bart", "YELLOW", "ANTC", "North", 18, "YELLOW"),
+    ]);
+    assert_snapshot("bart_predictions", &render(&state));
+}
+
+#[test]
+fn muni_predictions_arriving_now() {
+    let state = TransitState::Predictions(vec![
+        prediction("muni", "38R", "Ocean Beach", "West", 0, "PURPLE"),
+        prediction("muni", "38", "Ocean Beach", "West", 1, "PURPLE"),
+    ]);
+    assert_snapshot("muni_predictions_arriving_now", &render(&state));
+}
+
+#[test]
+fn long_label_is_clipped_before_arrival_time() {
+    let state = TransitState::Predictions(vec![
+        prediction("actransit", "NL", "San Francisco Salesforce Transit Center", "West", 25, "GREEN"),
+    ]);
+    assert_snapshot("long_label", &render(&state));
+}
+
+#[test]
+fn departed_predictions_are_hidden() {
+    let state = TransitState::Predictions(vec![
+        prediction("bart", "RED", "RICH", "North", -2, "RED"),
+        prediction("bart", "YELLOW", "ANTC", "North", 7, "YELLOW"),
+    ]);
+    assert_snapshot("departed_hidden", &render(&state));
+}
+
+#[test]
+fn bike_inventory() {
+    let state = TransitState::BikeInventory(BikeInventory {
+        provider_key: "baywheels".to_string(),
+        station_name: "3144f47a-86f7-40f6-9ff0-5c8120babf6a".to_string(),
+        bikes_available: 7,
+        docks_available: 12,
+        ebikes_v1_available: 2,
+        ebikes_v2_available: 3,
+        last_updated: now(),
+        stop_id: "3144f47a-86f7-40f6-9ff0-5c8120babf6a".to_string(),
+    });
+    assert_snapshot("bike_inventory", &render(&state));
+}
+
+#[test]
+fn empty_state() {
+    assert_snapshot("empty_state", &render(&TransitState::EmptyState));
+}
+
+#[test]
+fn no_departures_is_no_service() {
+    let state = TransitState::Predictions(vec![
+        prediction("muni", "24", "Divisadero", "North", -5, "PURPLE"),
+    ]);
+    assert_snapshot("no_service", &render(&state));
+}
+
+#[test]
+fn filtered_out_departures_are_not_no_service() {
+    let state = TransitState::Predictions(vec![
+        prediction("muni", "24", "Divisadero", "North", 3, "PURPLE"),
+    ]);
+    let walk_time = PredictionFilter { walk_time: Some(std::time::Duration::from_secs(5 * 60)), ..Default::default() };
+    assert_snapshot("filtered_out", &render_filtered(&state, &walk_time));
+}
+
+#[test]
+fn scheduled_trips_are_not_listed() {
+    let mut scheduled = prediction("muni", "24", "Divisadero", "North", 95, "PURPLE");
+    scheduled.scheduled = true;
+    let state = TransitState::Predictions(vec![scheduled]);
+    // Drawn as "No service until" rather than as a departure
+    assert!(!state.has_service_within(now(), Duration::MAX));
+    assert_eq!(state.next_departure(now()), Some(now() + Duration::minutes(95)));
+}
+
+#[test]
+fn no_service_names_the_day_of_a_later_departure() {
+    // Friday night, with the next timetabled trip on Tuesday morning, in local time
+    // as drawn so the frame is the same in any time zone
+    let friday_night = Local.with_ymd_and_hms(2024, 3, 1, 22, 30, 0).unwrap().with_timezone(&Utc);
+    let mut tuesday = prediction("muni", "24", "Divisadero", "North", 0, "PURPLE");
+    tuesday.predicted_time = Local.with_ymd_and_hms(2024, 3, 5, 8, 2, 0).unwrap().with_timezone(&Utc);
+    tuesday.scheduled = true;
+    let mut tonight = tuesday.clone();
+    tonight.predicted_time = friday_night + Duration::minutes(20);
+
+    let draw = |state: TransitState| {
+        let mut display = get_framebuffer_display(&PanelConfig::default(), None);
+        state.draw_at(&mut display, &PredictionFilter::default(), 0, 0, None, friday_night);
+        display.target_mut().clone()
+    };
+    assert_snapshot("no_service_until_tuesday", &draw(TransitState::Predictions(vec![tuesday])));
+    assert_snapshot("no_service_until_tonight", &draw(TransitState::Predictions(vec![tonight])));
+}
+
+#[test]
+fn service_beyond_the_horizon() {
+    let state = TransitState::Predictions(vec![
+        prediction("muni", "24", "Divisadero", "North", -5, "PURPLE"),
+        prediction("muni", "24", "Divisadero", "North", 95, "PURPLE"),
+    ]);
+    assert!(state.has_service_within(now(), Duration::minutes(120)));
+    assert!(!state.has_service_within(now(), Duration::minutes(90)));
+    assert_eq!(state.next_departure(now()), Some(now() + Duration::minutes(95)));
+    assert!(TransitState::EmptyState.has_service_within(now(), Duration::minutes(90)));
+}
@@ -1,77 +1,296 @@
 use std::collections::HashMap;
-use std::error::Error;
+use std::sync::Arc;
+use std::time::Instant;
+use chrono::{DateTime, Utc};
 use log::debug;
-use futures::future::try_join_all;
-use crate::config::{Config, Stop};
-use super::{TransitIdentifier, TransitProvider, TransitState};
-
-pub async fn get_state_for_stops<'a, T: TransitProvider>(
-    provider: &'a T,
-    stops: &'a [Stop],
-) -> Result<Vec<TransitState>, Box<dyn Error + Send + Sync>> {
-    let futures: Vec<_> = stops
-        .iter()
-        .map(|stop| async move {
-            let updates = provider.get_updates(stop.clone()).await?;
-            Ok::<_, Box<dyn Error + Send + Sync>>(updates)
-        })
-        .collect();
+use serde::Serialize;
+use futures::future::join_all;
+use crate::config::{Config, ProviderConfig, Stop};
+use super::{TransitIdentifier, TransitState};
+use super::http::{redact_secrets, HttpClient};
+use super::registry::{create_provider, stop_directions, SharedTransitProvider};
+use super::scheduler::{ProviderScheduler, RateLimited, RefreshSchedule};
 
-    let result = try_join_all(futures).await?;
+// A transit state along with when it was last fetched and the most recent fetch error, if any
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TransitStateEntry {
+    pub state: TransitState,
+    pub fetched_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
 
-    Ok(result)
+// A stop due for a refresh, along with the provider to fetch it from
+pub struct DueFetch {
+    provider_key: String,
+    stop_idx: usize,
+    stop: Stop,
+    provider: SharedTransitProvider,
+    requested_at: Instant,
 }
 
 pub struct TransitStateManager {
     config: Config,
-    pub bart: super::bart::BartProvider,
-    pub muni: super::muni::MuniProvider,
-    pub baywheels: super::baywheels::BayWheelsProvider,
+    // Keyed by provider_key, the same key used in TransitIdentifier
+    pub providers: HashMap<String, SharedTransitProvider>,
+    schedulers: HashMap<String, ProviderScheduler>,
+    // Kept for building providers when the configuration is reloaded
+    http: HttpClient,
+    // Added to fetched times so replayed recordings line up with the current time
+    time_offset: chrono::Duration,
 
-    pub state: HashMap<TransitIdentifier, TransitState>,
+    pub state: HashMap<TransitIdentifier, TransitStateEntry>,
 }
 
 impl TransitStateManager {
-    pub fn new(config: Config, bart_api_key: String, muni_api_key: String) -> Self {
-        Self {
+    pub fn new(config: Config, http: HttpClient) -> anyhow::Result<Self> {
+        let providers = config.providers
+            .iter()
+            .map(|(key, provider_config)| Ok((key.clone(), create_provider(key, provider_config, &http)?)))
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+
+        let schedulers = providers
+            .iter()
+            .map(|(key, provider)| (key.clone(), Self::scheduler_for(provider, &config.providers[key])))
+            .collect();
+
+        Ok(Self {
             config,
-            bart: super::bart::BartProvider::new(bart_api_key),
-            muni: super::muni::MuniProvider::new(muni_api_key),
-            baywheels: super::baywheels::BayWheelsProvider::new(),
+            providers,
+            schedulers,
+            time_offset: http.time_offset(),
+            http,
             state: HashMap::new(),
+        })
+    }
+
+    fn scheduler_for(provider: &SharedTransitProvider, provider_config: &ProviderConfig) -> ProviderScheduler {
+        let schedule = RefreshSchedule::from_config(provider.default_schedule(), provider_config);
+        ProviderScheduler::new(schedule, provider_config.stops.len())
+    }
+
+    // Swaps in a new config.toml. Providers whose table is unchanged keep their
+    // refresh schedule and any rate-limit backoff; the rest are rebuilt and
+    // fetched right away. State for stops no longer configured is dropped. If any
+    // provider can't be built, the running configuration is left as it was.
+    pub fn reload(&mut self, config: Config) -> anyhow::Result<()> {
+        let mut rebuilt = config.providers
+            .iter()
+            .filter(|(key, provider_config)| self.config.providers.get(*key) != Some(*provider_config))
+            .map(|(key, provider_config)| Ok((key.clone(), create_provider(key, provider_config, &self.http)?)))
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+
+        let mut providers = HashMap::new();
+        let mut schedulers = HashMap::new();
+        for (key, provider_config) in &config.providers {
+            let (provider, scheduler) = match rebuilt.remove(key) {
+                Some(provider) => {
+                    let scheduler = Self::scheduler_for(&provider, provider_config);
+                    (provider, scheduler)
+                }
+                None => (
+                    self.providers.remove(key).expect("unchanged provider is running"),
+                    self.schedulers.remove(key).expect("scheduler exists for every provider"),
+                ),
+            };
+            providers.insert(key.clone(), provider);
+            schedulers.insert(key.clone(), scheduler);
         }
+
+        self.state.retain(|identifier, _| {
+            config.providers
+                .get(&identifier.provider_key)
+                .is_some_and(|provider_config| provider_config.stops_by_id.contains_key(&identifier.station_id))
+        });
+        self.config = config;
+        self.providers = providers;
+        self.schedulers = schedulers;
+        Ok(())
     }
 
     pub fn get_state_for_identifier(&self, identifier: &TransitIdentifier) -> TransitState {
+        self.get_entry_for_identifier(identifier).state
+    }
+
+    pub fn get_entry_for_identifier(&self, identifier: &TransitIdentifier) -> TransitStateEntry {
         self.state.get(identifier).cloned().unwrap_or_default()
     }
 
-    async fn fetch_all<'a>(&'a self) -> (
-        Result<Vec<TransitState>, Box<dyn Error + Send + Sync>>,
-        Result<Vec<TransitState>, Box<dyn Error + Send + Sync>>,
-        Result<Vec<TransitState>, Box<dyn Error + Send + Sync>>,
-    ) {
-        tokio::join!(
-            async {
-                debug!("Getting BART Updates...");
-                get_state_for_stops(&self.bart, &self.config.bart.stops).await
-            },
-            async {
-                debug!("Getting Muni Updates...");
-                get_state_for_stops(&self.muni, &self.config.muni.stops).await
-            },
-            async {
-                debug!("Getting Bay Wheels Updates...");
-                get_state_for_stops(&self.baywheels, &self.config.baywheels.stops).await
+    // Merges the entries for a combined subpage: predictions from every stop in
+    // time order, dated by the least recently fetched stop, with the first error
+    pub fn get_entry_for_identifiers(&self, identifiers: &[TransitIdentifier]) -> TransitStateEntry {
+        if let [identifier] = identifiers {
+            return self.get_entry_for_identifier(identifier);
+        }
+
+        let entries: Vec<TransitStateEntry> = identifiers.iter().map(|identifier| self.get_entry_for_identifier(identifier)).collect();
+        TransitStateEntry {
+            state: TransitState::merge(entries.iter().map(|entry| &entry.state)),
+            fetched_at: entries.iter().filter_map(|entry| entry.fetched_at).min(),
+            last_error: entries.iter().find_map(|entry| entry.last_error.clone()),
+        }
+    }
+
+    // How often each of a provider's stops is refreshed, once its request budget is shared out
+    pub fn refresh_interval(&self, provider_key: &str) -> Option<std::time::Duration> {
+        self.schedulers.get(provider_key).map(ProviderScheduler::stop_interval)
+    }
+
+    // When the next stop is due to be refreshed
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        self.schedulers.values().filter_map(|scheduler| scheduler.next_wakeup()).min()
+    }
+
+    // Every stop whose refresh is due. They are fetched with `fetch` while the
+    // manager is unlocked, so slow requests don't hold up the display, and the
+    // results handed back to `apply_fetched`.
+    pub fn due_fetches(&self) -> Vec<DueFetch> {
+        let now = Instant::now();
+        self.schedulers
+            .iter()
+            .flat_map(|(key, scheduler)| scheduler.due_stops(now).into_iter().map(move |idx| (key, idx)))
+            .map(|(key, stop_idx)| DueFetch {
+                provider_key: key.clone(),
+                stop_idx,
+                stop: self.config.providers[key].stops[stop_idx].clone(),
+                provider: self.providers[key].clone(),
+                requested_at: now,
+            })
+            .collect()
+    }
+
+    pub async fn fetch(due: Vec<DueFetch>) -> Vec<(DueFetch, anyhow::Result<TransitState>)> {
+        let results = join_all(due.iter().map(|fetch| async move {
+            debug!("Getting {} Updates for {} stop {}...", fetch.provider.name(), fetch.provider_key, fetch.stop.id);
+            fetch.provider.get_updates(fetch.stop.clone()).await
+        })).await;
+        due.into_iter().zip(results).collect()
+    }
+
+    // Records fetch results and returns the identifiers whose state changed.
+    // Results from a provider that a reload has replaced since are dropped.
+    pub fn apply_fetched(&mut self, fetched: Vec<(DueFetch, anyhow::Result<TransitState>)>) -> Vec<TransitIdentifier> {
+        let now = Instant::now();
+        let fetched_at = Utc::now();
+        let mut changed = Vec::new();
+        for (DueFetch { provider_key: key, stop_idx, provider, requested_at, .. }, result) in fetched {
+            if !self.providers.get(&key).is_some_and(|running| Arc::ptr_eq(running, &provider)) {
+                debug!("Dropping {} update fetched before a reload", key);
+                continue;
+            }
+            let provider_config = &self.config.providers[&key];
+            let stop = &provider_config.stops[stop_idx];
+            let configured = Self::configured_identifiers(&key, &provider_config.provider_type, stop);
+            let scheduler = self.schedulers.get_mut(&key).expect("scheduler exists for every provider");
+            match result {
+                Ok(mut update) => {
+                    scheduler.record_success(stop_idx, requested_at, now);
+                    update.shift_times(self.time_offset);
+                    changed.extend(Self::apply_update(&mut self.state, &key, stop, configured, update, fetched_at));
+                }
+                Err(e) => {
+                    let error = redact_secrets(&e.to_string());
+                    match e.downcast_ref::<RateLimited>() {
+                        Some(rate_limited) => {
+                            debug!("{} rate limited: {}", key, rate_limited);
+                            scheduler.record_rate_limited(stop_idx, now, rate_limited);
+                        }
+                        None => {
+                            debug!("{} update failed: {}", key, error);
+                            scheduler.record_failure(stop_idx, now);
+                        }
+                    }
+                    changed.extend(Self::apply_error(&mut self.state, &key, stop, configured, error));
+                }
+            }
+        }
+
+        changed
+    }
+
+    // Replaces every identifier belonging to the stop, so directions that no longer
+    // have departures are emptied rather than left showing old predictions. The
+    // stop as configured is included, so one with nothing running yet shows as
+    // having no service instead of loading forever. Every fetch time is updated,
+    // but only identifiers whose state changed or whose error cleared are returned.
+    fn apply_update(
+        state: &mut HashMap<TransitIdentifier, TransitStateEntry>,
+        provider_key: &str,
+        stop: &Stop,
+        configured: Vec<TransitIdentifier>,
+        update: TransitState,
+        fetched_at: DateTime<Utc>,
+    ) -> Vec<TransitIdentifier> {
+        let mut identifiers = Self::identifiers_for_stop(state, provider_key, stop);
+        if matches!(update, TransitState::Predictions(_)) {
+            identifiers.extend(configured);
+        }
+
+        let mut updates: HashMap<TransitIdentifier, TransitState> = update.to_state_updates().collect();
+        for identifier in identifiers {
+            updates.entry(identifier).or_insert_with(|| TransitState::Predictions(Vec::new()));
+        }
+
+        let mut changed = Vec::new();
+        for (identifier, new_state) in updates {
+            let entry = state.entry(identifier.clone()).or_default();
+            if entry.state != new_state || entry.last_error.is_some() {
+                changed.push(identifier);
             }
-        )
+            *entry = TransitStateEntry {
+                state: new_state,
+                fetched_at: Some(fetched_at),
+                last_error: None,
+            };
+        }
+        changed
+    }
+
+    // Keeps the last good state but records the error on every identifier for the stop
+    fn apply_error(
+        state: &mut HashMap<TransitIdentifier, TransitStateEntry>,
+        provider_key: &str,
+        stop: &Stop,
+        configured: Vec<TransitIdentifier>,
+        error: String,
+    ) -> Vec<TransitIdentifier> {
+        let mut identifiers = Self::identifiers_for_stop(state, provider_key, stop);
+        if identifiers.is_empty() {
+            // Never fetched successfully, so record against the stop as configured
+            identifiers = configured;
+        }
+
+        let mut changed = Vec::new();
+        for identifier in identifiers {
+            let entry = state.entry(identifier.clone()).or_default();
+            if entry.last_error.as_ref() != Some(&error) {
+                entry.last_error = Some(error.clone());
+                changed.push(identifier);
+            }
+        }
+        changed
+    }
+
+    // The identifiers a stop's departures are filed under: one per direction the
+    // provider reports for a stop configured as Both
+    fn configured_identifiers(provider_key: &str, provider_type: &str, stop: &Stop) -> Vec<TransitIdentifier> {
+        stop_directions(provider_type, stop.direction)
+            .into_iter()
+            .map(|direction| TransitIdentifier {
+                provider_key: provider_key.to_string(),
+                station_id: stop.id.clone(),
+                direction,
+            })
+            .collect()
     }
 
-    pub async fn update_state(&mut self) -> () {
-        let (bart, muni, baywheels) = self.fetch_all().await;
-        // Update self.state with new updates
-        self.state.extend(bart.unwrap_or_default().iter().flat_map(|state| state.to_state_updates()));
-        self.state.extend(muni.unwrap_or_default().iter().flat_map(|state| state.to_state_updates()));
-        self.state.extend(baywheels.unwrap_or_default().iter().flat_map(|state| state.to_state_updates()));
+    fn identifiers_for_stop(
+        state: &HashMap<TransitIdentifier, TransitStateEntry>,
+        provider_key: &str,
+        stop: &Stop,
+    ) -> Vec<TransitIdentifier> {
+        state.keys()
+            .filter(|identifier| identifier.provider_key == provider_key && identifier.station_id == stop.id)
+            .cloned()
+            .collect()
     }
 }
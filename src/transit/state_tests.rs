@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+
+use crate::config::{Config, Stop};
+use crate::display::Color;
+use super::http::HttpClient;
+use super::state::{TransitStateEntry, TransitStateManager};
+use super::{Direction, LabelFormat, Prediction, TransitIdentifier, TransitProvider, TransitState};
+
+const CONFIG_TOML: &str = r#"
+[providers.bart]
+type = "bart"
+api_key = "key"
+stops = [
+    { id = "MONT", name = "Montgomery St.", direction = "Both" },
+    { id = "EMBR", name = "Embarcadero", direction = "Both" },
+]
+"#;
+
+fn identifier(station_id: &str) -> TransitIdentifier {
+    TransitIdentifier {
+        provider_key: "bart".to_string(),
+        station_id: station_id.to_string(),
+        direction: Direction::North,
+    }
+}
+
+fn fetched() -> TransitStateEntry {
+    TransitStateEntry {
+        state: TransitState::Predictions(Vec::new()),
+        fetched_at: Some(Utc::now()),
+        last_error: None,
+    }
+}
+
+// Answers every stop with the same northbound train
+struct StubProvider;
+
+#[async_trait]
+impl TransitProvider for StubProvider {
+    async fn get_updates(&self, stop: Stop) -> anyhow::Result<TransitState> {
+        Ok(TransitState::Predictions(vec![Prediction {
+            provider_key: "bart".to_string(),
+            route_name: "YELLOW".to_string(),
+            destination: "ANTC".to_string(),
+            minutes_until_arrival: 5,
+            predicted_time: Utc.with_ymd_and_hms(2024, 3, 1, 8, 5, 0).unwrap(),
+            station_id: stop.id.clone(),
+            stop_id: stop.id,
+            direction: Direction::North,
+            color: Color::default(),
+            label_format: LabelFormat::default(),
+            scheduled: false,
+        }]))
+    }
+
+    fn name(&self) -> &'static str {
+        "Stub"
+    }
+}
+
+// Fails the way an unreachable API does, quoting the request URL
+struct UnreachableProvider;
+
+#[async_trait]
+impl TransitProvider for UnreachableProvider {
+    async fn get_updates(&self, stop: Stop) -> anyhow::Result<TransitState> {
+        Err(anyhow::anyhow!("error sending request for url (https://api.bart.gov/api/etd.aspx?orig={}&key=secret)", stop.id))
+    }
+
+    fn name(&self) -> &'static str {
+        "Unreachable"
+    }
+}
+
+fn manager() -> TransitStateManager {
+    let mut manager = TransitStateManager::new(Config::from_toml(CONFIG_TOML).unwrap(), HttpClient::live()).unwrap();
+    manager.state.insert(identifier("MONT"), fetched());
+    manager.state.insert(identifier("EMBR"), fetched());
+    manager
+}
+
+#[test]
+fn reload_drops_state_for_removed_stops() {
+    let mut manager = manager();
+    let without_embarcadero = CONFIG_TOML.replace(r#"    { id = "EMBR", name = "Embarcadero", direction = "Both" },"#, "");
+
+    manager.reload(Config::from_toml(&without_embarcadero).unwrap()).unwrap();
+    assert!(manager.state.contains_key(&identifier("MONT")));
+    assert!(!manager.state.contains_key(&identifier("EMBR")));
+    assert!(manager.providers.contains_key("bart"));
+}
+
+#[test]
+fn reload_keeps_running_config_when_a_provider_fails() {
+    let mut manager = manager();
+    let broken = format!("{}\n[providers.other]\ntype = \"not_a_provider\"\n", CONFIG_TOML.replace("EMBR", "CIVC"));
+
+    assert!(manager.reload(Config::from_toml(&broken).unwrap()).is_err());
+    assert!(manager.state.contains_key(&identifier("EMBR")));
+    assert_eq!(manager.providers.len(), 1);
+}
+
+#[tokio::test]
+async fn due_stops_are_fetched_and_applied() {
+    let mut manager = TransitStateManager::new(Config::from_toml(CONFIG_TOML).unwrap(), HttpClient::live()).unwrap();
+    manager.providers.insert("bart".to_string(), Arc::new(StubProvider));
+
+    let due = manager.due_fetches();
+    assert_eq!(due.len(), 2);
+    let changed = manager.apply_fetched(TransitStateManager::fetch(due).await);
+
+    assert_eq!(changed.len(), 4);
+    let TransitState::Predictions(predictions) = manager.get_state_for_identifier(&identifier("MONT")) else {
+        panic!("expected predictions");
+    };
+    assert_eq!(predictions.len(), 1);
+    // Nothing else is due until the next interval
+    assert!(manager.due_fetches().is_empty());
+}
+
+#[tokio::test]
+async fn results_from_a_replaced_provider_are_dropped() {
+    let mut manager = TransitStateManager::new(Config::from_toml(CONFIG_TOML).unwrap(), HttpClient::live()).unwrap();
+    manager.providers.insert("bart".to_string(), Arc::new(StubProvider));
+    let fetched = TransitStateManager::fetch(manager.due_fetches()).await;
+
+    // The config changes while the requests are out
+    manager.reload(Config::from_toml(&CONFIG_TOML.replace("Embarcadero", "Embarcadero Station")).unwrap()).unwrap();
+    assert!(manager.apply_fetched(fetched).is_empty());
+    assert!(manager.state.is_empty());
+}
+
+#[tokio::test]
+async fn unchanged_refreshes_only_update_the_fetch_time() {
+    let config = Config::from_toml(&CONFIG_TOML.replace("api_key = \"key\"", "api_key = \"key\"\npoll_interval_secs = 1")).unwrap();
+    let mut manager = TransitStateManager::new(config, HttpClient::live()).unwrap();
+    manager.providers.insert("bart".to_string(), Arc::new(StubProvider));
+    manager.apply_fetched(TransitStateManager::fetch(manager.due_fetches()).await);
+    let first_fetch = manager.get_entry_for_identifier(&identifier("MONT")).fetched_at;
+
+    // The same departures again aren't reported, but the display reads the newer fetch time
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    let changed = manager.apply_fetched(TransitStateManager::fetch(manager.due_fetches()).await);
+    assert!(changed.is_empty(), "{:?}", changed);
+    assert!(manager.get_entry_for_identifier(&identifier("MONT")).fetched_at > first_fetch);
+}
+
+#[tokio::test]
+async fn errors_are_stored_without_keys() {
+    let mut manager = TransitStateManager::new(Config::from_toml(CONFIG_TOML).unwrap(), HttpClient::live()).unwrap();
+    manager.providers.insert("bart".to_string(), Arc::new(UnreachableProvider));
+    manager.apply_fetched(TransitStateManager::fetch(manager.due_fetches()).await);
+
+    assert_eq!(
+        manager.get_entry_for_identifier(&identifier("MONT")).last_error.as_deref(),
+        Some("error sending request for url (https://api.bart.gov/api/etd.aspx?orig=MONT&key=REDACTED)"),
+    );
+}
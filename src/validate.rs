@@ -0,0 +1,389 @@
+// Loads config.toml and display.toml and checks them against each other, so a
+// typo in a provider key or station id is reported with its location instead of
+// showing "Loading..." forever. Every problem found is reported, not just the first.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+use log::info;
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::config::{Config, DisplayConfig};
+use crate::config_files::{self, ConfigPaths, LineOrigin, CONFIG_OVERRIDE_PREFIX, DISPLAY_OVERRIDE_PREFIX};
+use crate::display::PageDisplayHandler;
+use crate::display::schedule::TimeOfDay;
+use crate::transit::Direction;
+use crate::transit::gtfs_rt;
+use crate::transit::registry::{configurable_directions, stop_directions, PROVIDER_TYPES};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}: {}", self.file, self.message),
+            line => write!(f, "{}:{}:{}: {}", self.file, line, self.column, self.message),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Issues(pub Vec<Issue>);
+
+impl fmt::Display for Issues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for issue in &self.0 {
+            writeln!(f, "{}", issue)?;
+        }
+        write!(f, "{} problem{} found", self.0.len(), if self.0.len() == 1 { "" } else { "s" })
+    }
+}
+
+impl std::error::Error for Issues {}
+
+// A configuration file's path and contents, for turning spans into line numbers.
+// When environment overrides rewrote the file, `lines` maps each line back to the
+// file as written or to the variable that wrote it.
+pub struct Source<'a> {
+    pub file: &'a str,
+    pub text: &'a str,
+    pub lines: &'a [LineOrigin],
+}
+
+impl Source<'_> {
+    fn issue(&self, span: Range<usize>, message: String) -> Issue {
+        let before = &self.text[..span.start.min(self.text.len())];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line = before.matches('\n').count() + 1;
+        let column = before[line_start..].chars().count() + 1;
+        match self.lines.get(line - 1) {
+            Some(LineOrigin::Env(name)) => Issue { file: name.clone(), line: 0, column: 0, message },
+            Some(LineOrigin::File(line)) => Issue { file: self.file.to_string(), line: *line, column, message },
+            None => Issue { file: self.file.to_string(), line, column, message },
+        }
+    }
+
+    fn parse_error(&self, error: toml::de::Error) -> Issue {
+        self.issue(error.span().unwrap_or(0..0), error.message().trim().to_string())
+    }
+}
+
+pub fn load(paths: &ConfigPaths) -> Result<(Config, DisplayConfig), Issues> {
+    let read = |path: &Path, prefix: &str| config_files::read_with_overrides(path, prefix).map_err(|message| Issues(vec![Issue {
+        file: path.display().to_string(),
+        line: 0,
+        column: 0,
+        message,
+    }]));
+    let config = read(&paths.config, CONFIG_OVERRIDE_PREFIX)?;
+    let display = read(&paths.display, DISPLAY_OVERRIDE_PREFIX)?;
+    for name in config.applied.iter().chain(&display.applied) {
+        info!("Applying {} from the environment", name);
+    }
+
+    let (config_file, display_file) = (paths.config.display().to_string(), paths.display.display().to_string());
+    check(
+        &Source { file: &config_file, text: &config.text, lines: &config.lines },
+        &Source { file: &display_file, text: &display.text, lines: &display.lines },
+    )
+}
+
+// Parses both files and runs every check, returning the configuration only if
+// there are no problems
+pub fn check(config_source: &Source, display_source: &Source) -> Result<(Config, DisplayConfig), Issues> {
+    let config = Config::from_toml(config_source.text).map_err(|e| config_source.parse_error(e));
+    let display = DisplayConfig::from_toml(display_source.text).map_err(|e| display_source.parse_error(e));
+    let (mut config, display) = match (config, display) {
+        (Ok(config), Ok(display)) => (config, display),
+        (config, display) => return Err(Issues(config.err().into_iter().chain(display.err()).collect())),
+    };
+
+    // The typed configuration parsed, so these will too
+    let config_spans: ConfigSpans = toml::from_str(config_source.text).map_err(|e| Issues(vec![config_source.parse_error(e)]))?;
+    let display_spans: DisplaySpans = toml::from_str(display_source.text).map_err(|e| Issues(vec![display_source.parse_error(e)]))?;
+
+    let mut issues = Vec::new();
+    check_providers(config_source, &config_spans, &mut issues);
+    load_secrets(config_source, &config_spans, &mut config, &mut issues);
+    resolve_feed_paths(config_source, &mut config);
+    check_pages(display_source, &display_spans, &config, &mut issues);
+    check_schedules(display_source, &display_spans, &mut issues);
+
+    if issues.is_empty() {
+        Ok((config, display))
+    } else {
+        Err(Issues(issues))
+    }
+}
+
+// Just enough of each file to locate the values being checked
+
+#[derive(Deserialize)]
+struct ConfigSpans {
+    providers: HashMap<String, Spanned<ProviderSpans>>,
+}
+
+#[derive(Deserialize)]
+struct ProviderSpans {
+    #[serde(rename = "type")]
+    provider_type: Spanned<String>,
+    #[serde(default)]
+    api_key: Option<Spanned<String>>,
+    #[serde(default)]
+    api_key_file: Option<Spanned<String>>,
+    #[serde(default)]
+    stops: Vec<StopSpans>,
+}
+
+#[derive(Deserialize)]
+struct StopSpans {
+    id: String,
+    direction: Spanned<Direction>,
+}
+
+#[derive(Deserialize)]
+struct DisplaySpans {
+    #[serde(default)]
+    panel: Option<PanelSpans>,
+    #[serde(default)]
+    location: Option<toml::Value>,
+    #[serde(default)]
+    brightness_schedule: Vec<Spanned<BrightnessSpans>>,
+    pages: Spanned<Vec<Spanned<PageSpans>>>,
+}
+
+#[derive(Deserialize)]
+struct PanelSpans {
+    #[serde(default)]
+    brightness: Option<Spanned<u8>>,
+}
+
+#[derive(Deserialize)]
+struct BrightnessSpans {
+    start: Spanned<String>,
+    end: Spanned<String>,
+    brightness: Spanned<u8>,
+}
+
+#[derive(Deserialize)]
+struct WindowSpans {
+    start: Spanned<String>,
+    end: Spanned<String>,
+}
+
+#[derive(Deserialize)]
+struct PageSpans {
+    subpages: Vec<SubpageSpans>,
+    #[serde(default)]
+    schedule: Vec<WindowSpans>,
+}
+
+#[derive(Deserialize)]
+struct SubpageSpans {
+    #[serde(default)]
+    transit_identifier: Option<IdentifierSpans>,
+    #[serde(default)]
+    transit_identifiers: Vec<IdentifierSpans>,
+    page_display_handler_key: Spanned<String>,
+}
+
+#[derive(Deserialize)]
+struct IdentifierSpans {
+    provider_key: Spanned<String>,
+    station_id: Spanned<String>,
+    direction: Spanned<Direction>,
+}
+
+fn quoted_list(values: &[&str]) -> String {
+    values.iter().map(|value| format!("\"{}\"", value)).collect::<Vec<_>>().join(", ")
+}
+
+fn quoted_directions(directions: &[Direction]) -> String {
+    directions.iter().map(|direction| format!("\"{}\"", direction)).collect::<Vec<_>>().join(", ")
+}
+
+// Providers in the order they appear in config.toml
+fn providers_in_order(spans: &ConfigSpans) -> Vec<(&String, &ProviderSpans)> {
+    let mut providers: Vec<(&String, &Spanned<ProviderSpans>)> = spans.providers.iter().collect();
+    providers.sort_by_key(|(_, provider)| provider.span().start);
+    providers.into_iter().map(|(provider_key, provider)| (provider_key, provider.get_ref())).collect()
+}
+
+fn check_providers(source: &Source, spans: &ConfigSpans, issues: &mut Vec<Issue>) {
+    for (provider_key, provider) in providers_in_order(spans) {
+        let provider_type = &provider.provider_type;
+        if !PROVIDER_TYPES.contains(&provider_type.get_ref().as_str()) {
+            issues.push(source.issue(provider_type.span(), format!(
+                "[providers.{}] has unknown type \"{}\", expected one of {}",
+                provider_key, provider_type.get_ref(), quoted_list(&PROVIDER_TYPES)
+            )));
+            continue;
+        }
+
+        // A direction the provider never reports would leave the stop empty
+        let Some(choices) = configurable_directions(provider_type.get_ref()) else {
+            continue;
+        };
+        for stop in &provider.stops {
+            if !choices.contains(stop.direction.get_ref()) {
+                issues.push(source.issue(stop.direction.span(), format!(
+                    "stop \"{}\" of [providers.{}] has direction \"{}\", but {} stops take {}",
+                    stop.id, provider_key, stop.direction.get_ref(), provider_type.get_ref(), quoted_directions(choices)
+                )));
+            }
+        }
+    }
+}
+
+// Reads each provider's `api_key_file` into its `api_key` setting, where the
+// provider picks it up. Relative paths are relative to config.toml.
+fn load_secrets(source: &Source, spans: &ConfigSpans, config: &mut Config, issues: &mut Vec<Issue>) {
+    let config_dir = Path::new(source.file).parent().unwrap_or(Path::new("."));
+    for (provider_key, provider) in providers_in_order(spans) {
+        let Some(path) = &provider.api_key_file else {
+            continue;
+        };
+        if provider.api_key.is_some() {
+            issues.push(source.issue(path.span(), format!(
+                "[providers.{}] sets both api_key and api_key_file", provider_key
+            )));
+            continue;
+        }
+
+        match config_files::read_secret(Path::new(path.get_ref()), config_dir) {
+            Ok(secret) => {
+                let settings = &mut config.providers.get_mut(provider_key).expect("provider was parsed").settings;
+                settings.insert("api_key".to_string(), toml::Value::String(secret));
+            }
+            Err(e) => issues.push(source.issue(path.span(), format!("can't read api_key_file {}", e))),
+        }
+    }
+}
+
+// Local gtfs_rt feeds are relative to config.toml too, rather than to the working
+// directory (/ under systemd)
+fn resolve_feed_paths(source: &Source, config: &mut Config) {
+    let config_dir = Path::new(source.file).parent().unwrap_or(Path::new("."));
+    for provider in config.providers.values_mut().filter(|provider| provider.provider_type == "gtfs_rt") {
+        for name in ["trip_updates_url", "static_url"] {
+            let Some(path) = provider.setting_str(name).and_then(gtfs_rt::local_path) else {
+                continue;
+            };
+            let path = config_dir.join(path).display().to_string();
+            provider.settings.insert(name.to_string(), toml::Value::String(path));
+        }
+    }
+}
+
+fn check_pages(source: &Source, spans: &DisplaySpans, config: &Config, issues: &mut Vec<Issue>) {
+    if spans.pages.get_ref().is_empty() {
+        issues.push(source.issue(spans.pages.span(), "display.toml needs at least one [[pages]] entry".to_string()));
+    }
+
+    for page in spans.pages.get_ref() {
+        if page.get_ref().subpages.is_empty() {
+            issues.push(source.issue(page.span(), "page has no subpages".to_string()));
+        }
+
+        for subpage in &page.get_ref().subpages {
+            let handler_key = &subpage.page_display_handler_key;
+            let handler = PageDisplayHandler::parse(handler_key.get_ref());
+            if handler.is_none() {
+                issues.push(source.issue(handler_key.span(), format!(
+                    "unknown page_display_handler_key \"{}\", expected one of {}",
+                    handler_key.get_ref(), quoted_list(&PageDisplayHandler::KEYS)
+                )));
+            }
+
+            for identifier in subpage.transit_identifier.iter().chain(&subpage.transit_identifiers) {
+                check_identifier(source, identifier, handler_key, handler.as_ref(), config, issues);
+            }
+        }
+    }
+}
+
+fn check_identifier(
+    source: &Source,
+    identifier: &IdentifierSpans,
+    handler_key: &Spanned<String>,
+    handler: Option<&PageDisplayHandler>,
+    config: &Config,
+    issues: &mut Vec<Issue>,
+) {
+    let provider_key = identifier.provider_key.get_ref();
+    let Some(provider) = config.providers.get(provider_key) else {
+        let mut declared: Vec<&str> = config.providers.keys().map(String::as_str).collect();
+        declared.sort();
+        issues.push(source.issue(identifier.provider_key.span(), format!(
+            "unknown provider \"{}\"; config.toml declares {}", provider_key, quoted_list(&declared)
+        )));
+        return;
+    };
+
+    let is_bikes = provider.provider_type == "baywheels";
+    match handler {
+        Some(PageDisplayHandler::BikeInventoryDisplay) if !is_bikes => issues.push(source.issue(handler_key.span(), format!(
+            "\"bike_inventory\" needs a baywheels provider, but \"{}\" is {}", provider_key, provider.provider_type
+        ))),
+        Some(PageDisplayHandler::PredictionsDisplay) if is_bikes => issues.push(source.issue(handler_key.span(), format!(
+            "\"{}\" is a baywheels provider, so it needs \"bike_inventory\" rather than \"predictions\"", provider_key
+        ))),
+        _ => (),
+    }
+
+    let station_id = identifier.station_id.get_ref();
+    let Some(stop) = provider.stops_by_id.get(station_id) else {
+        issues.push(source.issue(identifier.station_id.span(), format!(
+            "station_id \"{}\" is not among the stops of [providers.{}] in config.toml", station_id, provider_key
+        )));
+        return;
+    };
+
+    // An unknown type or a stop direction the type doesn't take is reported in config.toml
+    let stop_direction_is_valid = configurable_directions(&provider.provider_type).is_none_or(|choices| choices.contains(&stop.direction));
+    if !PROVIDER_TYPES.contains(&provider.provider_type.as_str()) || !stop_direction_is_valid {
+        return;
+    }
+    let expected = stop_directions(&provider.provider_type, stop.direction);
+    let direction = identifier.direction.get_ref();
+    if !expected.contains(direction) {
+        issues.push(source.issue(identifier.direction.span(), format!(
+            "direction \"{}\" never matches {} at \"{}\", expected {}",
+            direction, provider_key, station_id, quoted_directions(&expected)
+        )));
+    }
+}
+
+fn check_schedules(source: &Source, spans: &DisplaySpans, issues: &mut Vec<Issue>) {
+    let page_windows = spans.pages.get_ref().iter().flat_map(|page| &page.get_ref().schedule).map(|window| (&window.start, &window.end));
+    let brightness_windows = spans.brightness_schedule.iter().map(|entry| (&entry.get_ref().start, &entry.get_ref().end));
+
+    if spans.location.is_none() {
+        for time in page_windows.chain(brightness_windows).flat_map(|(start, end)| [start, end]) {
+            if TimeOfDay::from_str(time.get_ref()).is_some_and(|time| time.is_solar()) {
+                issues.push(source.issue(time.span(), format!(
+                    "\"{}\" needs a [location] with latitude and longitude", time.get_ref()
+                )));
+            }
+        }
+    }
+
+    let panel_brightness = spans.panel.iter().flat_map(|panel| &panel.brightness);
+    let scheduled_brightness = spans.brightness_schedule.iter().map(|entry| &entry.get_ref().brightness);
+    for brightness in panel_brightness.chain(scheduled_brightness) {
+        if *brightness.get_ref() > 100 {
+            issues.push(source.issue(brightness.span(), format!(
+                "brightness {} is out of range 0-100", brightness.get_ref()
+            )));
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::path::PathBuf;
+
+use super::config_files::{apply_overrides, CONFIG_OVERRIDE_PREFIX};
+use super::validate::{check, Issue, Source};
+
+const CONFIG_TOML: &str = r#"
+[providers.bart]
+type = "bart"
+stops = [{ id = "MONT", name = "Montgomery St.", direction = "Both" }]
+
+[providers.muni]
+type = "muni"
+stops = [{ id = "14293", name = "Geary Blvd & Divisadero St", direction = "West" }]
+
+[providers.baywheels]
+type = "baywheels"
+stops = [{ id = "3144f47a", name = "Divisadero St at Geary Blvd", direction = "Both" }]
+"#;
+
+const TIMEOUTS: &str = r#"
+message_timeout = { secs = 5, nanos = 0 }
+error_timeout = { secs = 30, nanos = 0 }
+"#;
+
+// Problems as "line:column: message" in display.toml, or an empty list if the files are valid
+fn problems(config_toml: &str, display_toml: &str) -> Vec<String> {
+    let display_toml = format!("{}{}", TIMEOUTS, display_toml);
+    match check(
+        &Source { file: "config.toml", text: config_toml, lines: &[] },
+        &Source { file: "display.toml", text: &display_toml, lines: &[] },
+    ) {
+        Ok(_) => Vec::new(),
+        Err(issues) => issues.0.iter()
+            .map(|Issue { file, line, column, message }| format!("{}:{}:{}: {}", file, line, column, message))
+            .collect(),
+    }
+}
+
+#[test]
+fn shipped_configuration_is_valid() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let config_toml = std::fs::read_to_string(root.join("config.toml")).unwrap();
+    let display_toml = std::fs::read_to_string(root.join("display.toml")).unwrap();
+
+    let result = check(
+        &Source { file: "config.toml", text: &config_toml, lines: &[] },
+        &Source { file: "display.toml", text: &display_toml, lines: &[] },
+    );
+    assert!(result.is_ok(), "{}", result.err().unwrap());
+}
+
+#[test]
+fn matching_subpages_are_valid() {
+    let display_toml = r#"
+[[pages]]
+subpages = [
+    { transit_identifier = { provider_key = "bart", station_id = "MONT", direction = "North" }, page_display_handler_key = "predictions" },
+    { transit_identifier = { provider_key = "muni", station_id = "14293", direction = "West" }, page_display_handler_key = "predictions" },
+    { transit_identifier = { provider_key = "baywheels", station_id = "3144f47a", direction = "None" }, page_display_handler_key = "bike_inventory" },
+]
+"#;
+    assert_eq!(problems(CONFIG_TOML, display_toml), Vec::<String>::new());
+}
+
+#[test]
+fn every_mismatch_is_reported_where_it_is() {
+    let display_toml = r#"
+[[pages]]
+subpages = [
+    { transit_identifier = { provider_key = "munni", station_id = "14293", direction = "West" }, page_display_handler_key = "predictions" },
+    { transit_identifier = { provider_key = "muni", station_id = "14239", direction = "West" }, page_display_handler_key = "predictions" },
+    { transit_identifier = { provider_key = "muni", station_id = "14293", direction = "East" }, page_display_handler_key = "predictions" },
+    { transit_identifier = { provider_key = "bart", station_id = "MONT", direction = "Both" }, page_display_handler_key = "predictions" },
+    { transit_identifier = { provider_key = "bart", station_id = "MONT", direction = "North" }, page_display_handler_key = "bike_inventory" },
+    { transit_identifier = { provider_key = "baywheels", station_id = "3144f47a", direction = "None" }, page_display_handler_key = "bikes" },
+]
+"#;
+    assert_eq!(problems(CONFIG_TOML, display_toml), vec![
+        r#"display.toml:7:45: unknown provider "munni"; config.toml declares "bart", "baywheels", "muni""#,
+        r#"display.toml:8:66: station_id "14239" is not among the stops of [providers.muni] in config.toml"#,
+        r#"display.toml:9:87: direction "East" never matches muni at "14293", expected "West""#,
+        r#"display.toml:10:86: direction "Both" never matches bart at "MONT", expected "North", "South""#,
+        r#"display.toml:11:124: "bike_inventory" needs a baywheels provider, but "bart" is bart"#,
+        r#"display.toml:12:132: unknown page_display_handler_key "bikes", expected one of "predictions", "bike_inventory""#,
+    ]);
+}
+
+#[test]
+fn pages_must_have_subpages() {
+    assert_eq!(problems(CONFIG_TOML, "pages = []\n"), vec![
+        "display.toml:4:9: display.toml needs at least one [[pages]] entry",
+    ]);
+    assert_eq!(problems(CONFIG_TOML, "[[pages]]\nsubpages = []\n"), vec![
+        "display.toml:4:1: page has no subpages",
+    ]);
+}
+
+#[test]
+fn unknown_provider_type_is_reported_in_config_toml() {
+    let config_toml = CONFIG_TOML.replace(r#"type = "baywheels""#, r#"type = "bay_wheels""#);
+    let display_toml = "[[pages]]\nsubpages = [{ transit_identifier = { provider_key = \"bart\", station_id = \"MONT\", direction = \"South\" }, page_display_handler_key = \"predictions\" }]\n";
+    assert_eq!(problems(&config_toml, display_toml), vec![
+        r#"config.toml:11:8: [providers.baywheels] has unknown type "bay_wheels", expected one of "bart", "muni", "baywheels", "gtfs_rt""#,
+    ]);
+}
+
+#[test]
+fn stop_directions_the_provider_never_reports_are_reported_in_config_toml() {
+    let config_toml = CONFIG_TOML
+        .replace(r#""Montgomery St.", direction = "Both""#, r#""Montgomery St.", direction = "Inbound""#)
+        .replace(r#"direction = "West""#, r#"direction = "None""#);
+    let display_toml = "[[pages]]\nsubpages = [{ transit_identifier = { provider_key = \"bart\", station_id = \"MONT\", direction = \"Inbound\" }, page_display_handler_key = \"predictions\" }]\n";
+    assert_eq!(problems(&config_toml, display_toml), vec![
+        r#"config.toml:4:62: stop "MONT" of [providers.bart] has direction "Inbound", but bart stops take "North", "South", "Both""#,
+        r#"config.toml:8:75: stop "14293" of [providers.muni] has direction "None", but muni stops take "North", "South", "East", "West", "Inbound", "Outbound", "Both""#,
+    ]);
+}
+
+#[test]
+fn overridden_files_are_reported_against_the_file_or_the_variable() {
+    let config_toml = CONFIG_TOML.replace(r#"type = "baywheels""#, r#"type = "bay_wheels""#);
+    let overridden = apply_overrides(&config_toml, CONFIG_OVERRIDE_PREFIX, vec![
+        ("TRANSIT_SIGN_CONFIG__PROVIDERS__BART__API_KEY".to_string(), "12345".to_string()),
+        ("TRANSIT_SIGN_CONFIG__PROVIDERS__MUNI__TYPE".to_string(), "munni".to_string()),
+    ]).unwrap();
+    let display_toml = format!("{}[[pages]]\nsubpages = [{{ transit_identifier = {{ provider_key = \"bart\", station_id = \"MONT\", direction = \"South\" }}, page_display_handler_key = \"predictions\" }}]\n", TIMEOUTS);
+
+    let issues = check(
+        &Source { file: "config.toml", text: &overridden.text, lines: &overridden.lines },
+        &Source { file: "display.toml", text: &display_toml, lines: &[] },
+    ).unwrap_err();
+    let found: Vec<String> = issues.0.iter().map(Issue::to_string).collect();
+    assert_eq!(found, vec![
+        r#"TRANSIT_SIGN_CONFIG__PROVIDERS__MUNI__TYPE: [providers.muni] has unknown type "munni", expected one of "bart", "muni", "baywheels", "gtfs_rt""#,
+        r#"config.toml:11:8: [providers.baywheels] has unknown type "bay_wheels", expected one of "bart", "muni", "baywheels", "gtfs_rt""#,
+    ]);
+}
+
+#[test]
+fn parse_errors_in_both_files_are_reported() {
+    let config_toml = CONFIG_TOML.replace("stops = [{ id = \"MONT\"", "stops = [{ idd = \"MONT\"");
+    let found = problems(&config_toml, "[[pages]]\nsubpages = [{ page_display_handler_key = \"predictions\" }]\n");
+
+    assert_eq!(found.len(), 2, "{:?}", found);
+    assert!(found[0].starts_with("config.toml:4:"), "{}", found[0]);
+    assert!(found[0].contains("missing field `id`"), "{}", found[0]);
+    assert!(found[1].starts_with("display.toml:5:"), "{}", found[1]);
+    assert!(found[1].contains("transit_identifier"), "{}", found[1]);
+}
+
+#[test]
+fn solar_schedules_need_a_location() {
+    let display_toml = r#"
+[[brightness_schedule]]
+start = "sunset"
+end = "23:00"
+brightness = 120
+
+[[pages]]
+subpages = [{ transit_identifier = { provider_key = "bart", station_id = "MONT", direction = "South" }, page_display_handler_key = "predictions" }]
+"#;
+    assert_eq!(problems(CONFIG_TOML, display_toml), vec![
+        r#"display.toml:6:9: "sunset" needs a [location] with latitude and longitude"#,
+        "display.toml:8:14: brightness 120 is out of range 0-100",
+    ]);
+}
+
+#[test]
+fn local_gtfs_feeds_are_relative_to_config_toml() {
+    let config_toml = format!("{}\n[providers.actransit]\ntype = \"gtfs_rt\"\ntrip_updates_url = \"https://example.com/tripupdates\"\nstatic_url = \"feeds/gtfs.zip\"\n", CONFIG_TOML);
+    let display_toml = format!("{}[[pages]]\nsubpages = [{{ transit_identifier = {{ provider_key = \"bart\", station_id = \"MONT\", direction = \"South\" }}, page_display_handler_key = \"predictions\" }}]\n", TIMEOUTS);
+
+    let (config, _) = check(
+        &Source { file: "/etc/transit-sign/config.toml", text: &config_toml, lines: &[] },
+        &Source { file: "display.toml", text: &display_toml, lines: &[] },
+    ).unwrap();
+    let actransit = &config.providers["actransit"];
+    assert_eq!(actransit.setting_str("static_url"), Some("/etc/transit-sign/feeds/gtfs.zip"));
+    assert_eq!(actransit.setting_str("trip_updates_url"), Some("https://example.com/tripupdates"));
+}
+
+#[test]
+fn api_key_file_is_read_into_api_key() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/validate_secrets");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("bart.key"), "from-file\n").unwrap();
+    let config_file = dir.join("config.toml").display().to_string();
+    let display_toml = format!("{}[[pages]]\nsubpages = [{{ transit_identifier = {{ provider_key = \"bart\", station_id = \"MONT\", direction = \"South\" }}, page_display_handler_key = \"predictions\" }}]\n", TIMEOUTS);
+
+    let config_toml = CONFIG_TOML.replace("type = \"bart\"", "type = \"bart\"\napi_key_file = \"bart.key\"");
+    let (config, _) = check(
+        &Source { file: &config_file, text: &config_toml, lines: &[] },
+        &Source { file: "display.toml", text: &display_toml, lines: &[] },
+    ).unwrap();
+    assert_eq!(config.providers["bart"].setting_str("api_key"), Some("from-file"));
+
+    let both = config_toml.replace("api_key_file", "api_key = \"inline\"\napi_key_file");
+    let issues = check(
+        &Source { file: &config_file, text: &both, lines: &[] },
+        &Source { file: "display.toml", text: &display_toml, lines: &[] },
+    ).unwrap_err();
+    assert!(issues.0[0].message.contains("both api_key and api_key_file"), "{}", issues);
+}